@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+
+/// Read (name, address) pairs from a vCard or abook file. The format is
+/// picked by file extension: '.vcf' and '.vcard' are vCard, anything else
+/// is treated as abook.
+pub fn load(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+
+    let is_vcard = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("vcf") || ext.eq_ignore_ascii_case("vcard"),
+        None => false
+    };
+
+    if is_vcard {
+        Ok(parse_vcard(&contents))
+    } else {
+        Ok(parse_abook(&contents))
+    }
+}
+
+
+pub fn parse_vcard(contents: &str) -> Vec<(String, String)> {
+    let mut contacts = vec![];
+
+    let mut name = String::new();
+    let mut addresses: Vec<String> = vec![];
+
+    for line in unfold(contents) {
+        let (property, value) = match line.find(':') {
+            Some(idx) => (&line[..idx], line[idx + 1..].trim()),
+            None => continue
+        };
+
+        // strip parameters and the optional group: "item1.EMAIL;TYPE=work"
+        let property = property.split(';').next().unwrap_or("");
+        let property = property.rsplit('.').next().unwrap_or("").to_uppercase();
+
+        match property.as_str() {
+            "BEGIN" => {
+                name.clear();
+                addresses.clear();
+            },
+            "FN" => name = unescape(value),
+            "EMAIL" => if !value.is_empty() {
+                addresses.push(value.to_string())
+            },
+            "END" => {
+                for address in addresses.drain(..) {
+                    contacts.push((name.clone(), address));
+                }
+            },
+            _ => ()
+        }
+    }
+
+    contacts
+}
+
+
+pub fn parse_abook(contents: &str) -> Vec<(String, String)> {
+    let mut contacts = vec![];
+
+    let mut name = String::new();
+    let mut addresses: Vec<String> = vec![];
+
+    // a trailing empty section makes sure the last entry is added
+    for line in contents.lines().chain(Some("[]")) {
+        let line = line.trim();
+
+        if line.starts_with('[') {
+            for address in addresses.drain(..) {
+                contacts.push((name.clone(), address));
+            }
+            name.clear();
+        } else if line.starts_with("name=") {
+            name = line["name=".len()..].trim().to_string();
+        } else if line.starts_with("email=") {
+            addresses = line["email=".len()..].split(',')
+                                              .map(|a| a.trim().to_string())
+                                              .filter(|a| !a.is_empty())
+                                              .collect();
+        }
+    }
+
+    contacts
+}
+
+
+/// Join folded vCard lines
+fn unfold(contents: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+
+    for line in contents.lines() {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(&line[1..]);
+                continue;
+            }
+        }
+        lines.push(line.to_string());
+    }
+
+    lines
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("\\,", ",")
+         .replace("\\;", ";")
+         .replace("\\n", " ")
+         .replace("\\\\", "\\")
+}
+
+
+#[test]
+fn test_parse_vcard() {
+    let vcard = "BEGIN:VCARD\r\n\
+                 VERSION:3.0\r\n\
+                 FN:Doe\\, John\r\n\
+                 item1.EMAIL;TYPE=INTERNET:john@example.com\r\n\
+                 EMAIL:jo\r\n hn.doe@example.org\r\n\
+                 END:VCARD\r\n";
+
+    assert_eq!(parse_vcard(vcard),
+               vec![("Doe, John".to_string(), "john@example.com".to_string()),
+                    ("Doe, John".to_string(), "john.doe@example.org".to_string())]);
+}
+
+#[test]
+fn test_parse_abook() {
+    let abook = "[format]\nprogram=abook\nversion=0.6.1\n\n\
+                 [0]\nname=Jane Doe\nemail=jane@example.com,jd@example.org\n\n\
+                 [1]\nname=Nobody\n";
+
+    assert_eq!(parse_abook(abook),
+               vec![("Jane Doe".to_string(), "jane@example.com".to_string()),
+                    ("Jane Doe".to_string(), "jd@example.org".to_string())]);
+}
@@ -0,0 +1,344 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+
+use log::*;
+use chrono::Utc;
+use dirs;
+use md5;
+use serde_derive::{Serialize, Deserialize};
+use shellexpand;
+
+use gmime;
+use gmime::{InternetAddressExt, InternetAddressListExt, InternetAddressMailboxExt};
+use glib::prelude::*;
+use notmuch;
+
+use crate::settings::Settings;
+use crate::store;
+
+pub mod contacts;
+
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Contact {
+    pub address: String,
+    pub name: String,
+
+    /// Number of messages the address was seen in
+    pub count: u32,
+
+    /// Date of the newest message the address was seen in
+    pub last_seen: i64,
+
+    /// Listed in the user's contacts file
+    #[serde(default)]
+    pub in_contacts: bool,
+}
+
+impl Contact {
+
+    /// The address with display name, ready to put in a header
+    pub fn mailbox(&self) -> String {
+        if self.name.is_empty() {
+            return self.address.clone();
+        }
+
+        if self.name.contains(|c: char| ",;:<>@\"".contains(c)) {
+            format!("\"{}\" <{}>", self.name.replace('"', "\\\""), self.address)
+        } else {
+            format!("{} <{}>", self.name, self.address)
+        }
+    }
+
+    /// Rank by how often we see an address, like `notmuch address
+    /// --output=count`, but let recent correspondents win over old ones.
+    fn score(&self, now: i64) -> f64 {
+        let age_days = (now - self.last_seen).max(0) as f64 / 86400.0;
+        let recency = 1.0 / (1.0 + age_days / 30.0);
+        let bonus = if self.in_contacts { 10.0 } else { 0.0 };
+
+        f64::from(self.count) * recency + bonus
+    }
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AddressBook {
+    /// uuid and revision of the database at the last update
+    uuid: String,
+    revision: u64,
+
+    /// keyed by lowercase address
+    contacts: HashMap<String, Contact>,
+
+    /// hashes of the message ids of all messages that were counted. Tag
+    /// changes bump the revision of a message as well, so this tells new
+    /// mail, however old its date, from mail counted before.
+    seen: HashSet<u64>,
+}
+
+
+impl AddressBook {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// None when there is no home directory to keep the cache in
+    pub fn cache_path() -> Option<PathBuf> {
+        let mut path = dirs::cache_dir()?;
+        path.push("enamel");
+        path.push("addressbook.json");
+        Some(path)
+    }
+
+    /// Load a cached address book. A missing or broken cache gives an empty
+    /// address book, which is rebuilt on the next update.
+    pub fn load(path: &Path) -> Self {
+        store::load(path)
+    }
+
+    pub fn store(&self, path: &Path) -> io::Result<()> {
+        store::store(self, path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.contacts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contacts.is_empty()
+    }
+
+    /// Collect addresses from all messages that changed since the last update
+    pub fn update(&mut self, db: &notmuch::Database, headers: &[String]) -> Result<(), notmuch::Error> {
+        let revision = db.revision();
+
+        if revision.uuid != self.uuid {
+            debug!("addressbook: new database, rebuilding");
+            *self = AddressBook{
+                uuid: revision.uuid.clone(),
+                ..Default::default()
+            };
+        }
+
+        let query_str = if self.revision == 0 {
+            "*".to_string()
+        } else {
+            format!("lastmod:{}..", self.revision + 1)
+        };
+
+        let query = db.create_query(&query_str)?;
+        let messages = query.search_messages()?;
+
+        for message in messages {
+            let date = message.date();
+            if !self.first_seen(&message.id()) {
+                continue;
+            }
+
+            for header in headers {
+                if let Ok(Some(value)) = message.header(header) {
+                    self.add_addresses(&value, date);
+                }
+            }
+        }
+
+        self.revision = revision.revision as u64;
+        Ok(())
+    }
+
+    /// Whether a message is to be counted, because it was not counted before
+    fn first_seen(&mut self, message_id: &str) -> bool {
+        self.seen.insert(hash_id(message_id))
+    }
+
+    /// Mark addresses from the user's contacts file
+    pub fn merge_contacts(&mut self, contacts: Vec<(String, String)>) {
+        for (name, address) in contacts {
+            let contact = self.entry(&address);
+            contact.in_contacts = true;
+
+            // the contacts file knows the name best
+            if !name.is_empty() {
+                contact.name = name;
+            }
+        }
+    }
+
+    /// Find contacts whose address or name contains the query, best first
+    pub fn search(&self, query: &str, limit: usize) -> Vec<&Contact> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return vec![];
+        }
+
+        let now = Utc::now().timestamp();
+
+        let mut matches: Vec<(bool, f64, &Contact)> = self.contacts.iter()
+            .filter_map(|(address, contact)| {
+                let name = contact.name.to_lowercase();
+
+                if address.starts_with(&query) || name.starts_with(&query) {
+                    Some((true, contact.score(now), contact))
+                } else if address.contains(&query) || name.contains(&query) {
+                    Some((false, contact.score(now), contact))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        // prefix matches first, then by score
+        matches.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.partial_cmp(&a.1).unwrap()));
+
+        matches.into_iter()
+               .take(limit)
+               .map(|(_, _, contact)| contact)
+               .collect()
+    }
+
+    fn add_addresses(&mut self, header: &str, date: i64) {
+        let list = match gmime::InternetAddressList::parse(None, header) {
+            Some(list) => list,
+            None => return
+        };
+
+        for i in 0..list.length() {
+            let mailbox = match list.get_address(i).and_then(|a| a.downcast::<gmime::InternetAddressMailbox>().ok()) {
+                Some(mailbox) => mailbox,
+                None => continue
+            };
+
+            let address = match mailbox.get_addr() {
+                Some(address) => address.to_string(),
+                None => continue
+            };
+            let name = mailbox.get_name().map(|n| n.to_string()).unwrap_or_default();
+
+            let contact = self.entry(&address);
+            contact.count += 1;
+
+            if date >= contact.last_seen {
+                contact.last_seen = date;
+                if !name.is_empty() && !contact.in_contacts {
+                    contact.name = name;
+                }
+            }
+        }
+    }
+
+    fn entry(&mut self, address: &str) -> &mut Contact {
+        let key = address.trim().to_lowercase();
+
+        self.contacts.entry(key.clone()).or_insert_with(|| Contact{
+            address: key,
+            name: String::new(),
+            count: 0,
+            last_seen: 0,
+            in_contacts: false
+        })
+    }
+}
+
+
+/// Load the cached address book, bring it up to date with the database and
+/// merge the contacts file. Opens its own database handle, so it can run on
+/// a background thread.
+pub fn build(settings: &Settings) -> Result<AddressBook, notmuch::Error> {
+    let cache_path = AddressBook::cache_path();
+    let mut addressbook = match cache_path {
+        Some(ref path) => AddressBook::load(path),
+        None => {
+            warn!("No cache directory, the address book is built from scratch");
+            AddressBook::new()
+        }
+    };
+
+    let db_path = settings.notmuch_config.database_path();
+    let db = notmuch::Database::open(&db_path, notmuch::DatabaseMode::ReadOnly)?;
+
+    addressbook.update(&db, &settings.enamel_config.addressbook.headers)?;
+
+    if let Some(ref path) = cache_path {
+        if let Err(err) = addressbook.store(path) {
+            warn!("Could not store address book cache {:?}: {:?}", path, err);
+        }
+    }
+
+    // merged after storing, so removing someone from the contacts file works
+    if let Some(contacts_path) = settings.enamel_config.addressbook.contacts.as_ref() {
+        let contacts_path = shellexpand::tilde(contacts_path).into_owned();
+        match contacts::load(Path::new(&contacts_path)) {
+            Ok(contacts) => addressbook.merge_contacts(contacts),
+            Err(err) => warn!("Could not read contacts from {:?}: {:?}", contacts_path, err)
+        }
+    }
+
+    debug!("addressbook: {} addresses", addressbook.len());
+    Ok(addressbook)
+}
+
+
+fn hash_id(id: &str) -> u64 {
+    let digest = md5::compute(id);
+
+    digest.iter()
+          .take(8)
+          .fold(0u64, |acc, b| (acc << 8) | u64::from(*b))
+}
+
+
+#[test]
+fn test_search() {
+    let mut addressbook = AddressBook::new();
+    let now = Utc::now().timestamp();
+
+    for (address, name, count, age_days) in &[("jane@example.org", "Jane Doe", 3, 1),
+                                              ("bjane@example.com", "B. Jane", 40, 1),
+                                              ("john@example.org", "John", 50, 1)] {
+        let contact = addressbook.entry(address);
+        contact.name = name.to_string();
+        contact.count = *count;
+        contact.last_seen = now - age_days * 86400;
+    }
+
+    // prefix matches go first, however often the others are seen
+    let found: Vec<&str> = addressbook.search("Jane", 10).iter().map(|c| c.address.as_str()).collect();
+    assert_eq!(found, vec!["jane@example.org", "bjane@example.com"]);
+
+    assert_eq!(addressbook.search("example", 1)[0].address, "john@example.org");
+    assert!(addressbook.search("  ", 10).is_empty());
+    assert!(addressbook.search("nobody", 10).is_empty());
+}
+
+#[test]
+fn test_score() {
+    let now = Utc::now().timestamp();
+    let contact = |count, age_days, in_contacts| Contact{
+        address: "jane@example.org".to_string(),
+        name: String::new(),
+        count,
+        last_seen: now - age_days * 86400,
+        in_contacts
+    };
+
+    // recent correspondents win over old ones seen as often
+    assert!(contact(10, 1, false).score(now) > contact(10, 365, false).score(now));
+    // a month halves the weight of a message
+    assert!((contact(10, 30, false).score(now) - 5.0).abs() < 0.01);
+    assert!(contact(10, 0, false).score(now) > contact(20, 365, false).score(now));
+    assert!(contact(0, 365, true).score(now) >= 10.0);
+}
+
+#[test]
+fn test_first_seen() {
+    let mut addressbook = AddressBook::new();
+
+    // a tag change brings a message up again, it is counted only once
+    assert!(addressbook.first_seen("new@example.org"));
+    assert!(!addressbook.first_seen("new@example.org"));
+    assert!(addressbook.first_seen("imported@example.org"));
+    assert_eq!(addressbook.seen.len(), 2);
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+
+/// keydata is folded into lines of this many characters
+const KEYDATA_LINE_LENGTH: usize = 72;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreferEncrypt {
+    NoPreference,
+    Mutual,
+}
+
+impl Default for PreferEncrypt {
+    fn default() -> Self {
+        PreferEncrypt::NoPreference
+    }
+}
+
+
+/// An Autocrypt header, see https://autocrypt.org/level1.html#the-autocrypt-header
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AutocryptHeader {
+    pub addr: String,
+    pub prefer_encrypt: PreferEncrypt,
+
+    /// base64 encoded OpenPGP key, without whitespace
+    pub keydata: String,
+}
+
+
+impl AutocryptHeader {
+
+    /// Parse a header value. Headers with unknown critical attributes (not
+    /// starting with '_') or without addr or keydata are invalid.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut addr = None;
+        let mut prefer_encrypt = PreferEncrypt::NoPreference;
+        let mut keydata = None;
+
+        for attribute in value.split(';') {
+            let attribute = attribute.trim();
+            if attribute.is_empty() {
+                continue;
+            }
+
+            let mut kv = attribute.splitn(2, '=');
+            let key = kv.next()?.trim();
+            let value = kv.next()?.trim();
+
+            match key {
+                "addr" => addr = Some(value.to_lowercase()),
+                "prefer-encrypt" => if value == "mutual" {
+                    prefer_encrypt = PreferEncrypt::Mutual
+                },
+                "keydata" => keydata = Some(value.split_whitespace().collect::<String>()),
+                key if key.starts_with('_') => (),
+                _ => return None
+            }
+        }
+
+        let keydata = keydata.filter(|k| !k.is_empty())?;
+
+        Some(AutocryptHeader{
+            addr: addr.filter(|a| !a.is_empty())?,
+            prefer_encrypt,
+            keydata
+        })
+    }
+}
+
+impl fmt::Display for AutocryptHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "addr={};", self.addr)?;
+        if self.prefer_encrypt == PreferEncrypt::Mutual {
+            write!(f, " prefer-encrypt=mutual;")?;
+        }
+        write!(f, " keydata=")?;
+
+        // whitespace in keydata is allowed, and lets the header be folded
+        let chunks: Vec<&str> = self.keydata.as_bytes()
+                                    .chunks(KEYDATA_LINE_LENGTH)
+                                    .map(|chunk| std::str::from_utf8(chunk).unwrap_or(""))
+                                    .collect();
+        write!(f, "{}", chunks.join(" "))
+    }
+}
+
+
+#[test]
+fn parse_header() {
+    let header = AutocryptHeader::parse("addr=Alice@Example.org; prefer-encrypt=mutual; _foo=bar; keydata=\r\n mQGNBFn+zzUBDADBo2D+WUbm3lN1lXtQ\r\n 3DsW0Au8P=").unwrap();
+
+    assert_eq!(header.addr, "alice@example.org");
+    assert_eq!(header.prefer_encrypt, PreferEncrypt::Mutual);
+    assert_eq!(header.keydata, "mQGNBFn+zzUBDADBo2D+WUbm3lN1lXtQ3DsW0Au8P=");
+
+    assert_eq!(AutocryptHeader::parse(&header.to_string()), Some(header));
+
+    // unknown critical attribute, missing keydata
+    assert!(AutocryptHeader::parse("addr=a@b.example; type=2; keydata=abc").is_none());
+    assert!(AutocryptHeader::parse("addr=a@b.example; prefer-encrypt=mutual").is_none());
+}
@@ -0,0 +1,347 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::collections::HashMap;
+
+use log::*;
+use chrono::Utc;
+use dirs;
+use serde_derive::{Serialize, Deserialize};
+
+use notmuch;
+
+use crate::settings::{Settings, AccountConfig, EncryptPolicy, bare_address};
+use crate::store;
+use crate::mime::crypto::CryptoProtocol;
+
+mod header;
+
+pub use self::header::{AutocryptHeader, PreferEncrypt};
+
+/// A key that was not seen in a peer's mail for this long is stale
+const STALE_SECONDS: i64 = 35 * 24 * 60 * 60;
+
+
+/// What we know about a correspondent, following the Autocrypt Level 1 peer
+/// state
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct PeerState {
+    /// date of the newest message from the peer
+    pub last_seen: i64,
+
+    /// date of the newest message with an Autocrypt header
+    pub autocrypt_timestamp: i64,
+
+    /// base64 encoded key from that header
+    pub public_key: Option<String>,
+
+    pub prefer_mutual: bool,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recommendation {
+    /// no key for the peer
+    Disable,
+
+    /// the peer stopped sending Autocrypt headers, the key may be stale
+    Discourage,
+
+    Available,
+
+    /// both sides prefer encryption
+    Encrypt,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PeerStore {
+    /// uuid and revision of the database at the last update
+    uuid: String,
+    revision: u64,
+
+    /// keyed by lowercase address
+    peers: HashMap<String, PeerState>,
+}
+
+
+impl PeerStore {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The peer store is state rather than a cache: it can't be rebuilt once
+    /// mail is deleted. None when there is no home directory to keep it in.
+    pub fn store_path() -> Option<PathBuf> {
+        let mut path = dirs::data_dir()?;
+        path.push("enamel");
+        path.push("autocrypt.json");
+        Some(path)
+    }
+
+    pub fn load(path: &Path) -> Self {
+        store::load(path)
+    }
+
+    pub fn store(&self, path: &Path) -> io::Result<()> {
+        store::store(self, path)
+    }
+
+    pub fn get(&self, address: &str) -> Option<&PeerState> {
+        self.peers.get(&address.trim().to_lowercase())
+    }
+
+    /// Process a message from a peer. `header` is the value of its Autocrypt
+    /// header, if any.
+    pub fn process(&mut self, from: &str, date: i64, header: Option<&str>) {
+        let from = from.trim().to_lowercase();
+
+        // don't let mail from the future pin the state
+        let date = date.min(Utc::now().timestamp());
+
+        // a header only counts for the address that sent it
+        let header = header.and_then(AutocryptHeader::parse)
+                           .filter(|header| header.addr == from);
+
+        let peer = self.peers.entry(from).or_insert_with(PeerState::default);
+
+        if date > peer.last_seen {
+            peer.last_seen = date;
+        }
+
+        if let Some(header) = header {
+            if date > peer.autocrypt_timestamp {
+                peer.autocrypt_timestamp = date;
+                peer.public_key = Some(header.keydata);
+                peer.prefer_mutual = header.prefer_encrypt == PreferEncrypt::Mutual;
+            }
+        }
+    }
+
+    /// Collect Autocrypt headers from all messages that changed since the
+    /// last update. Mail from our own accounts is skipped.
+    pub fn update(&mut self, db: &notmuch::Database, settings: &Settings) -> Result<(), notmuch::Error> {
+        let revision = db.revision();
+
+        if revision.uuid != self.uuid {
+            debug!("autocrypt: new database, rescanning");
+            self.uuid = revision.uuid.clone();
+            self.revision = 0;
+        }
+
+        let query_str = if self.revision == 0 {
+            "*".to_string()
+        } else {
+            format!("lastmod:{}..", self.revision + 1)
+        };
+
+        let query = db.create_query(&query_str)?;
+        let messages = query.search_messages()?;
+
+        for message in messages {
+            let from = match message.header("From") {
+                Ok(Some(from)) => bare_address(&from).to_string(),
+                _ => continue
+            };
+
+            if settings.account_for(&from).is_some() {
+                continue;
+            }
+
+            let header = message.header("Autocrypt").ok().and_then(|h| h).map(|h| h.to_string());
+            self.process(&from, message.date(), header.as_ref().map(|h| h.as_str()));
+        }
+
+        self.revision = revision.revision as u64;
+        Ok(())
+    }
+
+    /// The Autocrypt recommendation for encrypting to a peer
+    pub fn recommendation(&self, address: &str, prefer_mutual: bool) -> Recommendation {
+        let peer = match self.get(address) {
+            Some(peer) if peer.public_key.is_some() => peer,
+            _ => return Recommendation::Disable
+        };
+
+        if peer.autocrypt_timestamp + STALE_SECONDS < peer.last_seen {
+            Recommendation::Discourage
+        } else if prefer_mutual && peer.prefer_mutual {
+            Recommendation::Encrypt
+        } else {
+            Recommendation::Available
+        }
+    }
+
+    /// Import the Autocrypt keys of the given peers into the gpg keyring, so
+    /// a message can be encrypted to them. Returns the fingerprints of the
+    /// keys that were new to the keyring, which go again with `remove_keys`
+    /// once the message is sent: Autocrypt keys don't belong with the keys
+    /// the user manages.
+    pub fn import_keys(&self, addresses: &[String]) -> io::Result<Vec<String>> {
+        let mut imported = vec![];
+
+        for address in addresses {
+            let key = match self.get(address).and_then(|peer| peer.public_key.as_ref()) {
+                Some(key) => key,
+                None => continue
+            };
+
+            debug!("autocrypt: importing key for {}", address);
+            let status = gpg(&["--batch", "--status-fd", "1", "--import"], Some(armor(key).as_bytes()))?;
+            imported.extend(new_keys(&status));
+        }
+        Ok(imported)
+    }
+}
+
+
+/// Load the peer store and bring it up to date with the database. Opens its
+/// own database handle, so it can run on a background thread.
+pub fn build(settings: &Settings) -> Result<PeerStore, notmuch::Error> {
+    let path = PeerStore::store_path();
+    let mut peers = match path {
+        Some(ref path) => PeerStore::load(path),
+        None => {
+            warn!("No data directory, autocrypt peers are not kept");
+            PeerStore::new()
+        }
+    };
+
+    let db_path = settings.notmuch_config.database_path();
+    let db = notmuch::Database::open(&db_path, notmuch::DatabaseMode::ReadOnly)?;
+
+    peers.update(&db, settings)?;
+
+    if let Some(ref path) = path {
+        if let Err(err) = peers.store(path) {
+            warn!("Could not store autocrypt peer store {:?}: {:?}", path, err);
+        }
+    }
+
+    Ok(peers)
+}
+
+/// Remove keys that `PeerStore::import_keys` added to the keyring
+pub fn remove_keys(fingerprints: &[String]) -> io::Result<()> {
+    if fingerprints.is_empty() {
+        return Ok(());
+    }
+
+    let mut args = vec!["--batch", "--yes", "--delete-keys", "--"];
+    args.extend(fingerprints.iter().map(|f| f.as_str()));
+    gpg(&args, None).map(|_| ())
+}
+
+/// Whether an account publishes its key with Autocrypt
+pub fn prefers_mutual(account: &AccountConfig) -> bool {
+    account.encrypt != EncryptPolicy::Never
+}
+
+/// Build the Autocrypt header for mail sent from an account. Only accounts
+/// using OpenPGP with a configured key have one.
+pub fn own_header(account: &AccountConfig) -> Option<AutocryptHeader> {
+    if !account.autocrypt || account.crypto_protocol != CryptoProtocol::OpenPgp {
+        return None;
+    }
+    let key = account.gpg_key.as_ref()?;
+
+    let exported = match gpg(&["--batch", "--armor", "--export", "--export-options", "export-minimal", key], None) {
+        Ok(exported) => exported,
+        Err(err) => {
+            warn!("autocrypt: could not export key {}: {:?}", key, err);
+            return None;
+        }
+    };
+
+    let keydata = dearmor(&exported);
+    if keydata.is_empty() {
+        warn!("autocrypt: no key found for {}", key);
+        return None;
+    }
+
+    Some(AutocryptHeader{
+        addr: account.email.to_lowercase(),
+        prefer_encrypt: if prefers_mutual(account) { PreferEncrypt::Mutual } else { PreferEncrypt::NoPreference },
+        keydata
+    })
+}
+
+
+fn gpg(args: &[&str], input: Option<&[u8]>) -> io::Result<String> {
+    let mut child = Command::new("gpg")
+                        .args(args)
+                        .stdin(Stdio::piped())
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::null())
+                        .spawn()?;
+
+    if let Some(input) = input {
+        child.stdin.take().unwrap().write_all(input)?;
+    }
+    drop(child.stdin.take());
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("gpg exited with {}", output.status)));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Fingerprints of the keys an import added, from the IMPORT_OK lines of
+/// gpg's status output. Bit 1 of the reason says the key is new, rather
+/// than merged into one that was already there.
+fn new_keys(status: &str) -> Vec<String> {
+    status.lines()
+          .filter_map(|line| {
+              let fields: Vec<&str> = line.split_whitespace().collect();
+              if fields.len() < 4 || fields[0] != "[GNUPG:]" || fields[1] != "IMPORT_OK" {
+                  return None;
+              }
+
+              let reason: u32 = fields[2].parse().ok()?;
+              if reason & 1 != 0 {
+                  Some(fields[3].to_string())
+              } else {
+                  None
+              }
+          })
+          .collect()
+}
+
+/// Wrap base64 keydata in ASCII armor, which gpg imports without a checksum
+fn armor(keydata: &str) -> String {
+    let lines: Vec<&str> = keydata.as_bytes()
+                                  .chunks(64)
+                                  .map(|chunk| std::str::from_utf8(chunk).unwrap_or(""))
+                                  .collect();
+
+    format!("-----BEGIN PGP PUBLIC KEY BLOCK-----\n\n{}\n-----END PGP PUBLIC KEY BLOCK-----\n", lines.join("\n"))
+}
+
+/// Get the base64 data out of an ASCII armored key, skipping the armor
+/// headers and the checksum
+fn dearmor(armored: &str) -> String {
+    let mut lines = armored.lines().skip_while(|line| !line.starts_with("-----BEGIN"));
+    lines.next();
+
+    lines.skip_while(|line| !line.trim().is_empty())
+         .take_while(|line| !line.starts_with("-----END"))
+         .map(|line| line.trim())
+         .filter(|line| !line.starts_with('='))
+         .collect()
+}
+
+
+#[test]
+fn imported_keys() {
+    let status = "[GNUPG:] IMPORTED 0123456789ABCDEF Alice <alice@example.org>\n\
+                  [GNUPG:] IMPORT_OK 1 A1B2C3D4E5F60718293A4B5C6D7E8F9012345678\n\
+                  [GNUPG:] IMPORT_OK 0 FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF\n\
+                  [GNUPG:] IMPORT_OK 2 EEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEE\n\
+                  [GNUPG:] IMPORT_RES 1 0 0 0 1 0 0 0 0 0 0 0 0 0\n";
+
+    // only new keys are removed again, never ones that were merged
+    assert_eq!(new_keys(status), vec!["A1B2C3D4E5F60718293A4B5C6D7E8F9012345678".to_string()]);
+}
@@ -0,0 +1,147 @@
+//! The command language of the `:` command line in the terminal client and
+//! the command palette in the GTK client.
+
+use std::fmt;
+
+use crate::settings::ACTIONS;
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// show the threads matching a notmuch query
+    Search(String),
+
+    /// change the tags of the marked threads, or the selected thread
+    Tag{ add: Vec<String>, remove: Vec<String> },
+
+    /// mark the threads in the list that also match a notmuch query
+    Mark(String),
+
+    /// show the thread matching a notmuch query, like `id:…`
+    Open(String),
+
+    Quit,
+
+    /// run an action that can also be bound to keys
+    Action(&'static str),
+}
+
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Empty,
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    InvalidTagChange(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Empty => write!(f, "No command given"),
+            Error::UnknownCommand(name) => write!(f, "Unknown command: {}", name),
+            Error::MissingArgument(usage) => write!(f, "Usage: {}", usage),
+            Error::InvalidTagChange(change) => write!(f, "Tag changes start with + or -, not {:?}", change)
+        }
+    }
+}
+
+
+/// A command that takes arguments, as listed in help and completion
+#[derive(Clone, Copy, Debug)]
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo{ name: "search", usage: "search <query>", description: "Show the threads matching a query" },
+    CommandInfo{ name: "tag", usage: "tag +<tag> -<tag> ...", description: "Add and remove tags of the marked or selected threads" },
+    CommandInfo{ name: "mark", usage: "mark <query>", description: "Mark the listed threads matching a query" },
+    CommandInfo{ name: "open", usage: "open <query>", description: "Show the thread matching a query" },
+    CommandInfo{ name: "quit", usage: "quit", description: "Quit" },
+];
+
+
+impl Command {
+
+    /// Parse a command line, with or without the leading ':'. Besides the
+    /// commands above, any action name from the key bindings is a command.
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        let line = line.trim();
+        let line = line.trim_start_matches(':').trim_start();
+
+        let (name, args) = match line.find(char::is_whitespace) {
+            Some(end) => (&line[..end], line[end..].trim()),
+            None => (line, "")
+        };
+
+        match name {
+            "" => Err(Error::Empty),
+            "search" | "s" => {
+                if args.is_empty() {
+                    Err(Error::MissingArgument("search <query>"))
+                } else {
+                    Ok(Command::Search(args.to_string()))
+                }
+            },
+            "open" | "o" => {
+                if args.is_empty() {
+                    Err(Error::MissingArgument("open <query>"))
+                } else {
+                    Ok(Command::Open(args.to_string()))
+                }
+            },
+            "mark" | "m" => {
+                if args.is_empty() {
+                    Err(Error::MissingArgument("mark <query>"))
+                } else {
+                    Ok(Command::Mark(args.to_string()))
+                }
+            },
+            "tag" | "t" => parse_tag_changes(args),
+            "quit" | "q" => Ok(Command::Quit),
+            _ => ACTIONS.iter()
+                        .find(|action| action.name == name)
+                        .map(|action| Command::Action(action.name))
+                        .ok_or_else(|| Error::UnknownCommand(name.to_string()))
+        }
+    }
+}
+
+fn parse_tag_changes(args: &str) -> Result<Command, Error> {
+    let mut add = vec![];
+    let mut remove = vec![];
+
+    for change in args.split_whitespace() {
+        match (change.chars().next(), change.get(1..).unwrap_or("")) {
+            (Some('+'), tag) if !tag.is_empty() => add.push(tag.to_string()),
+            (Some('-'), tag) if !tag.is_empty() => remove.push(tag.to_string()),
+            _ => return Err(Error::InvalidTagChange(change.to_string()))
+        }
+    }
+
+    if add.is_empty() && remove.is_empty() {
+        return Err(Error::MissingArgument("tag +<tag> -<tag> ..."));
+    }
+
+    Ok(Command::Tag{ add, remove })
+}
+
+
+#[test]
+fn test_parse() {
+    assert_eq!(Command::parse(":search tag:inbox and from:me"),
+               Ok(Command::Search("tag:inbox and from:me".to_string())));
+    assert_eq!(Command::parse("tag +done -inbox"),
+               Ok(Command::Tag{ add: vec!["done".to_string()], remove: vec!["inbox".to_string()] }));
+    assert_eq!(Command::parse(":open id:1234@example.org"), Ok(Command::Open("id:1234@example.org".to_string())));
+    assert_eq!(Command::parse(":mark from:me"), Ok(Command::Mark("from:me".to_string())));
+    assert_eq!(Command::parse(":q"), Ok(Command::Quit));
+    assert_eq!(Command::parse(":archive"), Ok(Command::Action("archive")));
+
+    assert_eq!(Command::parse(":tag done"), Err(Error::InvalidTagChange("done".to_string())));
+    assert_eq!(Command::parse(":search"), Err(Error::MissingArgument("search <query>")));
+    assert_eq!(Command::parse(":frobnicate"), Err(Error::UnknownCommand("frobnicate".to_string())));
+}
@@ -0,0 +1,201 @@
+use std::process::{Command, Stdio};
+
+use log::*;
+
+use glib::prelude::*;
+use gmime;
+
+use crate::settings::{AccountConfig, EncryptPolicy};
+use crate::mime::crypto::CryptoProtocol;
+use crate::autocrypt::{self, PeerStore};
+
+use super::Error;
+
+
+/// What the composer should do for a set of recipients
+#[derive(Clone, Debug, Default)]
+pub struct Recommendation {
+    pub sign: bool,
+    pub encrypt: bool,
+
+    /// recipients without a usable public key
+    pub missing_keys: Vec<String>,
+
+    /// recipients we only have an Autocrypt key for, which has to be
+    /// imported before encrypting
+    pub autocrypt_keys: Vec<String>,
+}
+
+impl Recommendation {
+
+    /// Encryption is wanted, but not every recipient can read it
+    pub fn needs_warning(&self) -> bool {
+        self.encrypt && !self.missing_keys.is_empty()
+    }
+}
+
+
+/// Work out the signing and encryption defaults from the account policy, the
+/// keys we have for the recipients and what their Autocrypt state says.
+/// `has_key` looks up a single address in the keyring, so callers can cache
+/// lookups while the recipients are being typed.
+pub fn recommend<F>(account: &AccountConfig,
+                    recipients: &[String],
+                    peers: &PeerStore,
+                    mut has_key: F) -> Recommendation
+    where F: FnMut(&str) -> bool
+{
+    let mut missing_keys = vec![];
+    let mut autocrypt_keys = vec![];
+    let mut discouraged = false;
+
+    let autocrypt_usable = account.crypto_protocol == CryptoProtocol::OpenPgp;
+    let prefer_mutual = autocrypt::prefers_mutual(account);
+
+    for address in recipients {
+        if has_key(address) {
+            continue;
+        }
+
+        let peer = if autocrypt_usable {
+            peers.recommendation(address, prefer_mutual)
+        } else {
+            autocrypt::Recommendation::Disable
+        };
+
+        match peer {
+            autocrypt::Recommendation::Disable => missing_keys.push(address.clone()),
+            autocrypt::Recommendation::Discourage => {
+                discouraged = true;
+                autocrypt_keys.push(address.clone());
+            },
+            _ => autocrypt_keys.push(address.clone())
+        }
+    }
+
+    let encrypt = match account.encrypt {
+        EncryptPolicy::Never => false,
+        EncryptPolicy::Auto => !recipients.is_empty() && missing_keys.is_empty() && !discouraged,
+        EncryptPolicy::Always => true
+    };
+
+    Recommendation{
+        sign: account.sign,
+        encrypt,
+        missing_keys,
+        autocrypt_keys
+    }
+}
+
+/// Check whether gpg (or gpgsm for S/MIME) has a valid public key for an
+/// address
+pub fn has_public_key(protocol: CryptoProtocol, address: &str) -> bool {
+    let program = match protocol {
+        CryptoProtocol::OpenPgp => "gpg",
+        CryptoProtocol::Smime => "gpgsm"
+    };
+
+    let output = Command::new(program)
+                        .args(&["--batch", "--with-colons", "--list-keys", "--"])
+                        .arg(format!("<{}>", address))
+                        .stdin(Stdio::null())
+                        .stderr(Stdio::null())
+                        .output();
+
+    let output = match output {
+        Ok(output) => output,
+        Err(err) => {
+            warn!("Could not run {} to look up a key for {}: {:?}", program, address, err);
+            return false;
+        }
+    };
+
+    // the second field is the validity: skip invalid, disabled, revoked and
+    // expired keys. gpgsm lists certificates as 'crt' records.
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.starts_with("pub:") || line.starts_with("crt:"))
+        .filter_map(|line| line.split(':').nth(1))
+        .any(|validity| !["i", "d", "r", "e"].contains(&validity))
+}
+
+
+/// Wrap a body part in multipart/signed or multipart/encrypted, or in
+/// application/pkcs7-mime for encrypted S/MIME. Encrypted mail is also
+/// encrypted to the account itself, so the sent copy stays readable.
+///
+/// The flags can hide the key ids, so Bcc recipients can't be found from
+/// them, which only works for OpenPGP, or trust keys from Autocrypt.
+pub(crate) fn protect(part: gmime::Object,
+                      account: &AccountConfig,
+                      sign: bool,
+                      encrypt: bool,
+                      recipients: &[String],
+                      flags: gmime::EncryptFlags) -> Result<gmime::Object, Error> {
+
+    if !sign && !encrypt {
+        return Ok(part);
+    }
+
+    let userid = account.signing_key();
+
+    match account.crypto_protocol {
+        CryptoProtocol::OpenPgp => protect_openpgp(part, &userid, sign, encrypt, recipients, flags),
+        CryptoProtocol::Smime => protect_smime(part, &userid, sign, encrypt, recipients, flags)
+    }
+}
+
+fn protect_openpgp(part: gmime::Object,
+                   userid: &str,
+                   sign: bool,
+                   encrypt: bool,
+                   recipients: &[String],
+                   flags: gmime::EncryptFlags) -> Result<gmime::Object, Error> {
+
+    let ctx = gmime::GpgContext::new();
+
+    if encrypt {
+        let mut keys: Vec<&str> = recipients.iter().map(|r| r.as_str()).collect();
+        keys.push(userid);
+
+        let encrypted = gmime::MultipartEncrypted::encrypt(&ctx,
+                                                           &part,
+                                                           sign,
+                                                           Some(userid),
+                                                           flags,
+                                                           &keys)?;
+        Ok(encrypted.upcast())
+    } else {
+        let signed = gmime::MultipartSigned::sign(&ctx, &part, userid)?;
+        Ok(signed.upcast())
+    }
+}
+
+/// S/MIME signs with a detached multipart/signed, which clients without
+/// S/MIME support can still read, and encrypts by enveloping the (signed)
+/// part.
+fn protect_smime(part: gmime::Object,
+                 userid: &str,
+                 sign: bool,
+                 encrypt: bool,
+                 recipients: &[String],
+                 flags: gmime::EncryptFlags) -> Result<gmime::Object, Error> {
+
+    let ctx = gmime::Pkcs7Context::new();
+
+    let part = if sign {
+        gmime::MultipartSigned::sign(&ctx, &part, userid)?.upcast()
+    } else {
+        part
+    };
+
+    if !encrypt {
+        return Ok(part);
+    }
+
+    let mut keys: Vec<&str> = recipients.iter().map(|r| r.as_str()).collect();
+    keys.push(userid);
+
+    let encrypted = gmime::ApplicationPkcs7Mime::encrypt(&part, flags - gmime::EncryptFlags::THROW_KEYIDS, &keys)?;
+    Ok(encrypted.upcast())
+}
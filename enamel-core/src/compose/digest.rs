@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+use log::*;
+use gmime;
+use gmime::{MessageExt, ObjectExt, InternetAddressListExt};
+
+use crate::settings::AccountConfig;
+use crate::mime::{Chunk, MimeMessage, ParseOptions};
+use crate::mime::render;
+
+use super::ComposeMessage;
+
+
+/// A new message that forwards the given messages inline, one after the
+/// other, above the signature. Only the text is forwarded: attachments and
+/// encrypted bodies are left out, with a line saying so in their place.
+/// Files that can't be opened are left out, and None is returned when that
+/// leaves nothing to forward.
+pub fn forward_digest(account: &AccountConfig, files: &[PathBuf]) -> Option<ComposeMessage> {
+    let mut message = ComposeMessage::new(account);
+
+    let mut forwarded = 0;
+    let mut subjects = vec![];
+    let mut digest = String::new();
+
+    for file in files {
+        let mime = match MimeMessage::open(file, &ParseOptions::default()) {
+            Some(mime) => mime,
+            None => {
+                warn!("Could not forward {:?}", file);
+                continue;
+            }
+        };
+
+        let subject = mime.message.get_subject().map(|s| s.to_string()).unwrap_or_default();
+        let addresses = |address_type| {
+            mime.message.get_addresses(address_type)
+                        .and_then(|list| list.to_string(None, false))
+                        .map(|s| s.to_string())
+                        .unwrap_or_default()
+        };
+
+        digest.push_str("\n\n---------- Forwarded message ----------\n");
+        digest.push_str(&format!("From: {}\n", addresses(gmime::AddressType::From)));
+        digest.push_str(&format!("Date: {}\n", mime.message.get_header("Date").map(|s| s.to_string()).unwrap_or_default()));
+        digest.push_str(&format!("Subject: {}\n", subject));
+        digest.push_str(&format!("To: {}\n", addresses(gmime::AddressType::To)));
+        if let Some(text) = render::body_text(&mime.root) {
+            digest.push_str("\n");
+            digest.push_str(text.trim_end());
+            digest.push_str("\n");
+        }
+
+        let mut left_out = vec![];
+        not_forwarded(&mime.root, &mut left_out);
+        if !left_out.is_empty() {
+            digest.push_str("\n");
+            digest.push_str(&left_out.join("\n"));
+            digest.push_str("\n");
+        }
+
+        if !subjects.contains(&subject) {
+            subjects.push(subject);
+        }
+        forwarded += 1;
+    }
+
+    if forwarded == 0 {
+        return None;
+    }
+
+    message.subject = match subjects.len() {
+        1 => format!("Fwd: {}", subjects[0]),
+        _ => format!("Fwd: {} messages", forwarded)
+    };
+    message.body = digest + &message.body;
+    Some(message)
+}
+
+/// Notes for the parts below `chunk` that the digest has no text for
+fn not_forwarded(chunk: &Chunk, notes: &mut Vec<String>) {
+    if chunk.attachment {
+        let name = chunk.filename.as_ref().unwrap_or(&chunk.mime_type);
+        notes.push(format!("[attachment {} not forwarded]", name));
+        return;
+    }
+
+    match chunk.crypto {
+        Some(ref crypto) if crypto.encrypted && !crypto.decrypted => {
+            notes.push("[encrypted body not forwarded]".to_string());
+            return;
+        },
+        _ => ()
+    }
+
+    for kid in &chunk.kids {
+        not_forwarded(kid, notes);
+    }
+}
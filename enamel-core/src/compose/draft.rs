@@ -0,0 +1,88 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::*;
+
+use crate::settings::{Settings, AccountConfig};
+use crate::database::{Manager, Maildir};
+use crate::database::maildir::{FLAG_DRAFT, FLAG_SEEN};
+
+use super::{Error, ComposeMessage, resolve_folder};
+
+pub const TAG_DRAFT: &str = "draft";
+
+
+/// A draft on disk. Every save writes a new file and removes the previous
+/// one, so a crash while saving never loses the last good copy.
+#[derive(Debug, Default)]
+pub struct Draft {
+    path: Option<PathBuf>
+}
+
+impl Draft {
+
+    pub fn new() -> Self {
+        Draft{
+            path: None
+        }
+    }
+
+    /// Resume editing a draft that was saved earlier
+    pub fn open(path: &Path) -> Option<(Self, ComposeMessage)> {
+        let message = ComposeMessage::from_file(path)?;
+
+        Some((Draft{
+            path: Some(path.to_path_buf())
+        }, message))
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_ref().map(|p| p.as_path())
+    }
+
+    /// Save the message to the account's drafts folder and index it.
+    ///
+    /// Returns false if the account doesn't have a drafts folder.
+    pub fn save(&mut self,
+                settings: &Settings,
+                dbmanager: &Manager,
+                account: &AccountConfig,
+                message: &ComposeMessage) -> Result<bool, Error> {
+
+        let folder = match account.save_drafts_to.as_ref() {
+            Some(folder) => folder,
+            None => return Ok(false)
+        };
+
+        let maildir = Maildir::new(resolve_folder(settings, folder));
+        let flags: String = [FLAG_DRAFT, FLAG_SEEN].iter().collect();
+        let path = maildir.store_cur(&message.to_draft_bytes(account)?, &flags)?;
+
+        debug!("Saved draft to {:?}", path);
+
+        // every copy has the same Message-ID, so the previous one leaves the
+        // database before the new one is indexed
+        if let Some(previous) = self.path.as_ref() {
+            dbmanager.remove_message(previous)?;
+        }
+        dbmanager.add_message(&path, &[TAG_DRAFT.to_string()])?;
+
+        if let Some(previous) = self.path.replace(path) {
+            debug!("Removing draft {:?}", previous);
+            fs::remove_file(&previous)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Delete the draft from disk and from the database
+    pub fn discard(&mut self, dbmanager: &Manager) -> Result<(), Error> {
+        if let Some(path) = self.path.take() {
+            debug!("Removing draft {:?}", path);
+
+            dbmanager.remove_message(&path)?;
+            fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+}
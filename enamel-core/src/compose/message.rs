@@ -0,0 +1,281 @@
+use std::path::Path;
+
+use glib;
+use glib::prelude::*;
+use gmime;
+use gmime::{MessageExt, MultipartExt, ObjectExt, ParserExt, TextPartExt, InternetAddressListExt,
+            InternetAddressMailboxExt};
+
+use crate::settings::{AccountConfig, EncryptPolicy};
+use crate::autocrypt;
+use crate::mime::crypto as mime_crypto;
+
+use super::Error;
+use super::crypto;
+
+
+/// A message that is being written
+#[derive(Clone, Debug, Default)]
+pub struct ComposeMessage {
+    pub from: String,
+    pub to: String,
+    pub cc: String,
+    pub bcc: String,
+    pub reply_to: String,
+    pub subject: String,
+    pub body: String,
+
+    /// Html signature, sent as html alternative of the body
+    pub html_signature: Option<String>,
+
+    pub message_id: String,
+    pub in_reply_to: Option<String>,
+    pub references: Option<String>,
+
+    /// Sign and/or encrypt with PGP/MIME when sending
+    pub sign: bool,
+    pub encrypt: bool,
+
+    /// Encrypt to keys gpg doesn't consider valid. Only set for messages to
+    /// recipients whose keys come from Autocrypt.
+    pub trust_recipients: bool,
+}
+
+
+impl ComposeMessage {
+
+    pub fn new(account: &AccountConfig) -> Self {
+        let mut message = ComposeMessage{
+            from: account.mailbox(),
+            reply_to: account.reply_to.clone().unwrap_or_default(),
+            message_id: generate_message_id(&account.email),
+            sign: account.sign,
+            encrypt: account.encrypt == EncryptPolicy::Always,
+            ..Default::default()
+        };
+
+        if let Some(signature) = account.signature.as_ref() {
+            if let Some(contents) = signature.contents() {
+                if signature.html {
+                    message.html_signature = Some(contents);
+                } else if signature.separator {
+                    message.body = format!("\n\n-- \n{}", contents);
+                } else {
+                    message.body = format!("\n\n{}", contents);
+                }
+            }
+        }
+
+        message
+    }
+
+    /// Read a message back from disk, e.g. to resume a draft
+    pub fn from_file(path: &Path) -> Option<Self> {
+        let stream = gmime::StreamFile::open(&path.to_string_lossy(), &"r").ok()?;
+        let parser = gmime::Parser::new_with_stream(&stream);
+        let message = parser.construct_message(None)?;
+
+        let addresses = |address_type| {
+            message.get_addresses(address_type)
+                   .and_then(|list| list.to_string(None, false))
+                   .map(|s| s.to_string())
+                   .unwrap_or_default()
+        };
+
+        // drafts of encrypted messages are encrypted to the account
+        let (body, encrypt) = match message.get_body() {
+            Some(part) => open_part(part),
+            None => (None, false)
+        };
+        let body = body.as_ref()
+                       .and_then(plain_text)
+                       .unwrap_or_default();
+
+        Some(ComposeMessage{
+            from: addresses(gmime::AddressType::From),
+            to: addresses(gmime::AddressType::To),
+            cc: addresses(gmime::AddressType::Cc),
+            bcc: addresses(gmime::AddressType::Bcc),
+            reply_to: addresses(gmime::AddressType::ReplyTo),
+            subject: message.get_subject().map(|s| s.to_string()).unwrap_or_default(),
+            body,
+            message_id: message.get_message_id().map(|s| s.to_string()).unwrap_or_default(),
+            in_reply_to: message.get_header("In-Reply-To").map(|s| s.to_string()),
+            references: message.get_header("References").map(|s| s.to_string()),
+            html_signature: None,
+            sign: false,
+            encrypt,
+            trust_recipients: false,
+        })
+    }
+
+    /// Bare addresses of everyone the message goes to
+    pub fn recipients(&self) -> Vec<String> {
+        bare_addresses(&[&self.to, &self.cc, &self.bcc])
+    }
+
+    /// Bare addresses of the Bcc recipients, who must not show up in what
+    /// the others receive
+    pub fn bcc_recipients(&self) -> Vec<String> {
+        bare_addresses(&[&self.bcc])
+    }
+
+    /// The message as it is stored as a draft. Never signed, and only
+    /// encrypted to the account itself, so an encrypted message is not kept
+    /// in the clear while it is being written.
+    pub fn to_draft_bytes(&self, account: &AccountConfig) -> Result<Vec<u8>, Error> {
+        let body = crypto::protect(self.body_part(), account, false, self.encrypt, &[], gmime::EncryptFlags::NONE)?;
+        Ok(mime_bytes(&self.build_mime(body)))
+    }
+
+    /// The message as it is sent, signed and encrypted as requested, with
+    /// the account's Autocrypt header. It still has the Bcc header, which is
+    /// kept in the sent folder but not transmitted.
+    pub fn to_sendable_mime(&self, account: &AccountConfig) -> Result<gmime::Message, Error> {
+        let mut flags = gmime::EncryptFlags::NONE;
+        if !self.bcc_recipients().is_empty() {
+            flags |= gmime::EncryptFlags::THROW_KEYIDS;
+        }
+        if self.trust_recipients {
+            flags |= gmime::EncryptFlags::ALWAYS_TRUST;
+        }
+
+        let body = crypto::protect(self.body_part(), account, self.sign, self.encrypt, &self.recipients(), flags)?;
+        let message = self.build_mime(body);
+
+        if let Some(header) = autocrypt::own_header(account) {
+            message.set_header("Autocrypt", &header.to_string(), "utf-8");
+        }
+
+        Ok(message)
+    }
+
+    fn build_mime(&self, body: gmime::Object) -> gmime::Message {
+        let message = gmime::Message::new(true);
+
+        let add_addresses = |address_type, addresses: &str| {
+            if addresses.trim().is_empty() {
+                return;
+            }
+            if let (Some(list), Some(parsed)) = (message.get_addresses(address_type),
+                                                 gmime::InternetAddressList::parse(None, addresses)) {
+                list.append(&parsed);
+            }
+        };
+
+        add_addresses(gmime::AddressType::From, &self.from);
+        add_addresses(gmime::AddressType::To, &self.to);
+        add_addresses(gmime::AddressType::Cc, &self.cc);
+        add_addresses(gmime::AddressType::Bcc, &self.bcc);
+        add_addresses(gmime::AddressType::ReplyTo, &self.reply_to);
+
+        message.set_subject(&self.subject, "utf-8");
+        message.set_message_id(&self.message_id);
+        message.set_date(&glib::DateTime::new_now_local());
+
+        if let Some(in_reply_to) = self.in_reply_to.as_ref() {
+            message.set_header("In-Reply-To", in_reply_to, "utf-8");
+        }
+        if let Some(references) = self.references.as_ref() {
+            message.set_header("References", references, "utf-8");
+        }
+        message.set_header("User-Agent", "Enamel", "utf-8");
+
+        message.set_mime_part(&body);
+
+        message
+    }
+
+    /// The text/plain body, or a multipart/alternative if there is an html
+    /// signature to include
+    fn body_part(&self) -> gmime::Object {
+        let text = gmime::TextPart::new_with_subtype("plain");
+        text.set_text(&self.body);
+
+        let signature = match self.html_signature.as_ref() {
+            Some(signature) => signature,
+            None => return text.upcast()
+        };
+
+        let html = gmime::TextPart::new_with_subtype("html");
+        html.set_text(&format!("<pre>{}</pre>\n<div class=\"signature\">{}</div>",
+                               glib::markup_escape_text(&self.body),
+                               signature));
+
+        let alternative = gmime::Multipart::new_with_subtype("alternative");
+        alternative.add(&text);
+        alternative.add(&html);
+
+        alternative.upcast()
+    }
+}
+
+
+pub(super) fn mime_bytes(message: &gmime::Message) -> Vec<u8> {
+    message.to_string(None)
+           .map(|s| s.as_bytes().to_vec())
+           .unwrap_or_default()
+}
+
+
+/// Bare addresses in address fields like To:
+fn bare_addresses(fields: &[&String]) -> Vec<String> {
+    let mut recipients = vec![];
+
+    for addresses in fields {
+        let list = match gmime::InternetAddressList::parse(None, addresses) {
+            Some(list) => list,
+            None => continue
+        };
+
+        for i in 0..list.length() {
+            let address = list.get_address(i)
+                              .and_then(|a| a.downcast::<gmime::InternetAddressMailbox>().ok())
+                              .and_then(|mailbox| mailbox.get_addr());
+            if let Some(address) = address {
+                recipients.push(address.to_string());
+            }
+        }
+    }
+
+    recipients
+}
+
+
+/// Decrypt the body of a draft if it is encrypted. Also says whether it was.
+fn open_part(part: gmime::Object) -> (Option<gmime::Object>, bool) {
+    if let Ok(encrypted) = part.clone().downcast::<gmime::MultipartEncrypted>() {
+        return (mime_crypto::decrypt(&encrypted, &[]).0, true);
+    }
+    if let Ok(pkcs7) = part.clone().downcast::<gmime::ApplicationPkcs7Mime>() {
+        return (mime_crypto::open_pkcs7(&pkcs7, &[]).0, true);
+    }
+    (Some(part), false)
+}
+
+/// Find the first text part, looking into multiparts
+fn plain_text(part: &gmime::Object) -> Option<String> {
+    if let Ok(multipart) = part.clone().downcast::<gmime::Multipart>() {
+        return (0..multipart.get_count())
+                    .filter_map(|i| multipart.get_part(i))
+                    .filter_map(|part| plain_text(&part))
+                    .next();
+    }
+
+    part.clone()
+        .downcast::<gmime::TextPart>().ok()
+        .and_then(|part| part.get_text())
+        .map(|text| text.to_string())
+}
+
+fn generate_message_id(email: &str) -> String {
+    // use the domain of the sender rather than the local hostname
+    let fqdn = email.rsplit('@')
+                    .next()
+                    .filter(|domain| !domain.is_empty())
+                    .unwrap_or("localhost");
+
+    gmime::utils_generate_message_id(fqdn)
+        .map(|id| id.to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,63 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use glib;
+use notmuch;
+use shellexpand;
+
+use crate::settings::Settings;
+
+mod message;
+mod draft;
+mod send;
+mod sent;
+mod crypto;
+mod digest;
+
+pub use self::message::ComposeMessage;
+pub use self::draft::{Draft, TAG_DRAFT};
+pub use self::send::{send, send_message};
+pub use self::sent::save_sent;
+pub use self::crypto::{Recommendation, recommend, has_public_key};
+pub use self::digest::forward_digest;
+
+
+#[derive(Debug)]
+pub enum Error{
+    IoError(io::Error),
+    NotMuchError(notmuch::Error),
+    SendError(String),
+    CryptoError(glib::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl From<notmuch::Error> for Error {
+    fn from(err: notmuch::Error) -> Error {
+        Error::NotMuchError(err)
+    }
+}
+
+impl From<glib::Error> for Error {
+    fn from(err: glib::Error) -> Error {
+        Error::CryptoError(err)
+    }
+}
+
+
+/// Resolve a configured maildir folder. Relative folders are taken relative
+/// to the notmuch database, just like notmuch's `folder:` prefix.
+pub(crate) fn resolve_folder(settings: &Settings, folder: &str) -> PathBuf {
+    let expanded = shellexpand::tilde(folder).into_owned();
+    let path = Path::new(&expanded);
+
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+
+    settings.notmuch_config.mail_root().join(path)
+}
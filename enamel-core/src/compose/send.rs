@@ -0,0 +1,80 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use log::*;
+use gmime;
+
+use crate::settings::{Settings, AccountConfig, SendConfig};
+use crate::database::{Manager, Maildir};
+
+use super::{Error, ComposeMessage, Draft, save_sent, resolve_folder};
+use super::message::mime_bytes;
+
+
+/// Hand a message to the account's sending backend. Both get the message
+/// with its Bcc header: `sendmail -t` takes the recipients from the headers
+/// and strips Bcc before it transmits, and an outbox keeps it for the tool
+/// that sends the queue later.
+pub fn send(settings: &Settings, account: &AccountConfig, message: &gmime::Message) -> Result<(), Error> {
+
+    if settings.enamel_config.debug.dryrun_sending {
+        info!("Dry run: not sending message with {:?}", account.send);
+        return Ok(());
+    }
+
+    match &account.send {
+        SendConfig::Sendmail{ command } => sendmail(command, &mime_bytes(message)),
+        SendConfig::Outbox{ folder } => {
+            let path = Maildir::new(resolve_folder(settings, folder)).store_new(&mime_bytes(message))?;
+            debug!("Queued message in outbox as {:?}", path);
+            Ok(())
+        }
+    }
+}
+
+/// Pipe a message into a sendmail compatible command
+fn sendmail(command: &str, message: &[u8]) -> Result<(), Error> {
+    debug!("Sending message with '{}'", command);
+
+    let mut child = Command::new("sh")
+                        .arg("-c")
+                        .arg(command)
+                        .stdin(Stdio::piped())
+                        .stderr(Stdio::piped())
+                        .spawn()?;
+
+    child.stdin.take().unwrap().write_all(message)?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        return Err(Error::SendError(stderr));
+    }
+
+    Ok(())
+}
+
+
+/// Send a message, store it in the sent folder and remove its draft. Only
+/// sending can fail: once the message is out, trouble with the sent copy or
+/// the draft is logged, so nobody is tempted to send it again.
+pub fn send_message(settings: &Settings,
+                    dbmanager: &Manager,
+                    account: &AccountConfig,
+                    message: &ComposeMessage,
+                    draft: &mut Draft) -> Result<(), Error> {
+
+    let mime = message.to_sendable_mime(account)?;
+    let content = mime_bytes(&mime);
+
+    send(settings, account, &mime)?;
+
+    if let Err(err) = save_sent(settings, dbmanager, account, &content) {
+        error!("Could not save the sent message: {:?}", err);
+    }
+    if let Err(err) = draft.discard(dbmanager) {
+        error!("Could not remove the draft of the sent message: {:?}", err);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use std::path::PathBuf;
+
+use log::*;
+
+use crate::settings::{Settings, AccountConfig};
+use crate::database::{Manager, Maildir};
+use crate::database::maildir::FLAG_SEEN;
+
+use super::{Error, TAG_DRAFT, resolve_folder};
+
+
+/// Store a sent message in the account's sent folder and add it to the
+/// database, so it shows up in its thread right away. The sent copy shares
+/// the Message-ID of its draft, and is no draft itself.
+///
+/// Returns the path the message was stored at, or `None` if the account
+/// doesn't save sent mail.
+pub fn save_sent(settings: &Settings,
+                 dbmanager: &Manager,
+                 account: &AccountConfig,
+                 message: &[u8]) -> Result<Option<PathBuf>, Error> {
+
+    let folder = match account.save_sent_to.as_ref() {
+        Some(folder) => folder,
+        None => return Ok(None)
+    };
+
+    let maildir = Maildir::new(resolve_folder(settings, folder));
+    let path = maildir.store_cur(message, &FLAG_SEEN.to_string())?;
+
+    debug!("Saved sent message to {:?}", path);
+
+    dbmanager.index_message(&path, &account.sent_tags, &[TAG_DRAFT.to_string()])?;
+
+    Ok(Some(path))
+}
@@ -0,0 +1,117 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use chrono::Utc;
+use glib;
+
+// Maildir info flags, see http://cr.yp.to/proto/maildir.html
+pub const FLAG_DRAFT: char = 'D';
+pub const FLAG_FLAGGED: char = 'F';
+pub const FLAG_PASSED: char = 'P';
+pub const FLAG_REPLIED: char = 'R';
+pub const FLAG_SEEN: char = 'S';
+pub const FLAG_TRASHED: char = 'T';
+
+// number of deliveries done by this process, used to keep file names unique
+static DELIVERIES: AtomicUsize = ATOMIC_USIZE_INIT;
+
+
+#[derive(Clone, Debug)]
+pub struct Maildir {
+    path: PathBuf
+}
+
+impl Maildir {
+
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let mut path = path.into();
+
+        // people tend to point at the 'cur' folder itself
+        let is_subfolder = match path.file_name().and_then(|name| name.to_str()) {
+            Some("cur") | Some("new") | Some("tmp") => true,
+            _ => false
+        };
+        if is_subfolder {
+            path.pop();
+        }
+
+        Maildir{
+            path
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Create the 'cur', 'new' and 'tmp' folders if they don't exist yet
+    pub fn create_dirs(&self) -> io::Result<()> {
+        for sub in &["cur", "new", "tmp"] {
+            fs::create_dir_all(self.path.join(sub))?;
+        }
+        Ok(())
+    }
+
+    /// Store a message in 'cur' with the given maildir flags.
+    pub fn store_cur(&self, content: &[u8], flags: &str) -> io::Result<PathBuf> {
+        let name = unique_name();
+        self.deliver(content, &name, &format!("cur/{}:2,{}", name, info_flags(flags)))
+    }
+
+    /// Store a message in 'new', as an unseen message
+    pub fn store_new(&self, content: &[u8]) -> io::Result<PathBuf> {
+        let name = unique_name();
+        self.deliver(content, &name, &format!("new/{}", name))
+    }
+
+    /// Write a message to 'tmp' first and then move it into place, so other
+    /// readers of the maildir never see a partial file.
+    fn deliver(&self, content: &[u8], name: &str, destination: &str) -> io::Result<PathBuf> {
+        self.create_dirs()?;
+
+        let tmp_path = self.path.join("tmp").join(name);
+        let dest_path = self.path.join(destination);
+
+        {
+            let mut file = OpenOptions::new()
+                                .write(true)
+                                .create_new(true)
+                                .open(&tmp_path)?;
+            file.write_all(content)?;
+            file.sync_all()?;
+        }
+
+        if let Err(err) = fs::rename(&tmp_path, &dest_path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+
+        Ok(dest_path)
+    }
+}
+
+
+/// Build a unique file name of the form `<time>.<pid>_<n>.<hostname>`
+fn unique_name() -> String {
+    let delivery = DELIVERIES.fetch_add(1, Ordering::SeqCst);
+
+    // '/' and ':' are not allowed in the host part
+    let hostname = glib::get_host_name()
+                        .map(|h| h.to_string())
+                        .unwrap_or_else(|| "localhost".to_string())
+                        .replace('/', "\\057")
+                        .replace(':', "\\072");
+
+    format!("{}.{}_{}.{}", Utc::now().timestamp(), process::id(), delivery, hostname)
+}
+
+/// Maildir flags must be in ASCII order and appear only once
+fn info_flags(flags: &str) -> String {
+    let mut chars: Vec<char> = flags.chars().filter(|c| c.is_ascii_alphabetic()).collect();
+    chars.sort();
+    chars.dedup();
+    chars.into_iter().collect()
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use notmuch;
 use crate::settings::Settings;
@@ -17,7 +17,7 @@ impl Manager {
     pub fn new(settings: &Rc<Settings>) -> Self {
 
         Manager{
-            notmuch_db_path: PathBuf::from(settings.notmuch_config.database.path.clone()),
+            notmuch_db_path: settings.notmuch_config.database_path(),
             database: RefCell::new(None)
         }
     }
@@ -41,4 +41,108 @@ impl Manager {
 
         Ok(self.database.borrow().clone().unwrap())
     }
+
+    /// Index a message file and apply the given tags to it.
+    pub fn add_message(&self, path: &Path, tags: &[String]) -> Result<(), notmuch::Error>{
+        self.index_message(path, tags, &[])
+    }
+
+    /// Index a message file, add and remove tags. The file may be another
+    /// copy of a message that is in the database already, like the sent
+    /// copy of a draft; its tags are changed then.
+    pub fn index_message(&self, path: &Path, add: &[String], remove: &[String]) -> Result<(), notmuch::Error>{
+
+        // notmuch allows only one writer, so don't keep this handle around
+        let database = notmuch::Database::open(&self.notmuch_db_path, notmuch::DatabaseMode::ReadWrite)?;
+        {
+            let message = match database.index_file(&path, None) {
+                Ok(message) => message,
+                // a duplicate Message-ID is indexed all the same, as one
+                // more file of the existing message
+                Err(err) => match database.find_message_by_filename(&path)? {
+                    Some(message) => message,
+                    None => return Err(err)
+                }
+            };
+
+            message.freeze()?;
+            for tag in remove {
+                message.remove_tag(tag)?;
+            }
+            for tag in add {
+                message.add_tag(tag)?;
+            }
+            message.thaw()?;
+        }
+        database.close()?;
+
+        // the cached handle won't see the new message until it is reopened
+        self.database.replace(None);
+        Ok(())
+    }
+
+    /// Remove a message file from the database.
+    pub fn remove_message(&self, path: &Path) -> Result<(), notmuch::Error>{
+
+        let database = notmuch::Database::open(&self.notmuch_db_path, notmuch::DatabaseMode::ReadWrite)?;
+        database.remove_message(&path)?;
+        database.close()?;
+
+        self.database.replace(None);
+        Ok(())
+    }
+
+    /// Remember the session key of an encrypted message, the way `notmuch
+    /// reindex --decrypt=true` does, so it can be read without the secret key.
+    pub fn add_session_key(&self, message_id: &str, session_key: &str) -> Result<(), notmuch::Error>{
+
+        let database = notmuch::Database::open(&self.notmuch_db_path, notmuch::DatabaseMode::ReadWrite)?;
+        if let Some(message) = database.find_message(message_id)? {
+            message.add_property("session-key", session_key)?;
+        }
+        database.close()?;
+
+        self.database.replace(None);
+        Ok(())
+    }
+
+    /// The files of all messages matching a query, oldest first
+    pub fn message_files(&self, query: &str) -> Result<Vec<PathBuf>, notmuch::Error>{
+
+        let database = self.get(notmuch::DatabaseMode::ReadOnly)?;
+        let query = database.create_query(query)?;
+        query.set_sort(notmuch::Sort::OldestFirst);
+
+        let files = query.search_messages()?
+                         .map(|message| message.filename())
+                         .collect();
+        Ok(files)
+    }
+
+    /// Add and remove tags on all messages matching a query. Returns the
+    /// number of messages changed.
+    pub fn tag(&self, query: &str, add: &[String], remove: &[String]) -> Result<usize, notmuch::Error>{
+
+        let database = notmuch::Database::open(&self.notmuch_db_path, notmuch::DatabaseMode::ReadWrite)?;
+        let mut count = 0;
+        {
+            let query = database.create_query(query)?;
+
+            for message in query.search_messages()? {
+                message.freeze()?;
+                for tag in remove {
+                    message.remove_tag(tag)?;
+                }
+                for tag in add {
+                    message.add_tag(tag)?;
+                }
+                message.thaw()?;
+                count += 1;
+            }
+        }
+        database.close()?;
+
+        self.database.replace(None);
+        Ok(count)
+    }
 }
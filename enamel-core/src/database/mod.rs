@@ -1,6 +1,10 @@
 
 pub mod manager;
+pub mod maildir;
 pub mod thread;
+pub mod watch;
 
 pub use self::manager::Manager;
+pub use self::maildir::Maildir;
 pub use self::thread::{Thread, ThreadExtra};
+pub use self::watch::watch;
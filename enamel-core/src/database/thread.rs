@@ -72,6 +72,7 @@ pub type Thread = ArcThread;
 // TODO: get from settings
 const TAG_UNREAD: &str = "unread";
 const TAG_ATTACHMENT: &str = "attachment";
+const TAG_FLAGGED: &str = "flagged";
 
 
 pub trait ThreadExtra<'d, 'q>
@@ -82,6 +83,7 @@ where
     fn has_tag(&self, tag: &str) -> bool;
     fn is_unread(&self) -> bool;
     fn has_attachment(&self) -> bool;
+    fn is_flagged(&self) -> bool;
 
     // {
     //     let tags:Vec<String> = thread.tags().collect();
@@ -113,6 +115,11 @@ impl<'d, 'q> ThreadExtra<'d, 'q> for notmuch::Thread<'d, 'q> where 'd: 'q {
     {
         self.has_tag(TAG_ATTACHMENT)
     }
+
+    fn is_flagged(&self) -> bool
+    {
+        self.has_tag(TAG_FLAGGED)
+    }
 }
 
 impl<'d, 'q> ThreadExtra<'d, 'q> for Rc<notmuch::Thread<'d, 'q>> where 'd: 'q {
@@ -132,6 +139,11 @@ impl<'d, 'q> ThreadExtra<'d, 'q> for Rc<notmuch::Thread<'d, 'q>> where 'd: 'q {
     {
         self.has_tag(TAG_ATTACHMENT)
     }
+
+    fn is_flagged(&self) -> bool
+    {
+        self.has_tag(TAG_FLAGGED)
+    }
 }
 
 // impl Iterator for Threads {
@@ -0,0 +1,56 @@
+use std::thread;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::*;
+
+use notmuch;
+
+/// How often the database revision is checked
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+
+/// The uuid and revision of the database. None while it can't be opened,
+/// like when it doesn't exist yet.
+fn revision(path: &Path) -> Option<(String, u64)> {
+    match notmuch::Database::open(&path, notmuch::DatabaseMode::ReadOnly) {
+        Ok(db) => {
+            let revision = db.revision();
+            Some((revision.uuid, revision.revision as u64))
+        },
+        Err(err) => {
+            debug!("Could not open database {:?} to check its revision: {:?}", path, err);
+            None
+        }
+    }
+}
+
+/// Call `callback` with the new revision whenever the database at `path`
+/// changes, by enamel or by other programs like `notmuch new`. The watching
+/// thread ends once `callback` returns false.
+pub fn watch<F>(path: &Path, mut callback: F) -> thread::JoinHandle<()>
+    where F: FnMut(u64) -> bool + Send + 'static
+{
+    let path: PathBuf = path.into();
+
+    thread::spawn(move || {
+        let mut seen = revision(&path);
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let current = match revision(&path) {
+                Some(current) => current,
+                None => continue
+            };
+            if seen.as_ref() == Some(&current) {
+                continue;
+            }
+
+            let changed = current.1;
+            seen = Some(current);
+            if !callback(changed) {
+                break;
+            }
+        }
+    })
+}
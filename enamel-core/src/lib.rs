@@ -1,2 +1,9 @@
 pub mod settings;
 pub mod database;
+pub mod compose;
+pub mod addressbook;
+pub mod mime;
+pub mod autocrypt;
+pub mod command;
+
+mod store;
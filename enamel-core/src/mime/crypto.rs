@@ -0,0 +1,377 @@
+use log::*;
+use chrono::{Local, TimeZone};
+use serde_derive::{Serialize, Deserialize};
+
+use glib::prelude::*;
+use gmime;
+use gmime::{ApplicationPkcs7MimeExt, CertificateExt, CertificateListExt, ContentTypeExt, DecryptResultExt,
+            MultipartEncryptedExt, MultipartSignedExt, ObjectExt, SignatureExt, SignatureListExt};
+
+
+/// OpenPGP (PGP/MIME) or S/MIME
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CryptoProtocol {
+    OpenPgp,
+    Smime,
+}
+
+impl Default for CryptoProtocol {
+    fn default() -> Self {
+        CryptoProtocol::OpenPgp
+    }
+}
+
+
+/// Signature and encryption state of a part
+#[derive(Clone, Debug, Default)]
+pub struct Crypto {
+    pub protocol: CryptoProtocol,
+
+    pub signed: bool,
+    pub encrypted: bool,
+
+    /// the part could be decrypted
+    pub decrypted: bool,
+
+    /// all signatures are good
+    pub verified: bool,
+
+    pub signatures: Vec<Signature>,
+
+    /// keys the part was encrypted to
+    pub recipients: Vec<Certificate>,
+
+    /// session key of a decrypted part, if it was not known before
+    pub session_key: Option<String>,
+
+    /// error from the crypto backend, e.g. a missing secret key
+    pub error: Option<String>,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureStatus {
+    Good,
+    Bad,
+
+    /// the signing key is not in the keyring
+    UnknownKey,
+
+    /// the signature could not be checked for another reason
+    Error
+}
+
+#[derive(Clone, Debug)]
+pub struct Signature {
+    pub status: SignatureStatus,
+
+    /// short names of everything that is wrong with the signature, e.g.
+    /// 'expired-key'
+    pub errors: Vec<&'static str>,
+
+    pub certificate: Certificate,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Certificate {
+    pub name: String,
+    pub email: String,
+    pub key_id: String,
+    pub fingerprint: String,
+    pub trust: String,
+
+    /// certificate issuer, for S/MIME
+    pub issuer: String,
+
+    /// validity period as unix timestamps, 0 if unknown
+    pub created: i64,
+    pub expires: i64,
+}
+
+
+impl Crypto {
+
+    /// A line per signature and recipient, for status banners
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = vec![];
+
+        if self.encrypted {
+            if self.decrypted {
+                for recipient in &self.recipients {
+                    lines.push(format!("Encrypted for: {}", recipient.describe()));
+                }
+            } else {
+                lines.push(format!("Could not decrypt: {}",
+                                   self.error.as_ref().map(|e| e.as_str()).unwrap_or("unknown error")));
+            }
+        }
+
+        for signature in &self.signatures {
+            let mut line = format!("{} signature from: {} [trust: {}]",
+                                   signature.status.describe(),
+                                   signature.certificate.describe(),
+                                   signature.certificate.trust);
+            if !signature.errors.is_empty() {
+                line.push_str(&format!(" [Error: {}]", signature.errors.join(",")));
+            }
+            lines.push(line);
+        }
+
+        if self.signed && self.signatures.is_empty() {
+            lines.push(format!("Could not verify: {}",
+                               self.error.as_ref().map(|e| e.as_str()).unwrap_or("no signatures")));
+        }
+
+        lines
+    }
+}
+
+impl SignatureStatus {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            SignatureStatus::Good => "Good",
+            SignatureStatus::Bad => "Bad",
+            SignatureStatus::UnknownKey => "Unknown key",
+            SignatureStatus::Error => "Erroneous"
+        }
+    }
+}
+
+impl Certificate {
+    pub fn describe(&self) -> String {
+        if self.issuer.is_empty() {
+            return format!("{} ({}) [0x{}]", self.name, self.email, self.key_id);
+        }
+
+        // S/MIME certificates are about the subject and who vouches for it
+        let mut description = format!("{} ({}) issued by {}", self.name, self.email, self.issuer);
+        if self.created > 0 || self.expires > 0 {
+            description.push_str(&format!(" [valid {} to {}]",
+                                          format_date(self.created),
+                                          format_date(self.expires)));
+        }
+        description
+    }
+}
+
+fn format_date(timestamp: i64) -> String {
+    if timestamp <= 0 {
+        return "-".to_string();
+    }
+    Local.timestamp(timestamp, 0).format("%Y-%m-%d").to_string()
+}
+
+
+/// Verify a multipart/signed part. The crypto context is picked by gmime from
+/// the protocol parameter; gmime registers its GnuPG context for OpenPGP and
+/// its GpgSM context for S/MIME on init.
+pub fn verify(part: &gmime::MultipartSigned) -> Crypto {
+    let mut crypto = Crypto{
+        protocol: signed_protocol(part),
+        signed: true,
+        ..Default::default()
+    };
+
+    match part.verify(gmime::VerifyFlags::NONE) {
+        Ok(signatures) => {
+            crypto.signatures = signatures_from_list(&signatures);
+            crypto.verified = !crypto.signatures.is_empty() &&
+                              crypto.signatures.iter().all(|s| s.status == SignatureStatus::Good);
+        },
+        Err(err) => {
+            debug!("crypto: verification failed: {}", err);
+            crypto.error = Some(err.to_string());
+        }
+    };
+
+    crypto
+}
+
+/// Decrypt a multipart/encrypted part, returning the decrypted content. A
+/// known session key is tried first, which doesn't need the secret key.
+pub fn decrypt(part: &gmime::MultipartEncrypted, session_keys: &[String]) -> (Option<gmime::Object>, Crypto) {
+    let mut crypto = Crypto{
+        encrypted: true,
+        ..Default::default()
+    };
+
+    for session_key in session_keys {
+        if let Ok((content, result)) = part.decrypt(gmime::DecryptFlags::NONE, Some(session_key.as_str())) {
+            apply_decrypt_result(&mut crypto, &result);
+            return (Some(content), crypto);
+        }
+    }
+
+    match part.decrypt(gmime::DecryptFlags::EXPORT_SESSION_KEY, None) {
+        Ok((content, result)) => {
+            apply_decrypt_result(&mut crypto, &result);
+            crypto.session_key = result.get_session_key().map(|k| k.to_string());
+            (Some(content), crypto)
+        },
+        Err(err) => {
+            debug!("crypto: decryption failed: {}", err);
+            crypto.error = Some(err.to_string());
+            (None, crypto)
+        }
+    }
+}
+
+/// Verify or decrypt an application/pkcs7-mime part, returning its content.
+/// Which one depends on the smime-type parameter.
+pub fn open_pkcs7(part: &gmime::ApplicationPkcs7Mime, session_keys: &[String]) -> (Option<gmime::Object>, Crypto) {
+    let mut crypto = Crypto{
+        protocol: CryptoProtocol::Smime,
+        ..Default::default()
+    };
+
+    match part.get_smime_type() {
+        gmime::SecureMimeType::SignedData => {
+            crypto.signed = true;
+
+            match part.verify(gmime::VerifyFlags::NONE) {
+                Ok((signatures, content)) => {
+                    crypto.signatures = signatures_from_list(&signatures);
+                    crypto.verified = !crypto.signatures.is_empty() &&
+                                      crypto.signatures.iter().all(|s| s.status == SignatureStatus::Good);
+                    (Some(content), crypto)
+                },
+                Err(err) => {
+                    debug!("crypto: s/mime verification failed: {}", err);
+                    crypto.error = Some(err.to_string());
+                    (None, crypto)
+                }
+            }
+        },
+        gmime::SecureMimeType::EnvelopedData => {
+            crypto.encrypted = true;
+
+            for session_key in session_keys {
+                if let Ok((content, result)) = part.decrypt(gmime::DecryptFlags::NONE, Some(session_key.as_str())) {
+                    apply_decrypt_result(&mut crypto, &result);
+                    return (Some(content), crypto);
+                }
+            }
+
+            match part.decrypt(gmime::DecryptFlags::EXPORT_SESSION_KEY, None) {
+                Ok((content, result)) => {
+                    apply_decrypt_result(&mut crypto, &result);
+                    crypto.session_key = result.get_session_key().map(|k| k.to_string());
+                    (Some(content), crypto)
+                },
+                Err(err) => {
+                    debug!("crypto: s/mime decryption failed: {}", err);
+                    crypto.error = Some(err.to_string());
+                    (None, crypto)
+                }
+            }
+        },
+        other => {
+            debug!("crypto: unsupported smime-type {:?}", other);
+            (None, crypto)
+        }
+    }
+}
+
+/// Tell OpenPGP from S/MIME by the protocol parameter
+fn signed_protocol(part: &gmime::MultipartSigned) -> CryptoProtocol {
+    let protocol = part.get_content_type()
+                       .and_then(|ct| ct.get_parameter("protocol"))
+                       .map(|p| p.to_lowercase())
+                       .unwrap_or_default();
+
+    if protocol.contains("pkcs7") {
+        CryptoProtocol::Smime
+    } else {
+        CryptoProtocol::OpenPgp
+    }
+}
+
+fn apply_decrypt_result(crypto: &mut Crypto, result: &gmime::DecryptResult) {
+    crypto.decrypted = true;
+
+    if let Some(recipients) = result.get_recipients() {
+        crypto.recipients = (0..recipients.length())
+                                .filter_map(|i| recipients.get_certificate(i))
+                                .map(|c| certificate(&c))
+                                .collect();
+    }
+
+    // signed and encrypted in one go, rather than nested in a multipart/signed
+    if let Some(signatures) = result.get_signatures() {
+        crypto.signatures = signatures_from_list(&signatures);
+        crypto.signed = !crypto.signatures.is_empty();
+        crypto.verified = crypto.signed &&
+                          crypto.signatures.iter().all(|s| s.status == SignatureStatus::Good);
+    }
+}
+
+pub(crate) fn signatures_from_list(list: &gmime::SignatureList) -> Vec<Signature> {
+    (0..list.length())
+        .filter_map(|i| list.get_signature(i))
+        .map(|s| signature(&s))
+        .collect()
+}
+
+fn signature(signature: &gmime::Signature) -> Signature {
+    let status = signature.get_status();
+
+    let mut errors = vec![];
+    let flags = [
+        (gmime::SignatureStatus::KEY_REVOKED, "revoked-key"),
+        (gmime::SignatureStatus::KEY_EXPIRED, "expired-key"),
+        (gmime::SignatureStatus::SIG_EXPIRED, "expired-sig"),
+        (gmime::SignatureStatus::KEY_MISSING, "key-missing"),
+        (gmime::SignatureStatus::CRL_MISSING, "crl-missing"),
+        (gmime::SignatureStatus::CRL_TOO_OLD, "crl-too-old"),
+        (gmime::SignatureStatus::BAD_POLICY, "bad-policy"),
+        (gmime::SignatureStatus::SYS_ERROR, "sys-error"),
+        (gmime::SignatureStatus::TOFU_CONFLICT, "tofu-conflict"),
+    ];
+    for (flag, name) in flags.iter() {
+        if status.contains(*flag) {
+            errors.push(*name);
+        }
+    }
+
+    let verdict = if status.contains(gmime::SignatureStatus::RED) {
+        SignatureStatus::Bad
+    } else if status.contains(gmime::SignatureStatus::KEY_MISSING) {
+        SignatureStatus::UnknownKey
+    } else if !errors.is_empty() {
+        SignatureStatus::Error
+    } else {
+        SignatureStatus::Good
+    };
+
+    Signature{
+        status: verdict,
+        errors,
+        certificate: signature.get_certificate()
+                              .map(|c| certificate(&c))
+                              .unwrap_or_default()
+    }
+}
+
+pub(crate) fn certificate(certificate: &gmime::Certificate) -> Certificate {
+    let trust = match certificate.get_trust() {
+        gmime::Trust::Unknown => "unknown",
+        gmime::Trust::Undefined => "undefined",
+        gmime::Trust::Never => "never",
+        gmime::Trust::Marginal => "marginal",
+        gmime::Trust::Full => "full",
+        gmime::Trust::Ultimate => "ultimate",
+        _ => "unknown"
+    };
+
+    Certificate{
+        name: certificate.get_name().map(|s| s.to_string()).unwrap_or_default(),
+        email: certificate.get_email().map(|s| s.to_string()).unwrap_or_default(),
+        key_id: certificate.get_key_id().map(|s| s.to_string()).unwrap_or_default(),
+        fingerprint: certificate.get_fingerprint().map(|s| s.to_string()).unwrap_or_default(),
+        trust: trust.to_string(),
+        issuer: certificate.get_issuer_name().map(|s| s.to_string()).unwrap_or_default(),
+        created: certificate.get_created(),
+        expires: certificate.get_expires()
+    }
+}
@@ -0,0 +1,216 @@
+use std::path::Path;
+
+use glib::prelude::*;
+use gmime;
+use gmime::{ApplicationPkcs7MimeExt, ContentTypeExt, MessageExt, MessagePartExt, MultipartExt, ObjectExt,
+            ParserExt, PartExt};
+
+pub mod crypto;
+pub mod render;
+
+use self::crypto::{Crypto, CryptoProtocol};
+
+
+/// How to deal with encrypted parts while walking a message
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    pub decrypt: bool,
+
+    /// session keys stored for the message, tried before the secret key
+    pub session_keys: Vec<String>,
+}
+
+
+/// A node in the MIME tree of a message, with encrypted parts replaced by
+/// their decrypted content. multipart/signed, multipart/encrypted and
+/// application/pkcs7-mime chunks carry the crypto state for the content
+/// below them.
+#[derive(Debug)]
+pub struct Chunk {
+    /// position in a depth first walk of the tree, unique within a message
+    pub id: usize,
+
+    pub object: gmime::Object,
+    pub mime_type: String,
+    pub content_id: Option<String>,
+    pub filename: Option<String>,
+
+    /// can be shown inline as text
+    pub viewable: bool,
+
+    /// the alternative to show, in a multipart/alternative
+    pub preferred: bool,
+    pub attachment: bool,
+
+    /// set on multipart/signed, multipart/encrypted and
+    /// application/pkcs7-mime chunks
+    pub crypto: Option<Crypto>,
+
+    pub kids: Vec<Chunk>,
+}
+
+
+/// A parsed message file
+pub struct MimeMessage {
+    pub message: gmime::Message,
+    pub root: Chunk,
+}
+
+
+impl MimeMessage {
+
+    pub fn open(path: &Path, options: &ParseOptions) -> Option<Self> {
+        let stream = gmime::StreamFile::open(&path.to_string_lossy(), &"r").ok()?;
+        let parser = gmime::Parser::new_with_stream(&stream);
+        let message = parser.construct_message(None)?;
+
+        Self::new(message, options)
+    }
+
+    pub fn new(message: gmime::Message, options: &ParseOptions) -> Option<Self> {
+        let body = message.get_mime_part()?;
+
+        let mut next_id = 0;
+        let root = Chunk::new(body, options, &mut next_id);
+
+        Some(MimeMessage{
+            message,
+            root
+        })
+    }
+
+    /// Session keys found while decrypting, which were not known before
+    pub fn new_session_keys(&self) -> Vec<String> {
+        let mut keys = vec![];
+        self.root.walk(&mut |chunk| {
+            if let Some(key) = chunk.crypto.as_ref().and_then(|c| c.session_key.as_ref()) {
+                keys.push(key.clone());
+            }
+        });
+        keys
+    }
+
+    pub fn attachments(&self) -> Vec<&Chunk> {
+        let mut attachments = vec![];
+        self.root.collect(&mut attachments, &|chunk| chunk.attachment);
+        attachments
+    }
+
+    /// Chunks that carry signature or encryption state
+    pub fn crypto_chunks(&self) -> Vec<&Chunk> {
+        let mut chunks = vec![];
+        self.root.collect(&mut chunks, &|chunk| chunk.crypto.is_some());
+        chunks
+    }
+}
+
+
+impl Chunk {
+
+    fn new(object: gmime::Object, options: &ParseOptions, next_id: &mut usize) -> Self {
+        let id = *next_id;
+        *next_id += 1;
+
+        let mime_type = object.get_content_type()
+                              .and_then(|ct| ct.get_mime_type())
+                              .map(|t| t.to_lowercase())
+                              .unwrap_or_else(|| "text/plain".to_string());
+
+        let mut chunk = Chunk{
+            id,
+            content_id: object.get_content_id().map(|s| s.to_string()),
+            filename: None,
+            viewable: false,
+            preferred: false,
+            attachment: false,
+            crypto: None,
+            kids: vec![],
+            mime_type,
+            object: object.clone()
+        };
+
+        if let Ok(encrypted) = object.clone().downcast::<gmime::MultipartEncrypted>() {
+            let decrypted = if options.decrypt {
+                crypto::decrypt(&encrypted, &options.session_keys)
+            } else {
+                (None, Crypto{ encrypted: true, ..Default::default() })
+            };
+
+            let (content, crypto) = decrypted;
+            if let Some(content) = content {
+                chunk.kids.push(Chunk::new(content, options, next_id));
+            }
+            chunk.crypto = Some(crypto);
+
+        } else if let Ok(signed) = object.clone().downcast::<gmime::MultipartSigned>() {
+            chunk.crypto = Some(crypto::verify(&signed));
+
+            if let Some(content) = signed.get_part(gmime::MULTIPART_SIGNED_CONTENT) {
+                chunk.kids.push(Chunk::new(content, options, next_id));
+            }
+
+        } else if let Ok(multipart) = object.clone().downcast::<gmime::Multipart>() {
+            chunk.kids = (0..multipart.get_count())
+                            .filter_map(|i| multipart.get_part(i))
+                            .map(|part| Chunk::new(part, options, next_id))
+                            .collect();
+
+            if chunk.mime_type == "multipart/alternative" {
+                // alternatives are in increasing order of preference
+                if let Some(kid) = chunk.kids.iter_mut().rev().find(|k| k.viewable || !k.kids.is_empty()) {
+                    kid.preferred = true;
+                }
+            }
+
+        } else if let Ok(pkcs7) = object.clone().downcast::<gmime::ApplicationPkcs7Mime>() {
+            // S/MIME opaque signed or enveloped data, which needs unpacking
+            // before anything can be shown
+            let (content, crypto) = if options.decrypt || pkcs7.get_smime_type() == gmime::SecureMimeType::SignedData {
+                crypto::open_pkcs7(&pkcs7, &options.session_keys)
+            } else {
+                (None, Crypto{ protocol: CryptoProtocol::Smime, encrypted: true, ..Default::default() })
+            };
+
+            if let Some(content) = content {
+                chunk.kids.push(Chunk::new(content, options, next_id));
+            }
+            chunk.crypto = Some(crypto);
+
+        } else if let Ok(message_part) = object.clone().downcast::<gmime::MessagePart>() {
+            // forwarded messages are shown as attachments, but their parts
+            // are still walked so they can be displayed
+            chunk.attachment = true;
+            if let Some(body) = message_part.get_message().and_then(|m| m.get_mime_part()) {
+                chunk.kids.push(Chunk::new(body, options, next_id));
+            }
+
+        } else if let Ok(part) = object.clone().downcast::<gmime::Part>() {
+            chunk.filename = part.get_filename().map(|s| s.to_string());
+            chunk.attachment = part.is_attachment();
+            chunk.viewable = !chunk.attachment && chunk.mime_type.starts_with("text/");
+        }
+
+        chunk
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.mime_type.starts_with("multipart/")
+    }
+
+    /// Visit this chunk and all chunks below it, depth first
+    pub fn walk<F: FnMut(&Chunk)>(&self, f: &mut F) {
+        f(self);
+        for kid in &self.kids {
+            kid.walk(f);
+        }
+    }
+
+    fn collect<'a, F: Fn(&Chunk) -> bool>(&'a self, chunks: &mut Vec<&'a Chunk>, filter: &F) {
+        if filter(self) {
+            chunks.push(self);
+        }
+        for kid in &self.kids {
+            kid.collect(chunks, filter);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Rendering of message bodies as plain text, for the terminal client and
+//! the thread view until it renders html parts itself.
+
+use glib::prelude::*;
+use gmime;
+use gmime::TextPartExt;
+
+use super::Chunk;
+
+
+/// A run of lines in a body that are shown, or folded, together
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Segment {
+    Text(Vec<String>),
+
+    /// lines starting with '>', with `depth` the quote level of the first
+    Quote{ depth: usize, lines: Vec<String> },
+
+    /// everything below the "-- " signature separator
+    Signature(Vec<String>),
+}
+
+impl Segment {
+
+    pub fn lines(&self) -> &[String] {
+        match self {
+            Segment::Text(lines) => lines,
+            Segment::Quote{ lines, .. } => lines,
+            Segment::Signature(lines) => lines
+        }
+    }
+
+    /// Quotes and signatures can be folded away
+    pub fn foldable(&self) -> bool {
+        match self {
+            Segment::Text(_) => false,
+            _ => true
+        }
+    }
+}
+
+
+/// The text of all inline parts below `chunk`. In a multipart/alternative
+/// text/plain is used if there is one, otherwise the HTML part is converted
+/// to text.
+pub fn body_text(chunk: &Chunk) -> Option<String> {
+    let mut parts = vec![];
+    collect_text(chunk, &mut parts);
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("\n"))
+    }
+}
+
+fn collect_text(chunk: &Chunk, parts: &mut Vec<String>) {
+    if chunk.attachment {
+        return;
+    }
+
+    if chunk.mime_type == "multipart/alternative" {
+        let kid = chunk.kids.iter().find(|kid| kid.viewable && kid.mime_type == "text/plain")
+                                   .or_else(|| chunk.kids.iter().find(|kid| kid.preferred));
+        if let Some(kid) = kid {
+            collect_text(kid, parts);
+        }
+        return;
+    }
+
+    if chunk.viewable {
+        let text = chunk.object.clone()
+                        .downcast::<gmime::TextPart>().ok()
+                        .and_then(|part| part.get_text())
+                        .map(|text| text.to_string());
+
+        if let Some(text) = text {
+            if chunk.mime_type == "text/html" {
+                parts.push(html_to_text(&text));
+            } else {
+                parts.push(text);
+            }
+        }
+        return;
+    }
+
+    for kid in &chunk.kids {
+        collect_text(kid, parts);
+    }
+}
+
+
+/// Split a plain text body into text, quotes and the signature
+pub fn segments(text: &str) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut lines = text.lines();
+
+    while let Some(line) = lines.next() {
+        if line == "-- " {
+            segments.push(Segment::Signature(lines.map(|l| l.to_string()).collect()));
+            break;
+        }
+
+        let depth = quote_depth(line);
+        match segments.last_mut() {
+            Some(Segment::Quote{ lines, .. }) if depth > 0 => lines.push(line.to_string()),
+            Some(Segment::Text(lines)) if depth == 0 => lines.push(line.to_string()),
+            _ if depth > 0 => segments.push(Segment::Quote{ depth, lines: vec![line.to_string()] }),
+            _ => segments.push(Segment::Text(vec![line.to_string()]))
+        }
+    }
+
+    segments
+}
+
+fn quote_depth(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| *c == '>' || *c == ' ')
+        .filter(|c| *c == '>')
+        .count()
+}
+
+
+/// A rough conversion of HTML to text: tags are dropped, block elements
+/// start a new line and entities are decoded.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+
+    // content of these is never shown
+    let mut skip_until: Option<String> = None;
+
+    while !rest.is_empty() {
+        let lt = match rest.find('<') {
+            Some(lt) => lt,
+            None => {
+                if skip_until.is_none() {
+                    push_text(&mut text, rest);
+                }
+                break;
+            }
+        };
+
+        if skip_until.is_none() {
+            push_text(&mut text, &rest[..lt]);
+        }
+
+        let gt = match rest[lt..].find('>') {
+            Some(gt) => lt + gt,
+            None => break
+        };
+
+        let tag = rest[lt + 1..gt].trim();
+        rest = &rest[gt + 1..];
+
+        if tag.starts_with("!--") && !tag.ends_with("--") {
+            // a comment containing '>'
+            rest = rest.find("-->").map(|end| &rest[end + 3..]).unwrap_or("");
+            continue;
+        }
+
+        let closing = tag.starts_with('/');
+        let name: String = tag.trim_start_matches('/')
+                              .chars()
+                              .take_while(|c| c.is_ascii_alphanumeric())
+                              .collect::<String>()
+                              .to_lowercase();
+
+        if let Some(until) = &skip_until {
+            if closing && *until == name {
+                skip_until = None;
+            }
+            continue;
+        }
+
+        match name.as_str() {
+            "script" | "style" | "head" if !closing => skip_until = Some(name.clone()),
+            "br" => text.push('\n'),
+            "li" if !closing => {
+                new_line(&mut text);
+                text.push_str("* ");
+            },
+            "p" | "div" | "tr" | "table" | "ul" | "ol" | "blockquote" | "pre" |
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                new_line(&mut text);
+                if name == "p" && closing {
+                    text.push('\n');
+                }
+            },
+            "td" | "th" if closing => text.push(' '),
+            _ => ()
+        }
+    }
+
+    let lines: Vec<&str> = text.lines().map(|line| line.trim_end()).collect();
+    lines.join("\n").trim().to_string()
+}
+
+/// Append text from between tags, collapsing whitespace like a browser
+fn push_text(text: &mut String, html: &str) {
+    let decoded = decode_entities(html);
+
+    for c in decoded.chars() {
+        if c.is_whitespace() && c != '\u{a0}' {
+            if !text.is_empty() && !text.ends_with(' ') && !text.ends_with('\n') {
+                text.push(' ');
+            }
+        } else if c == '\u{a0}' {
+            text.push(' ');
+        } else {
+            text.push(c);
+        }
+    }
+}
+
+fn new_line(text: &mut String) {
+    while text.ends_with(' ') {
+        text.pop();
+    }
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+fn decode_entities(html: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = html;
+
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        let entity = rest.find(';')
+                         .filter(|semi| *semi <= 10)
+                         .map(|semi| (&rest[1..semi], semi));
+
+        let c = entity.and_then(|(name, _)| match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ if name.starts_with("#x") || name.starts_with("#X") =>
+                u32::from_str_radix(&name[2..], 16).ok().and_then(std::char::from_u32),
+            _ if name.starts_with('#') =>
+                name[1..].parse().ok().and_then(std::char::from_u32),
+            _ => None
+        });
+
+        match (c, entity) {
+            (Some(c), Some((_, semi))) => {
+                decoded.push(c);
+                rest = &rest[semi + 1..];
+            },
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+
+#[test]
+fn test_segments() {
+    let text = "Hi,\n\n> you wrote\n>> earlier\nreply\n-- \nme\nhttps://example.org";
+
+    assert_eq!(segments(text), vec![
+        Segment::Text(vec!["Hi,".to_string(), "".to_string()]),
+        Segment::Quote{ depth: 1, lines: vec!["> you wrote".to_string(), ">> earlier".to_string()] },
+        Segment::Text(vec!["reply".to_string()]),
+        Segment::Signature(vec!["me".to_string(), "https://example.org".to_string()]),
+    ]);
+
+    // without the trailing space it is just a line, e.g. in a diff
+    assert_eq!(segments("a\n--\nb"), vec![
+        Segment::Text(vec!["a".to_string(), "--".to_string(), "b".to_string()]),
+    ]);
+}
+
+#[test]
+fn test_html_to_text() {
+    let html = "<html><head><style>p { color: red }</style></head>\
+                <body><p>Hello&nbsp;<b>world</b> &amp; all</p>\
+                <ul><li>one</li><li>two</li></ul>line<br>break &#8364;</body></html>";
+
+    assert_eq!(html_to_text(html), "Hello world & all\n\n* one\n* two\nline\nbreak \u{20ac}");
+}
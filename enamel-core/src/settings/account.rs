@@ -0,0 +1,276 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+
+use log::*;
+use regex::RegexBuilder;
+use serde_derive::{Serialize, Deserialize};
+use shellexpand;
+
+use crate::mime::crypto::CryptoProtocol;
+
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AccountConfig {
+    #[serde(default)]
+    pub default: bool,
+    pub name: String,
+    pub email: String,
+
+    /// Other addresses that belong to this account
+    #[serde(default)]
+    pub aliases: Vec<String>,
+
+    /// Addresses matching this (case insensitive) regex also belong to this
+    /// account, e.g. "me\+.*@example\.com"
+    #[serde(default)]
+    pub alias_regex: Option<String>,
+
+    #[serde(default)]
+    pub reply_to: Option<String>,
+
+    #[serde(default)]
+    pub signature: Option<SignatureConfig>,
+
+    #[serde(default)]
+    pub send: SendConfig,
+
+    /// Maildir folder to store sent mail in. Relative paths are taken
+    /// relative to the notmuch database path.
+    #[serde(default)]
+    pub save_sent_to: Option<String>,
+
+    /// Tags to apply to sent mail when it is added to the database
+    #[serde(default = "default_account_sent_tags")]
+    pub sent_tags: Vec<String>,
+
+    /// Maildir folder to store drafts in. Relative paths are taken relative
+    /// to the notmuch database path.
+    #[serde(default)]
+    pub save_drafts_to: Option<String>,
+
+    /// Key id used for signing mail from this account. Without it, gpg
+    /// picks a key for the account address.
+    #[serde(default)]
+    pub gpg_key: Option<String>,
+
+    /// Certificate id (fingerprint) used for S/MIME signing. Without it,
+    /// gpgsm picks a certificate for the account address.
+    #[serde(default)]
+    pub smime_key: Option<String>,
+
+    /// Sign and encrypt with OpenPGP or S/MIME
+    #[serde(default)]
+    pub crypto_protocol: CryptoProtocol,
+
+    /// Sign outgoing mail by default
+    #[serde(default)]
+    pub sign: bool,
+
+    /// When to encrypt outgoing mail by default
+    #[serde(default)]
+    pub encrypt: EncryptPolicy,
+
+    /// Publish the OpenPGP key in an Autocrypt header. Needs `gpg_key`.
+    #[serde(default = "default_account_autocrypt")]
+    pub autocrypt: bool,
+}
+
+impl AccountConfig {
+
+    /// The full address, as used in the From header
+    pub fn mailbox(&self) -> String {
+        format!("{} <{}>", self.name, self.email)
+    }
+
+    /// The key or certificate to sign with for the account's protocol
+    pub fn signing_key(&self) -> String {
+        let key = match self.crypto_protocol {
+            CryptoProtocol::OpenPgp => self.gpg_key.as_ref(),
+            CryptoProtocol::Smime => self.smime_key.as_ref()
+        };
+        key.cloned().unwrap_or_else(|| self.email.clone())
+    }
+
+    /// Check whether an address, with or without display name, belongs to
+    /// this account
+    pub fn matches(&self, address: &str) -> bool {
+        let address = bare_address(address);
+
+        if address.eq_ignore_ascii_case(&self.email) {
+            return true;
+        }
+        if self.aliases.iter().any(|alias| address.eq_ignore_ascii_case(alias)) {
+            return true;
+        }
+
+        match self.alias_regex.as_ref() {
+            Some(re) => build_alias_regex(re).map(|re| re.is_match(address)).unwrap_or(false),
+            None => false
+        }
+    }
+}
+
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SignatureConfig {
+    /// Signature text. Ignored when `file` is set.
+    #[serde(default)]
+    pub text: Option<String>,
+
+    #[serde(default)]
+    pub file: Option<String>,
+
+    /// The signature is html instead of plain text
+    #[serde(default)]
+    pub html: bool,
+
+    /// Put the "-- " separator line in front of the signature
+    #[serde(default = "default_signature_separator")]
+    pub separator: bool,
+}
+
+impl SignatureConfig {
+
+    /// Get the signature contents, reading it from disk if needed
+    pub fn contents(&self) -> Option<String> {
+        match self.file.as_ref() {
+            Some(file) => {
+                let expanded = shellexpand::tilde(file).into_owned();
+                let mut contents = String::new();
+
+                match File::open(Path::new(&expanded)).and_then(|mut f| f.read_to_string(&mut contents)) {
+                    Ok(_) => Some(contents),
+                    Err(err) => {
+                        warn!("Could not read signature file {:?}: {:?}", expanded, err);
+                        None
+                    }
+                }
+            },
+            None => self.text.clone()
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptPolicy {
+    Never,
+
+    /// Encrypt when there is a key for every recipient
+    Auto,
+
+    Always,
+}
+
+impl Default for EncryptPolicy {
+    fn default() -> Self {
+        EncryptPolicy::Never
+    }
+}
+
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum SendConfig {
+    /// Pipe the message into a sendmail compatible command, which reads the
+    /// recipients from the headers like `sendmail -t` does
+    Sendmail {
+        #[serde(default = "default_sendmail_command")]
+        command: String
+    },
+
+    /// Queue the message in a maildir, for an external program to send
+    Outbox {
+        folder: String
+    },
+}
+
+impl Default for SendConfig {
+    fn default() -> Self {
+        SendConfig::Sendmail{
+            command: default_sendmail_command()
+        }
+    }
+}
+
+
+/// Check the accounts for problems. Returns a description of each problem.
+pub fn validate_accounts(accounts: &BTreeMap<String, AccountConfig>) -> Vec<String> {
+    let mut problems = vec![];
+
+    if !accounts.is_empty() {
+        let defaults: Vec<&String> = accounts.iter()
+                                             .filter(|(_, account)| account.default)
+                                             .map(|(key, _)| key)
+                                             .collect();
+        match defaults.len() {
+            0 => problems.push("No account is marked as default".to_string()),
+            1 => (),
+            _ => problems.push(format!("Only one account can be default, found: {}", join(&defaults)))
+        }
+    }
+
+    let mut owners: HashMap<String, &String> = HashMap::new();
+    for (key, account) in accounts {
+        for address in Some(&account.email).into_iter().chain(account.aliases.iter()) {
+            let address = address.to_lowercase();
+
+            match owners.get(&address) {
+                Some(owner) if *owner == key => problems.push(format!("Account '{}' lists {} twice", key, address)),
+                Some(owner) => problems.push(format!("Address {} is used by both '{}' and '{}'", address, owner, key)),
+                None => { owners.insert(address, key); }
+            }
+        }
+
+        if let Some(re) = account.alias_regex.as_ref() {
+            if let Err(err) = build_alias_regex(re) {
+                problems.push(format!("Account '{}' has an invalid alias_regex: {}", key, err));
+            }
+        }
+
+        if account.email.is_empty() {
+            problems.push(format!("Account '{}' has no email address", key));
+        }
+    }
+
+    problems
+}
+
+
+fn build_alias_regex(re: &str) -> Result<regex::Regex, regex::Error> {
+    // match the whole address, not just a part of it
+    RegexBuilder::new(&format!("^(?:{})$", re))
+        .case_insensitive(true)
+        .build()
+}
+
+/// Strip the display name from an address: "Foo <foo@bar>" -> "foo@bar"
+pub fn bare_address(address: &str) -> &str {
+    match (address.rfind('<'), address.rfind('>')) {
+        (Some(start), Some(end)) if start < end => address[start + 1..end].trim(),
+        _ => address.trim()
+    }
+}
+
+fn join(keys: &[&String]) -> String {
+    keys.iter().map(|k| k.as_str()).collect::<Vec<&str>>().join(", ")
+}
+
+fn default_account_autocrypt() -> bool {
+    true
+}
+
+fn default_account_sent_tags() -> Vec<String> {
+    vec!["sent".to_string()]
+}
+
+fn default_signature_separator() -> bool {
+    true
+}
+
+fn default_sendmail_command() -> String {
+    "sendmail -i -t".to_string()
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::fmt;
+use std::path::Path;
+use toml_edit::{Document, Item, Value, Table, Array, ArrayOfTables, value};
+
+use crate::settings::Error;
+use crate::settings::enamel::{Config, SavedSearch};
+
+
+/// The config file as it is written, for changing a few values without
+/// losing comments, formatting and keys enamel does not know about.
+#[derive(Debug)]
+pub struct ConfigDocument {
+    document: Document
+}
+
+
+impl ConfigDocument {
+
+    /// Read the config file. A missing file is an empty document.
+    pub fn load(location: &Path) -> Result<Self, Error> {
+        let contents = match fs::read_to_string(location) {
+            Ok(contents) => contents,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into())
+        };
+
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        Ok(ConfigDocument {
+            document: contents.parse::<Document>()?
+        })
+    }
+
+    /// Set the value at `path`, like `&["crypto", "decrypt"]`, creating the
+    /// tables on the way
+    pub fn set<V: Into<Value>>(&mut self, path: &[&str], new_value: V) {
+        let (last, parents) = match path.split_last() {
+            Some(split) => split,
+            None => return
+        };
+
+        let mut item = &mut self.document.root;
+        for key in parents {
+            item = &mut item[*key];
+        }
+        item[*last] = value(new_value);
+    }
+
+    /// Set the value at `path` only when it differs from the `current` one,
+    /// so options nobody touched keep following their defaults
+    pub fn set_if_changed<V: Into<Value> + PartialEq>(&mut self, path: &[&str], current: V, new_value: V) {
+        if current != new_value {
+            self.set(path, new_value);
+        }
+    }
+
+    /// Set a list of strings, like the hidden tags
+    pub fn set_list(&mut self, path: &[&str], values: &[String]) {
+        let list: Value = values.iter().map(|value| value.as_str()).collect();
+        self.set(path, list);
+    }
+
+    /// Remove the key or table at `path`, so its default applies again
+    pub fn remove(&mut self, path: &[&str]) {
+        let (last, parents) = match path.split_last() {
+            Some(split) => split,
+            None => return
+        };
+
+        let mut item = &mut self.document.root;
+        for key in parents {
+            if !item.as_table().map(|table| table.contains_key(key)).unwrap_or(false) {
+                return;
+            }
+            item = &mut item[*key];
+        }
+
+        if let Some(table) = item.as_table_mut() {
+            table.remove(last);
+        }
+    }
+
+    /// Replace the saved searches. Existing `[[searches]]` tables are changed
+    /// in place, so comments between them survive.
+    pub fn set_searches(&mut self, searches: &[SavedSearch]) {
+        let root = &mut self.document.root;
+
+        // without any searches, the defaults would come back
+        if searches.is_empty() {
+            root["searches"] = value(Array::default());
+            return;
+        }
+
+        if !root["searches"].is_array_of_tables() {
+            root["searches"] = Item::ArrayOfTables(ArrayOfTables::new());
+        }
+        let tables = root["searches"].as_array_of_tables_mut().unwrap();
+
+        for (i, search) in searches.iter().enumerate() {
+            let table = match tables.get_mut(i) {
+                Some(table) => table,
+                None => tables.append(Table::new())
+            };
+            table["name"] = value(search.name.as_str());
+            table["query"] = value(search.query.as_str());
+        }
+
+        while tables.len() > searches.len() {
+            let last = tables.len() - 1;
+            tables.remove(last);
+        }
+    }
+
+    /// The config this document describes, if it is a valid one
+    pub fn config(&self) -> Result<Config, Error> {
+        Config::parse(&self.document.to_string())
+    }
+
+    /// Write the document to `location`. Nothing is written when the result
+    /// would not load again.
+    pub fn store(&self, location: &Path) -> Result<(), Error> {
+        self.config()?;
+
+        if let Some(parent) = location.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(location, self.document.to_string())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for ConfigDocument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.document)
+    }
+}
+
+
+#[test]
+fn test_edit_keeps_comments() {
+    let mut document = ConfigDocument::parse(r#"# my mail setup
+debug = false
+
+[crypto]
+# I like to see everything
+decrypt = true
+unknown_option = "kept"
+
+[[searches]]
+name = "Inbox"
+query = "tag:inbox"
+
+# work stuff
+[[searches]]
+name = "Work"
+query = "tag:work"
+
+[[searches]]
+name = "Old"
+query = "date:..2010"
+"#).unwrap();
+
+    document.set(&["crypto", "decrypt"], false);
+    document.set(&["poll", "interval"], 60i64);
+    document.set_searches(&[SavedSearch{name: "Inbox".to_string(), query: "tag:inbox".to_string()},
+                            SavedSearch{name: "Work".to_string(), query: "tag:work and tag:unread".to_string()}]);
+
+    let contents = document.to_string();
+    assert!(contents.contains("# my mail setup"));
+    assert!(contents.contains("# I like to see everything"));
+    assert!(contents.contains("# work stuff"));
+    assert!(contents.contains("unknown_option = \"kept\""));
+    assert!(!contents.contains("Old"));
+
+    let config = document.config().unwrap();
+    assert!(!config.crypto.decrypt);
+    assert_eq!(config.poll.interval, 60);
+    assert_eq!(config.searches.len(), 2);
+    assert_eq!(config.searches[1].query, "tag:work and tag:unread");
+
+    document.remove(&["poll", "interval"]);
+    document.set_searches(&[]);
+    let config = document.config().unwrap();
+    assert_eq!(config.poll.interval, 300);
+    assert!(config.searches.is_empty());
+}
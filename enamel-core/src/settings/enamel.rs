@@ -6,6 +6,11 @@ use std::collections::BTreeMap;
 use toml;
 use serde_derive::{Serialize, Deserialize};
 
+use super::Error;
+use super::account::{AccountConfig, validate_accounts};
+use super::keybindings::{Keybindings, KeybindingsConfig};
+use super::tags::{TagDisplayConfig, validate_tags};
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Config {
 
@@ -18,38 +23,78 @@ pub struct Config {
     #[serde(default)]
     pub notmuch: NotMuchConfig,
 
+    #[serde(default)]
+    pub compose: ComposeConfig,
+
+    #[serde(default)]
+    pub addressbook: AddressBookConfig,
+
+    #[serde(default)]
+    pub crypto: CryptoConfig,
+
+    #[serde(default)]
+    pub ui: UiConfig,
+
+    #[serde(default)]
+    pub poll: PollConfig,
+
+    #[serde(default)]
+    pub tags: TagsConfig,
+
+    /// Searches listed above the tags
+    #[serde(default = "default_searches")]
+    pub searches: Vec<SavedSearch>,
+
     #[serde(default)]
     pub accounts: BTreeMap<String, AccountConfig>,
-    //shortcuts: ShortcutConfig,
+
+    /// Keys for named actions, applied over the defaults
+    #[serde(default)]
+    pub keybindings: KeybindingsConfig,
 
 }
 
 impl Config{
-    pub fn load(location: &Path) -> Self {
+    pub fn load(location: &Path) -> Result<Self, Error> {
         let mut conf_contents = String::new();
 
         match File::open(&location) {
             Ok(mut file) => {
-                file.read_to_string(&mut conf_contents);
+                file.read_to_string(&mut conf_contents)?;
             },
             Err(_err) => {
                 conf_contents = "".to_string();
             },
         };
 
+        Self::parse(&conf_contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let config: Config = toml::from_str(contents)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        let problems = validate_accounts(&self.accounts);
+
+        if !problems.is_empty() {
+            return Err(Error::InvalidAccounts(problems));
+        }
+
+        let problems = validate_tags(&self.tags);
+        if !problems.is_empty() {
+            return Err(Error::InvalidTags(problems));
+        }
 
-        toml::from_str(&conf_contents).unwrap()
+        self.keybindings().map(|_| ())
     }
 
-    // #[serde(skip_serializing)]
-    // pub fn store(self: &Self, location: &Path) -> Result<(), String> {
-    //     let mut outfile = File::create(location).unwrap();
-    //     outfile.write_all(toml::to_string(&self).unwrap().as_bytes());
-    //     outfile.sync_all();
-    //
-    //     return Ok(());
-    //
-    // }
+    pub fn keybindings(&self) -> Result<Keybindings, Error> {
+        Keybindings::new(&self.keybindings).map_err(Error::InvalidKeybindings)
+    }
 
 }
 
@@ -71,39 +116,225 @@ impl Default for DebugConfig {
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct NotMuchConfig {
-    #[serde(default = "default_notmuch_config_path")]
-    pub path: String,
+    /// The notmuch config file. Found the way notmuch finds it when not set.
+    #[serde(default)]
+    pub path: Option<String>,
 }
 
 
 impl Default for NotMuchConfig {
     fn default() -> Self {
         NotMuchConfig{
-            path: default_notmuch_config_path()
+            path: None
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ComposeConfig {
+    /// Seconds between draft autosaves while composing. 0 disables autosave.
+    #[serde(default = "default_compose_autosave_interval")]
+    pub autosave_interval: u32,
+}
+
+impl Default for ComposeConfig {
+    fn default() -> Self {
+        ComposeConfig{
+            autosave_interval: default_compose_autosave_interval()
         }
     }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct AccountConfig {
-    pub default: bool,
+pub struct AddressBookConfig {
+    /// vCard (.vcf) or abook file to merge into the address book
+    #[serde(default)]
+    pub contacts: Option<String>,
+
+    /// Headers addresses are collected from
+    #[serde(default = "default_addressbook_headers")]
+    pub headers: Vec<String>,
+}
+
+impl Default for AddressBookConfig {
+    fn default() -> Self {
+        AddressBookConfig{
+            contacts: None,
+            headers: default_addressbook_headers()
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SavedSearch {
     pub name: String,
-    pub email: String,
+
+    /// notmuch query
+    pub query: String,
+}
+
+impl SavedSearch {
+    fn new(name: &str, query: &str) -> Self {
+        SavedSearch{
+            name: name.to_string(),
+            query: query.to_string()
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CryptoConfig {
+    /// Decrypt encrypted messages when showing them
+    #[serde(default = "default_crypto_decrypt")]
+    pub decrypt: bool,
+
+    /// Store the session keys of decrypted messages in the notmuch database,
+    /// so they can be shown later without the secret key
+    #[serde(default)]
+    pub index_session_keys: bool,
+
+    /// Collect keys from Autocrypt headers of incoming mail
+    #[serde(default = "default_crypto_autocrypt")]
+    pub autocrypt: bool,
+}
+
+impl Default for CryptoConfig {
+    fn default() -> Self {
+        CryptoConfig{
+            decrypt: default_crypto_decrypt(),
+            index_session_keys: false,
+            autocrypt: default_crypto_autocrypt()
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct UiConfig {
+    /// Open the searches and threads of the last session on start
+    #[serde(default = "default_ui_restore_pages")]
+    pub restore_pages: bool,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        UiConfig{
+            restore_pages: default_ui_restore_pages()
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PollConfig {
+    /// Command that fetches new mail, like `mbsync -a && notmuch new`
+    #[serde(default)]
+    pub command: Option<String>,
+
+    /// Seconds between runs of the command. 0 disables polling.
+    #[serde(default = "default_poll_interval")]
+    pub interval: u32,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        PollConfig{
+            command: None,
+            interval: default_poll_interval()
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct TagsConfig {
+    /// Tag backgrounds are picked between the lower and upper colour
+    #[serde(default = "default_tags_upper_color")]
+    pub upper_color: String,
+
+    #[serde(default = "default_tags_lower_color")]
+    pub lower_color: String,
+
+    /// Opacity of the tag backgrounds
+    #[serde(default = "default_tags_alpha")]
+    pub alpha: f64,
+
+    /// Tags left out of the thread list, like those shown as an icon
+    #[serde(default = "default_tags_hidden")]
+    pub hidden: Vec<String>,
+
+    /// Tags listed first in the tag list, in this order
+    #[serde(default)]
+    pub pinned: Vec<String>,
+
+    /// Colours, icons and aliases of single tags, by tag name or `/regex/`
+    #[serde(default)]
+    pub display: BTreeMap<String, TagDisplayConfig>,
+}
+
+impl Default for TagsConfig {
+    fn default() -> Self {
+        TagsConfig{
+            upper_color: default_tags_upper_color(),
+            lower_color: default_tags_lower_color(),
+            alpha: default_tags_alpha(),
+            hidden: default_tags_hidden(),
+            pinned: vec![],
+            display: BTreeMap::new()
+        }
+    }
 }
 
+fn default_searches() -> Vec<SavedSearch> {
+    vec![SavedSearch::new("Inbox", "tag:inbox"),
+         SavedSearch::new("Unread", "tag:unread"),
+         SavedSearch::new("Flagged", "tag:flagged"),
+         SavedSearch::new("Drafts", "tag:draft"),
+         SavedSearch::new("Sent", "tag:sent")]
+}
 
 fn default_version() -> i16 {
     1
 }
 
-fn default_notmuch_config_path() -> String {
-    let env_var = std::env::var("NOTMUCH_CONFIG");
-    if env_var.is_ok(){
-        return env_var.unwrap();
-    };
-    "~/.notmuch-config".to_string()
-}
 
 fn default_debug_dryrun_sending() -> bool {
     false
 }
+
+fn default_compose_autosave_interval() -> u32 {
+    60
+}
+
+fn default_crypto_decrypt() -> bool {
+    true
+}
+
+fn default_crypto_autocrypt() -> bool {
+    true
+}
+
+fn default_tags_upper_color() -> String {
+    "#e5e5e5".to_string()
+}
+
+fn default_tags_lower_color() -> String {
+    "#333333".to_string()
+}
+
+fn default_tags_alpha() -> f64 {
+    0.5
+}
+
+fn default_tags_hidden() -> Vec<String> {
+    vec!["attachment".to_string(), "flagged".to_string(), "unread".to_string()]
+}
+
+fn default_poll_interval() -> u32 {
+    300
+}
+
+fn default_ui_restore_pages() -> bool {
+    true
+}
+
+fn default_addressbook_headers() -> Vec<String> {
+    vec!["From".to_string(), "To".to_string(), "Cc".to_string()]
+}
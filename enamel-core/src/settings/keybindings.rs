@@ -0,0 +1,482 @@
+use std::fmt;
+use std::collections::BTreeMap;
+
+use serde_derive::{Serialize, Deserialize};
+
+
+/// Where an action applies. Bindings in a specific context shadow global
+/// ones, so `j` can move to the next thread in the thread list and scroll
+/// in the thread view.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Context {
+    Global,
+    ThreadList,
+    ThreadView,
+    Compose,
+}
+
+impl Context {
+    pub fn title(self) -> &'static str {
+        match self {
+            Context::Global => "General",
+            Context::ThreadList => "Thread list",
+            Context::ThreadView => "Thread view",
+            Context::Compose => "Composer"
+        }
+    }
+}
+
+
+/// An action that can be bound to keys
+#[derive(Clone, Copy, Debug)]
+pub struct ActionInfo {
+    pub name: &'static str,
+    pub context: Context,
+    pub description: &'static str,
+    pub default_keys: &'static [&'static str],
+}
+
+macro_rules! actions {
+    ($($context:ident $name:expr, $description:expr, [$($key:expr),*];)*) => {
+        /// All actions that can be bound, with their default keys
+        pub const ACTIONS: &[ActionInfo] = &[
+            $(ActionInfo{
+                name: $name,
+                context: Context::$context,
+                description: $description,
+                default_keys: &[$($key),*]
+            }),*
+        ];
+    }
+}
+
+actions! {
+    Global "quit", "Quit", ["q", "C-q"];
+    Global "menu", "Open the main menu", ["F10"];
+    Global "help", "Show keyboard shortcuts", ["?", "C-?"];
+    Global "compose", "Compose a new message", ["m", "C-n"];
+    Global "search", "Start a new search", ["C-f"];
+    Global "command", "Enter a command", [":", "C-S-p"];
+    Global "focus-next", "Focus the next pane", ["Tab"];
+    Global "focus-previous", "Focus the previous pane", ["S-Tab"];
+    Global "down", "Move down", ["j", "Down"];
+    Global "up", "Move up", ["k", "Up"];
+    Global "page-down", "Move down a page", ["PageDown", "C-d"];
+    Global "page-up", "Move up a page", ["PageUp", "C-u"];
+    Global "open", "Open the selected item", ["Enter", "l", "Right"];
+    Global "back", "Go back", ["Esc", "h", "Left"];
+    Global "next-page", "Switch to the next page", ["C-PageDown"];
+    Global "previous-page", "Switch to the previous page", ["C-PageUp"];
+    Global "close-page", "Close the page", ["C-w"];
+    Global "preferences", "Edit the preferences", ["C-,"];
+
+    ThreadList "next-thread", "Select the next thread", ["j", "Down"];
+    ThreadList "previous-thread", "Select the previous thread", ["k", "Up"];
+    ThreadList "first-thread", "Select the first thread", ["g g", "Home"];
+    ThreadList "last-thread", "Select the last thread", ["G", "End"];
+    ThreadList "filter", "Narrow down the thread list", ["/"];
+    ThreadList "archive", "Archive the thread", ["a"];
+    ThreadList "toggle-unread", "Toggle the unread tag", ["N"];
+    ThreadList "toggle-flagged", "Toggle the flagged tag", ["*"];
+    ThreadList "delete", "Delete the thread", ["d"];
+    ThreadList "reply", "Reply to the newest message", ["r"];
+    ThreadList "reply-all", "Reply to all recipients", ["R"];
+    ThreadList "forward", "Forward the thread", ["f"];
+    ThreadList "toggle-mark", "Mark or unmark the thread", ["t"];
+    ThreadList "toggle-mark-all", "Mark all threads, or unmark them", ["T"];
+    ThreadList "mark-read", "Remove the unread tag", ["C-r"];
+    ThreadList "save", "Save the messages to a folder", ["s"];
+    ThreadList "forward-digest", "Forward the messages as one digest", ["F"];
+
+    ThreadView "next-message", "Focus the next message", ["J"];
+    ThreadView "previous-message", "Focus the previous message", ["K"];
+    ThreadView "scroll-down", "Scroll down", ["j", "Down"];
+    ThreadView "scroll-up", "Scroll up", ["k", "Up"];
+    ThreadView "toggle-expand", "Expand or collapse the message", ["Enter"];
+    ThreadView "toggle-quotes", "Show or hide quotes and signature", ["z"];
+    ThreadView "find", "Search in the message", ["/"];
+    ThreadView "find-next", "Go to the next match", ["n"];
+    ThreadView "find-previous", "Go to the previous match", ["N"];
+    ThreadView "reply", "Reply to the message", ["r"];
+    ThreadView "reply-all", "Reply to all recipients", ["R"];
+    ThreadView "forward", "Forward the message", ["f"];
+
+    Compose "send", "Send the message", ["C-Enter"];
+    Compose "save-draft", "Save as draft", ["C-s"];
+}
+
+pub fn action_info(context: Context, name: &str) -> Option<&'static ActionInfo> {
+    ACTIONS.iter().find(|action| action.context == context && action.name == name)
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KeyCode {
+    Char(char),
+    Enter,
+    Esc,
+    Tab,
+    Backspace,
+    Delete,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    F(u8),
+}
+
+/// A single key press with its modifiers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyPress {
+    pub code: KeyCode,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+impl KeyPress {
+
+    pub fn new(code: KeyCode) -> Self {
+        KeyPress{
+            code,
+            ctrl: false,
+            alt: false,
+            shift: false
+        }
+    }
+
+    /// Parse a key like `j`, `C-q`, `M-x`, `S-Tab` or `F10`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut key = KeyPress::new(KeyCode::Enter);
+        let mut rest = s;
+
+        // a lone '-' is a key, not a modifier
+        while rest.len() > 2 && rest.as_bytes()[1] == b'-' {
+            match &rest[..1] {
+                "C" => key.ctrl = true,
+                "M" | "A" => key.alt = true,
+                "S" => key.shift = true,
+                _ => return Err(format!("unknown modifier in {:?}", s))
+            }
+            rest = &rest[2..];
+        }
+
+        let mut chars = rest.chars();
+        key.code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_lowercase().as_str() {
+                "enter" | "return" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "space" => KeyCode::Char(' '),
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                name if name.starts_with('f') => name[1..].parse()
+                                                           .ok()
+                                                           .filter(|n| *n >= 1 && *n <= 12)
+                                                           .map(KeyCode::F)
+                                                           .ok_or_else(|| format!("unknown key {:?}", s))?,
+                _ => return Err(format!("unknown key {:?}", s))
+            }
+        };
+
+        Ok(key.normalized())
+    }
+
+    /// Shifted letters are written as the upper case letter, `G` rather
+    /// than `S-g`, which is also what terminals report. With Control or Alt
+    /// the shift is explicit instead: `C-S-p` rather than `C-P`.
+    pub fn normalized(mut self) -> Self {
+        if let KeyCode::Char(c) = self.code {
+            if !c.is_alphabetic() {
+                return self;
+            }
+
+            if self.ctrl || self.alt {
+                if c.is_uppercase() {
+                    self.shift = true;
+                }
+                self.code = KeyCode::Char(c.to_lowercase().next().unwrap_or(c));
+            } else if self.shift {
+                self.code = KeyCode::Char(c.to_uppercase().next().unwrap_or(c));
+                self.shift = false;
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Display for KeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "C-")?;
+        }
+        if self.alt {
+            write!(f, "M-")?;
+        }
+        if self.shift {
+            write!(f, "S-")?;
+        }
+
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            code => write!(f, "{:?}", code)
+        }
+    }
+}
+
+
+/// One or more key presses, pressed one after the other
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeySequence(pub Vec<KeyPress>);
+
+impl KeySequence {
+
+    /// Parse whitespace separated keys, like `g g`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let keys = s.split_whitespace()
+                    .map(KeyPress::parse)
+                    .collect::<Result<Vec<_>, _>>()?;
+
+        if keys.is_empty() {
+            return Err("empty key sequence".to_string());
+        }
+        Ok(KeySequence(keys))
+    }
+
+    pub fn is_prefix_of(&self, other: &KeySequence) -> bool {
+        self.0.len() < other.0.len() && other.0.starts_with(&self.0)
+    }
+}
+
+impl fmt::Display for KeySequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keys: Vec<String> = self.0.iter().map(|key| key.to_string()).collect();
+        write!(f, "{}", keys.join(" "))
+    }
+}
+
+
+/// The keys for an action in the config: a single sequence or a list
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum KeysConfig {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeysConfig {
+    fn sequences(&self) -> Vec<&str> {
+        match self {
+            KeysConfig::One(keys) => vec![keys.as_str()],
+            KeysConfig::Many(keys) => keys.iter().map(|keys| keys.as_str()).collect()
+        }
+    }
+}
+
+/// The `[keybindings]` section. Keys are action names, optionally prefixed
+/// with their context like `thread-view.reply`; unprefixed names apply to
+/// every context that has the action. Bindings replace the defaults of an
+/// action, an empty list unbinds it.
+pub type KeybindingsConfig = BTreeMap<String, KeysConfig>;
+
+
+/// The result of looking up the keys pressed so far
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lookup {
+    Action(&'static str),
+
+    /// more keys are needed to complete a sequence
+    Prefix,
+
+    None,
+}
+
+
+/// Resolved key bindings: the defaults with the configuration applied
+#[derive(Clone, Debug, Default)]
+pub struct Keybindings {
+    bindings: BTreeMap<(Context, &'static str), Vec<KeySequence>>,
+}
+
+impl Keybindings {
+
+    /// Apply the configured bindings over the defaults. Returns the problems
+    /// found: unknown actions, unparseable keys and conflicts.
+    pub fn new(config: &KeybindingsConfig) -> Result<Self, Vec<String>> {
+        let mut problems = vec![];
+        let mut bindings = BTreeMap::new();
+
+        for action in ACTIONS {
+            let sequences = action.default_keys.iter()
+                                  .map(|keys| KeySequence::parse(keys).expect("invalid default key binding"))
+                                  .collect();
+            bindings.insert((action.context, action.name), sequences);
+        }
+
+        for (name, keys) in config {
+            let sequences = keys.sequences().iter()
+                                .map(|keys| KeySequence::parse(keys))
+                                .collect::<Result<Vec<_>, _>>();
+
+            let sequences = match sequences {
+                Ok(sequences) => sequences,
+                Err(err) => {
+                    problems.push(format!("keybindings: {}: {}", name, err));
+                    continue;
+                }
+            };
+
+            // action names have no dots, so anything before one has to be
+            // a context
+            let (context, action_name) = match name.find('.') {
+                Some(dot) => match parse_context(&name[..dot]) {
+                    Some(context) => (Some(context), &name[dot + 1..]),
+                    None => {
+                        problems.push(format!("keybindings: unknown context {:?} in {:?}", &name[..dot], name));
+                        continue;
+                    }
+                },
+                None => (None, name.as_str())
+            };
+
+            let matching: Vec<&ActionInfo> = ACTIONS.iter()
+                .filter(|action| action.name == action_name)
+                .filter(|action| context.map_or(true, |context| action.context == context))
+                .collect();
+
+            if matching.is_empty() {
+                problems.push(format!("keybindings: unknown action {:?}", name));
+            }
+            for action in matching {
+                bindings.insert((action.context, action.name), sequences.clone());
+            }
+        }
+
+        let keybindings = Keybindings{ bindings };
+        problems.extend(keybindings.conflicts());
+
+        if problems.is_empty() {
+            Ok(keybindings)
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Two actions in the same context bound to the same keys, or to keys
+    /// where one is the start of the other, can't both be reached.
+    fn conflicts(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        let all: Vec<(&(Context, &str), &KeySequence)> = self.bindings.iter()
+            .flat_map(|(action, sequences)| sequences.iter().map(move |sequence| (action, sequence)))
+            .collect();
+
+        for (i, (a, a_keys)) in all.iter().enumerate() {
+            for (b, b_keys) in &all[i + 1..] {
+                if a.0 != b.0 || a == b {
+                    continue;
+                }
+
+                if a_keys == b_keys {
+                    problems.push(format!("keybindings: {} is bound to both {} and {} in the {}",
+                                          a_keys, a.1, b.1, a.0.title().to_lowercase()));
+                } else if a_keys.is_prefix_of(b_keys) || b_keys.is_prefix_of(a_keys) {
+                    problems.push(format!("keybindings: {} ({}) and {} ({}) overlap in the {}",
+                                          a_keys, a.1, b_keys, b.1, a.0.title().to_lowercase()));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// The keys bound to an action
+    pub fn keys(&self, context: Context, action: &str) -> &[KeySequence] {
+        self.bindings.iter()
+            .find(|((c, name), _)| *c == context && *name == action)
+            .map(|(_, sequences)| sequences.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Look up the keys pressed so far in a context, falling back to the
+    /// global bindings
+    pub fn lookup(&self, context: Context, keys: &[KeyPress]) -> Lookup {
+        let mut contexts = vec![context];
+        if context != Context::Global {
+            contexts.push(Context::Global);
+        }
+
+        let mut prefix = false;
+        for context in contexts {
+            for ((c, action), sequences) in &self.bindings {
+                if *c != context {
+                    continue;
+                }
+                for sequence in sequences {
+                    if sequence.0.as_slice() == keys {
+                        return Lookup::Action(*action);
+                    }
+                    if sequence.0.starts_with(keys) {
+                        prefix = true;
+                    }
+                }
+            }
+
+            // a partial sequence in the specific context shadows global
+            // single keys
+            if prefix {
+                return Lookup::Prefix;
+            }
+        }
+
+        Lookup::None
+    }
+}
+
+fn parse_context(name: &str) -> Option<Context> {
+    match name {
+        "global" => Some(Context::Global),
+        "thread-list" => Some(Context::ThreadList),
+        "thread-view" => Some(Context::ThreadView),
+        "compose" => Some(Context::Compose),
+        _ => None
+    }
+}
+
+
+#[test]
+fn test_keybindings() {
+    let defaults = Keybindings::new(&KeybindingsConfig::new()).unwrap();
+
+    let g = KeyPress::parse("g").unwrap();
+    assert_eq!(defaults.lookup(Context::ThreadList, &[g]), Lookup::Prefix);
+    assert_eq!(defaults.lookup(Context::ThreadList, &[g, g]), Lookup::Action("first-thread"));
+    assert_eq!(defaults.lookup(Context::ThreadView, &[KeyPress::parse("j").unwrap()]), Lookup::Action("scroll-down"));
+    assert_eq!(defaults.lookup(Context::ThreadView, &[KeyPress::parse("C-q").unwrap()]), Lookup::Action("quit"));
+    assert_eq!(KeyPress::parse("S-g").unwrap(), KeyPress::parse("G").unwrap());
+    assert_eq!(KeyPress::parse("C-P").unwrap(), KeyPress::parse("C-S-p").unwrap());
+
+    let mut config = KeybindingsConfig::new();
+    config.insert("thread-list.archive".to_string(), KeysConfig::One("g".to_string()));
+    config.insert("frobnicate".to_string(), KeysConfig::Many(vec![]));
+    config.insert("thread_list.archive".to_string(), KeysConfig::One("x".to_string()));
+
+    let problems = Keybindings::new(&config).unwrap_err();
+    assert_eq!(problems.len(), 3);
+    assert!(problems.iter().any(|problem| problem.contains("unknown context \"thread_list\"")));
+}
@@ -1,11 +1,71 @@
+use std::io;
+use std::fmt;
 use std::path::{Path, PathBuf};
 use log::*;
+use toml;
 
+mod account;
+mod document;
 mod enamel;
+mod keybindings;
 mod notmuch;
+mod tags;
+mod watch;
 
 use crate::settings::enamel::Config as EnamelConfig;
-use crate::settings::notmuch::Config as NotMuchConfig;
+
+pub use crate::settings::account::{AccountConfig, SignatureConfig, SendConfig, EncryptPolicy,
+                                   bare_address};
+pub use crate::settings::document::ConfigDocument;
+pub use crate::settings::enamel::{SavedSearch, PollConfig, TagsConfig};
+pub use crate::settings::notmuch::{Config as NotMuchConfig, DatabaseConfig, UserConfig, NewConfig, SearchConfig,
+                                   MailDirConfig, IndexConfig, IndexDecrypt, ShowConfig};
+pub use crate::settings::tags::{TagDisplayConfig, TagDisplay, TagRules};
+pub use crate::settings::keybindings::{Keybindings, KeybindingsConfig, KeysConfig, KeyPress, KeyCode, KeySequence,
+                                       Context, Lookup, ActionInfo, ACTIONS, action_info};
+pub use crate::settings::watch::{SettingsChanges, SettingsEvent, watch};
+
+
+#[derive(Debug)]
+pub enum Error{
+    IoError(io::Error),
+    ParseError(toml::de::Error),
+    EditError(toml_edit::TomlError),
+    InvalidAccounts(Vec<String>),
+    InvalidKeybindings(Vec<String>),
+    InvalidTags(Vec<String>),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Error {
+        Error::ParseError(err)
+    }
+}
+
+impl From<toml_edit::TomlError> for Error {
+    fn from(err: toml_edit::TomlError) -> Error {
+        Error::EditError(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::IoError(err) => write!(f, "Could not read config: {}", err),
+            Error::ParseError(err) => write!(f, "Could not parse config: {}", err),
+            Error::EditError(err) => write!(f, "Could not parse config: {}", err),
+            Error::InvalidAccounts(problems) => write!(f, "Invalid account configuration:\n{}", problems.join("\n")),
+            Error::InvalidKeybindings(problems) => write!(f, "Invalid key bindings:\n{}", problems.join("\n")),
+            Error::InvalidTags(problems) => write!(f, "Invalid tag display:\n{}", problems.join("\n"))
+        }
+    }
+}
 
 
 #[derive(Clone, Debug)]
@@ -15,27 +75,74 @@ pub struct Settings{
     pub config_path: PathBuf,
 
     pub enamel_config: EnamelConfig,
-    pub notmuch_config: NotMuchConfig
 
+    /// Path where the notmuch config was loaded from
+    pub notmuch_config_path: PathBuf,
+    pub notmuch_config: NotMuchConfig,
+
+    pub keybindings: Keybindings,
 }
 
 
 impl Settings{
 
-    pub fn new(location: &Path) -> Self {
+    pub fn new(location: &Path) -> Result<Self, Error> {
 
-        let enamel_conf = EnamelConfig::load(location);
+        let enamel_conf = EnamelConfig::load(location)?;
+        let keybindings = enamel_conf.keybindings()?;
 
-        let notmuch_config_path = PathBuf::from(&enamel_conf.notmuch.path);
+        let notmuch_config_path = match enamel_conf.notmuch.path {
+            Some(ref path) => crate::settings::notmuch::expand(path),
+            None => crate::settings::notmuch::config_path()
+        };
 
         debug!("Loading notmuch config from {0:?}", notmuch_config_path);
 
         let notmuch_conf = NotMuchConfig::load(&notmuch_config_path);
 
-        Settings {
+        Ok(Settings {
             config_path: location.into(),
             enamel_config: enamel_conf,
-            notmuch_config: notmuch_conf
+            notmuch_config_path,
+            notmuch_config: notmuch_conf,
+            keybindings
+        })
+    }
+
+    /// Enamel's saved searches, followed by the named queries from the
+    /// notmuch config that have no saved search of the same name
+    pub fn saved_searches(&self) -> Vec<SavedSearch> {
+        let mut searches = self.enamel_config.searches.clone();
+
+        for name in self.notmuch_config.query.keys() {
+            if !searches.iter().any(|search| &search.name == name) {
+                searches.push(SavedSearch{
+                    name: name.clone(),
+                    query: format!("query:{}", name)
+                });
+            }
         }
+
+        searches
+    }
+
+    /// Expand the named queries in a query, see `NotMuchConfig::expand_query`
+    pub fn expand_query(&self, query: &str) -> String {
+        self.notmuch_config.expand_query(query)
+    }
+
+    /// The account marked as default, or the first one if none is
+    pub fn default_account(&self) -> Option<&AccountConfig> {
+        let accounts = &self.enamel_config.accounts;
+
+        accounts.values()
+                .find(|account| account.default)
+                .or_else(|| accounts.values().next())
+    }
+
+    /// Find the account an address belongs to
+    pub fn account_for(&self, address: &str) -> Option<&AccountConfig> {
+        self.enamel_config.accounts.values()
+            .find(|account| account.matches(address))
     }
 }
@@ -1,147 +1,574 @@
 use std;
-use std::fs::File;
-use std::io::prelude::*;
+use std::env;
+use std::fs;
+use std::process;
+use std::sync::Mutex;
+use std::collections::{BTreeMap, HashMap};
 
-use std::path::Path;
-use serde;
-use serde::de::Deserializer;
-use serde_derive::{Serialize, Deserialize};
+use std::path::{Path, PathBuf};
+use log::*;
+use serde_derive::Serialize;
 use shellexpand;
+use dirs;
+use lazy_static::lazy_static;
 
-use serde_ini;
+/// How deep named queries may refer to other named queries
+const MAX_QUERY_DEPTH: usize = 8;
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+/// The keys of one ini section
+type Keys = BTreeMap<String, String>;
+
+/// Keys of an ini file by section, like `values["database"]["path"]`
+type Values = BTreeMap<String, Keys>;
+
+lazy_static! {
+    /// The settings notmuch keeps in the database, by config file. notmuch is
+    /// only asked once for each, not every time the config is reloaded.
+    static ref DATABASE_VALUES: Mutex<HashMap<PathBuf, Values>> = Mutex::new(HashMap::new());
+}
+
+
+/// The notmuch config. Every section and key may be missing, in which case
+/// the defaults from notmuch-config(1) apply.
+#[derive(Clone, Serialize, Debug, Default)]
 pub struct Config {
-    #[serde(default)]
     pub database: DatabaseConfig,
     pub user: UserConfig,
     pub new: NewConfig,
     pub search: SearchConfig,
-    pub maildir: MailDirConfig
+    pub maildir: MailDirConfig,
+    pub index: IndexConfig,
+
+    /// Named queries, usable as `query:<name>`
+    pub query: BTreeMap<String, String>,
+
+    pub show: ShowConfig,
 }
 
 
 impl Config{
-    pub fn load(location: &Path) -> Self {
-        let mut conf_contents = String::new();
-        let expanded = shellexpand::full(location.to_str().unwrap()).unwrap().into_owned();
-
-        let expanded_path = Path::new(&expanded);
 
-        match File::open(&expanded_path) {
-            Ok(mut file) => {
-                file.read_to_string(&mut conf_contents);
-            },
+    /// Read the config file at `location`, with the settings notmuch keeps
+    /// in the database for whatever the file leaves out
+    pub fn load(location: &Path) -> Self {
+        let mut values = match fs::read_to_string(location) {
+            Ok(contents) => parse_ini(&contents),
             Err(err) => {
-                println!("err {:?}", err);
-                conf_contents = "".to_string();
+                warn!("Could not read notmuch config {:?}: {}", location, err);
+                Values::new()
             },
         };
 
-        serde_ini::from_str(&conf_contents).unwrap()
+        for (section, keys) in database_values(location) {
+            let file_keys = values.entry(section).or_insert_with(BTreeMap::new);
+            for (key, value) in keys {
+                file_keys.entry(key).or_insert(value);
+            }
+        }
+
+        Self::from_values(&values)
+    }
+
+    /// Parse the contents of a config file. Lines that make no sense are
+    /// skipped, and so are values that are not valid.
+    pub fn parse(contents: &str) -> Self {
+        Self::from_values(&parse_ini(contents))
+    }
+
+    fn from_values(values: &Values) -> Self {
+        let empty = Keys::new();
+        let section = |name: &str| values.get(name).unwrap_or(&empty);
+
+        Config {
+            database: DatabaseConfig::from_keys(section("database")),
+            user: UserConfig::from_keys(section("user")),
+            new: NewConfig::from_keys(section("new")),
+            search: SearchConfig::from_keys(section("search")),
+            maildir: MailDirConfig::from_keys(section("maildir")),
+            index: IndexConfig::from_keys(section("index")),
+            query: section("query").clone(),
+            show: ShowConfig::from_keys(section("show"))
+        }
     }
 
-    // #[serde(skip_serializing)]
-    // pub fn store(self: &Self, location: &Path) -> Result<(), String> {
-    //     // let mut outfile = File::create(location).unwrap();
-    //     // outfile.write_all(toml::to_string(&self).unwrap().as_bytes());
-    //     // outfile.sync_all();
-    //
-    //     return Ok(());
-    //
-    // }
+    /// The directory to open the database from. A configured path is used
+    /// as it is: with a mail_root of its own it is the database directory,
+    /// with `xapian` in it, otherwise the mail store with the database in
+    /// `.notmuch`. Only when nothing is configured the places notmuch(1)
+    /// lists under DATABASE LOCATION are searched.
+    pub fn database_path(&self) -> PathBuf {
+        if let Ok(path) = env::var("NOTMUCH_DATABASE") {
+            return expand(&path);
+        }
+        if let Some(ref path) = self.database.path {
+            return expand(path);
+        }
+
+        let mut candidates = vec![xdg_data_home().join("notmuch").join(profile_name())];
+        if let Ok(path) = env::var("MAILDIR") {
+            candidates.push(expand(&path));
+        }
+        candidates.push(home_dir().join("mail"));
+
+        let found = candidates.into_iter()
+                              .find(|path| path.join(".notmuch").is_dir() || path.join("xapian").is_dir());
+        match found {
+            Some(path) => path,
+            // nothing indexed yet
+            None => env::var("MAILDIR").ok().map(|path| expand(&path))
+                                        .unwrap_or_else(|| home_dir().join("mail"))
+        }
+    }
+
+    /// Replace `query:<name>` with the named query it stands for. notmuch
+    /// only knows the named queries kept in its database, not those that are
+    /// only in the config file. Unknown names are left for notmuch.
+    pub fn expand_query(&self, query: &str) -> String {
+        self.expand_query_depth(query, 0)
+    }
+
+    fn expand_query_depth(&self, query: &str, depth: usize) -> String {
+        let mut expanded = String::with_capacity(query.len());
+        let mut last = 0;
+
+        for (start, prefix) in query.match_indices("query:") {
+            if start < last {
+                continue;
+            }
+
+            let word_start = query[..start].chars().last()
+                                            .map(|c| c.is_whitespace() || c == '(')
+                                            .unwrap_or(true);
+            let name_start = start + prefix.len();
+            let name_end = query[name_start..].find(|c: char| c.is_whitespace() || c == ')')
+                                              .map(|end| name_start + end)
+                                              .unwrap_or_else(|| query.len());
+            let name = query[name_start..name_end].trim_matches('"');
+
+            let named = match self.query.get(name) {
+                Some(named) if word_start && depth < MAX_QUERY_DEPTH => named,
+                _ => continue
+            };
+
+            expanded.push_str(&query[last..start]);
+            expanded.push('(');
+            expanded.push_str(&self.expand_query_depth(named, depth + 1));
+            expanded.push(')');
+            last = name_end;
+        }
+
+        expanded.push_str(&query[last..]);
+        expanded
+    }
+
+    /// Where the mail is, which `folder:` and `path:` queries are relative to
+    pub fn mail_root(&self) -> PathBuf {
+        match (&self.database.mail_root, &self.database.path) {
+            (Some(path), _) | (None, Some(path)) => expand(path),
+            (None, None) => self.database_path()
+        }
+    }
+
+    /// Where the notmuch hooks are: in `.notmuch/hooks` when the database
+    /// is kept in the mail store, otherwise in the XDG config directory
+    pub fn hook_dir(&self) -> PathBuf {
+        if let Some(ref path) = self.database.hook_dir {
+            return expand(path);
+        }
+
+        let legacy = self.database_path().join(".notmuch");
+        if legacy.is_dir() {
+            legacy.join("hooks")
+        } else {
+            xdg_config_home().join("notmuch").join(profile_name()).join("hooks")
+        }
+    }
+
+    /// Where notmuch keeps tag backups: in `.notmuch/backups` when the
+    /// database is kept in the mail store, otherwise in the XDG data directory
+    pub fn backup_dir(&self) -> PathBuf {
+        if let Some(ref path) = self.database.backup_dir {
+            return expand(path);
+        }
+
+        let legacy = self.database_path().join(".notmuch");
+        if legacy.is_dir() {
+            legacy.join("backups")
+        } else {
+            xdg_data_home().join("notmuch").join(profile_name()).join("backups")
+        }
+    }
 }
 
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct DatabaseConfig {
-    #[serde(default = "default_database_path")]
-    pub path: String
+fn home_dir() -> PathBuf {
+    dirs::home_dir().unwrap_or_default()
+}
+
+/// An environment variable holding a directory. Empty and relative ones are
+/// ignored, like the XDG base directory spec says.
+fn env_dir(name: &str) -> Option<PathBuf> {
+    env::var_os(name).map(PathBuf::from)
+                     .filter(|path| path.is_absolute())
+}
+
+fn xdg_config_home() -> PathBuf {
+    env_dir("XDG_CONFIG_HOME").unwrap_or_else(|| home_dir().join(".config"))
+}
+
+fn xdg_data_home() -> PathBuf {
+    env_dir("XDG_DATA_HOME").unwrap_or_else(|| home_dir().join(".local").join("share"))
+}
+
+/// The profile set with `$NOTMUCH_PROFILE`, if any
+fn profile() -> Option<String> {
+    env::var("NOTMUCH_PROFILE").ok().filter(|profile| !profile.is_empty())
+}
+
+fn profile_name() -> String {
+    profile().unwrap_or_else(|| "default".to_string())
+}
+
+/// Expand `~` and environment variables. Relative paths are taken relative
+/// to the home directory, as notmuch does.
+pub fn expand(path: &str) -> PathBuf {
+    let expanded = shellexpand::full(path).map(|path| path.into_owned())
+                                          .unwrap_or_else(|_| path.to_string());
+    let expanded = PathBuf::from(expanded);
+
+    if expanded.is_absolute() {
+        expanded
+    } else {
+        home_dir().join(expanded)
+    }
+}
+
+/// Where notmuch reads its config: `$NOTMUCH_CONFIG`, then
+/// `$XDG_CONFIG_HOME/notmuch/<profile>/config`, then `~/.notmuch-config`, or
+/// `~/.notmuch-config.<profile>` with a profile.
+pub fn config_path() -> PathBuf {
+    if let Ok(path) = env::var("NOTMUCH_CONFIG") {
+        return expand(&path);
+    }
+
+    let xdg = xdg_config_home().join("notmuch").join(profile_name()).join("config");
+    if xdg.is_file() {
+        return xdg;
+    }
+
+    match profile() {
+        Some(profile) => home_dir().join(format!(".notmuch-config.{}", profile)),
+        None => home_dir().join(".notmuch-config")
+    }
 }
 
-impl Default for DatabaseConfig{
-    fn default() -> DatabaseConfig{
-        DatabaseConfig{
-            path: default_database_path()
+
+/// Read the keys of an ini file. Lines that make no sense are skipped.
+fn parse_ini(contents: &str) -> Values {
+    let mut values = Values::new();
+    let mut section = String::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_string();
+            values.entry(section.clone()).or_insert_with(BTreeMap::new);
+            continue;
+        }
+
+        if let Some(index) = line.find('=') {
+            let key = line[..index].trim().to_string();
+            let value = line[index + 1..].trim().to_string();
+            values.entry(section.clone()).or_insert_with(BTreeMap::new).insert(key, value);
         }
     }
+
+    values
 }
 
-fn default_database_path() -> String {
-    //Default: $MAILDIR variable if set, otherwise $HOME/mail.
-    "~/mail".to_string()
+/// Since notmuch 0.32 settings can live in the database too. notmuch itself
+/// lists them, and there is nothing to list when it is missing or older.
+/// The list is only asked for once per config file.
+fn database_values(config_path: &Path) -> Values {
+    let mut cache = DATABASE_VALUES.lock().unwrap();
+    cache.entry(config_path.to_path_buf())
+         .or_insert_with(|| list_database_values(config_path))
+         .clone()
 }
 
+fn list_database_values(config_path: &Path) -> Values {
+    let output = process::Command::new("notmuch")
+                                  .arg(format!("--config={}", config_path.display()))
+                                  .args(&["config", "list"])
+                                  .output();
+
+    let output = match output {
+        Ok(ref output) if output.status.success() => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Ok(_) | Err(_) => {
+            debug!("No notmuch config from the database");
+            return Values::new();
+        }
+    };
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+    let mut values = Values::new();
+    for line in output.lines() {
+        let (name, value) = match line.find('=') {
+            Some(index) => (&line[..index], &line[index + 1..]),
+            None => continue
+        };
+        // like `query.inbox`, where the name may contain dots itself
+        if let Some(dot) = name.find('.') {
+            values.entry(name[..dot].to_string())
+                  .or_insert_with(BTreeMap::new)
+                  .insert(name[dot + 1..].to_string(), value.to_string());
+        }
+    }
+
+    values
+}
+
+
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct DatabaseConfig {
+    /// The mail store, with the database in its `.notmuch` folder
+    pub path: Option<String>,
+
+    /// The mail store, when the database is kept elsewhere
+    pub mail_root: Option<String>,
+
+    pub hook_dir: Option<String>,
+
+    pub backup_dir: Option<String>,
+}
+
+impl DatabaseConfig {
+    fn from_keys(keys: &Keys) -> Self {
+        DatabaseConfig {
+            path: keys.get("path").cloned(),
+            mail_root: keys.get("mail_root").cloned(),
+            hook_dir: keys.get("hook_dir").cloned(),
+            backup_dir: keys.get("backup_dir").cloned()
+        }
+    }
+}
+
+
+#[derive(Clone, Serialize, Debug)]
 pub struct UserConfig {
-    #[serde(default = "default_user_name")]
     pub name: String,
-    #[serde(default = "default_user_primary_email")]
     pub primary_email: String,
-    pub other_email: String
+    pub other_email: Vec<String>
+}
+
+impl Default for UserConfig {
+    fn default() -> Self {
+        UserConfig::from_keys(&Keys::new())
+    }
+}
+
+impl UserConfig {
+    fn from_keys(keys: &Keys) -> Self {
+        UserConfig {
+            name: keys.get("name").cloned().unwrap_or_else(default_user_name),
+            primary_email: keys.get("primary_email").cloned().unwrap_or_else(default_user_primary_email),
+            other_email: get_list(keys, "other_email", &[])
+        }
+    }
 }
 
 
 fn default_user_name() -> String {
     //Default: $NAME variable if set, otherwise read from /etc/passwd.
-
-    let env_var = std::env::var("NAME");
-    if env_var.is_ok(){
-        return env_var.unwrap();
-    };
-
-    "".to_string()
+    env::var("NAME").unwrap_or_default()
 }
 
 fn default_user_primary_email() -> String {
     //Default: $EMAIL variable if set, otherwise constructed from  the
     //username and hostname of the current machine.
-    let env_var = std::env::var("EMAIL");
-    if env_var.is_ok(){
-        return env_var.unwrap();
-    };
-
-    "".to_string()
+    env::var("EMAIL").unwrap_or_default()
 }
 
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Debug)]
 pub struct NewConfig {
     // A  list  of tags that will be added to all messages incorporated by notmuch new.
-    #[serde(deserialize_with="parse_csv")]
     pub tags: Vec<String>,
 
-    pub ignore: String
+    // File and directory names, or /regexes/, that notmuch new skips
+    pub ignore: Vec<String>
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+impl Default for NewConfig {
+    fn default() -> Self {
+        NewConfig::from_keys(&Keys::new())
+    }
+}
+
+impl NewConfig {
+    fn from_keys(keys: &Keys) -> Self {
+        NewConfig {
+            tags: get_list(keys, "tags", &["unread", "inbox"]),
+            ignore: get_list(keys, "ignore", &[])
+        }
+    }
+}
+
+
+#[derive(Clone, Serialize, Debug, Default)]
 pub struct SearchConfig {
-    #[serde(deserialize_with="parse_csv")]
+    // Messages with these tags are left out of searches, unless asked for
     pub exclude_tags: Vec<String>
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+impl SearchConfig {
+    fn from_keys(keys: &Keys) -> Self {
+        SearchConfig {
+            exclude_tags: get_list(keys, "exclude_tags", &[])
+        }
+    }
+}
+
+
+#[derive(Clone, Serialize, Debug)]
 pub struct MailDirConfig {
-    #[serde(default = "default_maildir_synchronize_flags", deserialize_with="parse_bool")]
     pub synchronize_flags: bool
 }
 
-fn default_maildir_synchronize_flags() -> bool {
-    true
+impl Default for MailDirConfig {
+    fn default() -> Self {
+        MailDirConfig::from_keys(&Keys::new())
+    }
+}
+
+impl MailDirConfig {
+    fn from_keys(keys: &Keys) -> Self {
+        MailDirConfig {
+            synchronize_flags: get_bool(keys, "synchronize_flags", true)
+        }
+    }
+}
+
+
+/// When notmuch decrypts messages to index them
+#[derive(Clone, Copy, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexDecrypt {
+    False,
+    True,
+    /// only with a session key that is already known
+    Auto,
+    /// like true, without keeping the session keys
+    NoStash
+}
+
+impl Default for IndexDecrypt {
+    fn default() -> Self {
+        IndexDecrypt::Auto
+    }
+}
+
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct IndexConfig {
+    pub decrypt: IndexDecrypt,
+
+    // MIME types, or /regexes/ of them, that are indexed like text
+    pub as_text: Vec<String>,
+
+    // Search prefixes for headers, like `header.List=List-Id`
+    pub headers: BTreeMap<String, String>
+}
+
+impl IndexConfig {
+    fn from_keys(keys: &Keys) -> Self {
+        let decrypt = match keys.get("decrypt").map(|value| value.to_lowercase()) {
+            None => IndexDecrypt::default(),
+            Some(ref value) if value == "auto" => IndexDecrypt::Auto,
+            Some(ref value) if value == "nostash" => IndexDecrypt::NoStash,
+            Some(ref value) => match parse_bool(value) {
+                Some(true) => IndexDecrypt::True,
+                Some(false) => IndexDecrypt::False,
+                None => {
+                    warn!("notmuch config: index.decrypt {:?} is not valid", value);
+                    IndexDecrypt::default()
+                }
+            }
+        };
+
+        let headers = keys.iter()
+                          .filter(|(key, _)| key.starts_with("header."))
+                          .map(|(key, value)| (key["header.".len()..].to_string(), value.clone()))
+                          .collect();
+
+        IndexConfig {
+            decrypt,
+            as_text: get_list(keys, "as_text", &[]),
+            headers
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct ShowConfig {
+    // Headers `notmuch show` prints besides the usual ones
+    pub extra_headers: Vec<String>
+}
+
+impl ShowConfig {
+    fn from_keys(keys: &Keys) -> Self {
+        ShowConfig {
+            extra_headers: get_list(keys, "extra_headers", &[])
+        }
+    }
+}
+
+
+/// A `;` separated list. Empty items, like after a trailing `;`, are left out.
+fn get_list(keys: &Keys, key: &str, default: &[&str]) -> Vec<String> {
+    match keys.get(key) {
+        Some(value) => value.split(';')
+                            .map(|item| item.trim())
+                            .filter(|item| !item.is_empty())
+                            .map(|item| item.to_string())
+                            .collect(),
+        None => default.iter().map(|item| item.to_string()).collect()
+    }
 }
 
+fn get_bool(keys: &Keys, key: &str, default: bool) -> bool {
+    match keys.get(key) {
+        Some(value) => parse_bool(value).unwrap_or_else(|| {
+            warn!("notmuch config: {} {:?} is not a boolean", key, value);
+            default
+        }),
+        None => default
+    }
+}
 
-fn parse_csv<'de, D>(d: D) -> Result<Vec<String>, D::Error> where D: Deserializer<'de> {
-    serde::de::Deserialize::deserialize(d)
-        .map(|x: Option<String>| {
-            x.unwrap().split(';').map(|s| s.to_string()).collect()
-        })
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "yes" | "1" => Some(true),
+        "false" | "no" | "0" => Some(false),
+        _ => None
+    }
 }
 
-fn parse_bool<'de, D>(d: D) -> Result<bool, D::Error> where D: Deserializer<'de> {
-    serde::de::Deserialize::deserialize(d)
-        .map(|x: Option<String>| {
-            x.unwrap().parse().unwrap()
-        })
+
+#[test]
+fn test_expand_query() {
+    let config = Config::parse("[query]
+inbox=tag:inbox and not tag:archived
+todo=query:inbox and tag:flagged
+loop=query:loop or tag:spam
+");
+
+    assert_eq!(config.expand_query("query:inbox"), "(tag:inbox and not tag:archived)");
+    assert_eq!(config.expand_query("(query:todo) and date:today.."),
+               "(((tag:inbox and not tag:archived) and tag:flagged)) and date:today..");
+    assert_eq!(config.expand_query("query:\"inbox\" tag:x"), "(tag:inbox and not tag:archived) tag:x");
+    assert_eq!(config.expand_query("query:unknown or subject:query:inbox"), "query:unknown or subject:query:inbox");
+    assert!(config.expand_query("query:loop").contains("query:loop"));
 }
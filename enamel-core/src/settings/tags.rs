@@ -0,0 +1,196 @@
+use log::*;
+use regex::Regex;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::settings::enamel::TagsConfig;
+
+
+/// How to show the tags matching one `[tags.display]` key. Keys are tag
+/// names, or regexes between slashes like `"/^lists\\//"`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct TagDisplayConfig {
+    #[serde(default)]
+    pub foreground: Option<String>,
+
+    #[serde(default)]
+    pub background: Option<String>,
+
+    /// Shown in front of the tag, like an emoji
+    #[serde(default)]
+    pub icon: Option<String>,
+
+    /// Shown instead of the tag. For a regex, only the part it matches is
+    /// replaced, and `$1` and the like refer to its groups.
+    #[serde(default)]
+    pub alias: Option<String>,
+
+    /// Leave the tag out wherever tags are shown
+    #[serde(default)]
+    pub hidden: Option<bool>,
+}
+
+
+/// How one tag is shown
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagDisplay {
+    pub tag: String,
+
+    /// the alias, or the tag itself
+    pub label: String,
+
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    pub icon: Option<String>,
+    pub hidden: bool,
+}
+
+impl TagDisplay {
+
+    /// The icon followed by the label
+    pub fn text(&self) -> String {
+        match self.icon {
+            Some(ref icon) if self.label.is_empty() => icon.clone(),
+            Some(ref icon) => format!("{} {}", icon, self.label),
+            None => self.label.clone()
+        }
+    }
+}
+
+
+/// The `[tags]` config, ready to look up tags with. Entries for the tag name
+/// win over regexes, and regexes are tried in the order of their keys.
+#[derive(Clone, Debug)]
+pub struct TagRules {
+    config: TagsConfig,
+    patterns: Vec<(Regex, TagDisplayConfig)>,
+}
+
+impl Default for TagRules {
+    fn default() -> Self {
+        TagRules::new(&TagsConfig::default())
+    }
+}
+
+impl TagRules {
+
+    pub fn new(config: &TagsConfig) -> Self {
+        let patterns = config.display.iter()
+            .filter_map(|(key, display)| {
+                let pattern = pattern(key)?;
+                match Regex::new(pattern) {
+                    Ok(regex) => Some((regex, display.clone())),
+                    Err(err) => {
+                        warn!("Ignoring tag display {:?}: {}", key, err);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        TagRules {
+            config: config.clone(),
+            patterns
+        }
+    }
+
+    pub fn config(&self) -> &TagsConfig {
+        &self.config
+    }
+
+    pub fn display(&self, tag: &str) -> TagDisplay {
+        let mut display = TagDisplay {
+            tag: tag.to_string(),
+            label: tag.to_string(),
+            foreground: None,
+            background: None,
+            icon: None,
+            hidden: false
+        };
+        let mut alias = None;
+        let mut hidden = None;
+
+        let matches = self.config.display.get(tag).map(|config| (None, config)).into_iter()
+            .chain(self.patterns.iter()
+                                .filter(|(regex, _)| regex.is_match(tag))
+                                .map(|(regex, config)| (Some(regex), config)));
+
+        for (regex, config) in matches {
+            display.foreground = display.foreground.or_else(|| config.foreground.clone());
+            display.background = display.background.or_else(|| config.background.clone());
+            display.icon = display.icon.or_else(|| config.icon.clone());
+            hidden = hidden.or(config.hidden);
+
+            if alias.is_none() {
+                alias = config.alias.as_ref().map(|alias| match regex {
+                    Some(regex) => regex.replace(tag, alias.as_str()).into_owned(),
+                    None => alias.clone()
+                });
+            }
+        }
+
+        if let Some(alias) = alias {
+            display.label = alias;
+        }
+        display.hidden = hidden.unwrap_or(false);
+        display
+    }
+
+    /// Whether a tag is left out of thread lists: hidden everywhere, or
+    /// listed in `tags.hidden`
+    pub fn hidden_in_list(&self, tag: &str) -> bool {
+        self.config.hidden.iter().any(|hidden| hidden == tag) || self.display(tag).hidden
+    }
+}
+
+
+/// The regex of a `/regex/` key
+fn pattern(key: &str) -> Option<&str> {
+    if key.len() >= 2 && key.starts_with('/') && key.ends_with('/') {
+        Some(&key[1..key.len() - 1])
+    } else {
+        None
+    }
+}
+
+pub fn validate_tags(config: &TagsConfig) -> Vec<String> {
+    config.display.keys()
+          .filter_map(|key| pattern(key).and_then(|pattern| Regex::new(pattern).err())
+                                        .map(|err| format!("Tag display {:?} is not a valid regex: {}", key, err)))
+          .collect()
+}
+
+
+#[test]
+fn test_tag_display() {
+    let mut config = TagsConfig::default();
+    config.display.insert("lists/rust-dev".to_string(), TagDisplayConfig{
+        alias: Some("rust".to_string()),
+        ..TagDisplayConfig::default()
+    });
+    config.display.insert("/^lists/(.*)$/".to_string(), TagDisplayConfig{
+        alias: Some("$1".to_string()),
+        background: Some("#224466".to_string()),
+        icon: Some("@".to_string()),
+        ..TagDisplayConfig::default()
+    });
+    config.display.insert("spam".to_string(), TagDisplayConfig{
+        hidden: Some(true),
+        ..TagDisplayConfig::default()
+    });
+
+    let rules = TagRules::new(&config);
+
+    let display = rules.display("lists/rust-dev");
+    assert_eq!(display.label, "rust");
+    assert_eq!(display.background.as_ref().unwrap(), "#224466");
+    assert_eq!(display.text(), "@ rust");
+
+    assert_eq!(rules.display("lists/gtk").label, "gtk");
+    assert_eq!(rules.display("inbox").text(), "inbox");
+    assert!(rules.display("spam").hidden);
+    assert!(rules.hidden_in_list("unread"));
+    assert!(!rules.display("unread").hidden);
+
+    config.display.insert("/(/".to_string(), TagDisplayConfig::default());
+    assert_eq!(validate_tags(&config).len(), 1);
+}
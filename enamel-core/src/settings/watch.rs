@@ -0,0 +1,224 @@
+use std::fs;
+use std::thread;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use log::*;
+use serde::Serialize;
+use serde_json;
+
+use crate::settings::{Settings, Error};
+use crate::settings::enamel::Config;
+use crate::settings::notmuch::Config as NotMuchConfig;
+
+/// How often the config files are checked. Polling works the same in the
+/// GTK main loop and in the terminal client, which has none.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sections that have a flag of their own in `SettingsChanges`
+const SECTIONS: &[&str] = &["keybindings", "searches", "tags", "poll", "accounts", "notmuch"];
+
+
+/// Which parts differ between two versions of the settings
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SettingsChanges {
+    pub keybindings: bool,
+    pub searches: bool,
+    pub tags: bool,
+    pub poll: bool,
+    pub accounts: bool,
+
+    /// the notmuch config, or where it is read from. Named queries count
+    /// as searches.
+    pub notmuch: bool,
+
+    /// anything else, like the crypto and compose options
+    pub other: bool,
+}
+
+#[derive(Debug)]
+pub enum SettingsEvent {
+    /// the config files changed and loaded fine
+    Changed(Settings, SettingsChanges),
+
+    /// the config files changed, but could not be loaded. The old settings
+    /// stay in use.
+    Failed(Error),
+}
+
+
+/// A value in a form that can be compared, or None if it can't be
+/// serialized
+fn to_json<T: Serialize>(value: &T) -> Option<serde_json::Value> {
+    match serde_json::to_value(value) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            error!("Could not compare settings: {}", err);
+            None
+        }
+    }
+}
+
+/// Values that could not be compared count as changed
+fn json_differs(old: Option<serde_json::Value>, new: Option<serde_json::Value>) -> bool {
+    match (old, new) {
+        (Some(old), Some(new)) => old != new,
+        _ => true
+    }
+}
+
+fn differs<T: Serialize>(old: &T, new: &T) -> bool {
+    json_differs(to_json(old), to_json(new))
+}
+
+/// A config without the sections that are compared on their own
+fn without<T: Serialize>(config: &T, skipped: &[&str]) -> Option<serde_json::Value> {
+    let mut value = to_json(config)?;
+    if let Some(sections) = value.as_object_mut() {
+        for section in skipped {
+            sections.remove(*section);
+        }
+    }
+    Some(value)
+}
+
+fn other_sections(config: &Config) -> Option<serde_json::Value> {
+    without(config, SECTIONS)
+}
+
+fn notmuch_sections(config: &NotMuchConfig) -> Option<serde_json::Value> {
+    without(config, &["query"])
+}
+
+impl SettingsChanges {
+
+    pub fn between(old: &Settings, new: &Settings) -> Self {
+        let (old_config, new_config) = (&old.enamel_config, &new.enamel_config);
+
+        SettingsChanges {
+            keybindings: differs(&old_config.keybindings, &new_config.keybindings),
+            searches: differs(&old.saved_searches(), &new.saved_searches()),
+            tags: differs(&old_config.tags, &new_config.tags),
+            poll: differs(&old_config.poll, &new_config.poll),
+            accounts: differs(&old_config.accounts, &new_config.accounts),
+            notmuch: old.notmuch_config_path != new.notmuch_config_path ||
+                     json_differs(notmuch_sections(&old.notmuch_config), notmuch_sections(&new.notmuch_config)),
+            other: json_differs(other_sections(old_config), other_sections(new_config))
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == SettingsChanges::default()
+    }
+}
+
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn modification_times(settings: &Settings) -> (Option<SystemTime>, Option<SystemTime>) {
+    (modified(&settings.config_path), modified(&settings.notmuch_config_path))
+}
+
+/// Load the settings again whenever the enamel or notmuch config file
+/// changes, and tell `callback` what changed. Edits that change nothing are
+/// not reported. The watching thread ends once `callback` returns false.
+pub fn watch<F>(settings: &Settings, mut callback: F) -> thread::JoinHandle<()>
+    where F: FnMut(SettingsEvent) -> bool + Send + 'static
+{
+    let mut current = settings.clone();
+
+    thread::spawn(move || {
+        let mut seen = modification_times(&current);
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let times = modification_times(&current);
+            if times == seen {
+                continue;
+            }
+            seen = times;
+
+            debug!("Reloading settings from {:?}", current.config_path);
+
+            let event = match Settings::new(&current.config_path) {
+                Ok(settings) => {
+                    let changes = SettingsChanges::between(&current, &settings);
+                    if changes.is_empty() {
+                        continue;
+                    }
+
+                    // the notmuch config may be somewhere else now
+                    current = settings.clone();
+                    seen = modification_times(&current);
+
+                    SettingsEvent::Changed(settings, changes)
+                },
+                Err(err) => {
+                    warn!("Could not reload settings: {}", err);
+                    SettingsEvent::Failed(err)
+                }
+            };
+
+            if !callback(event) {
+                break;
+            }
+        }
+    })
+}
+
+
+#[cfg(test)]
+fn test_settings(config: &str, notmuch_config: &str) -> Settings {
+    let enamel_config = Config::parse(config).unwrap();
+    Settings {
+        config_path: "config.toml".into(),
+        keybindings: enamel_config.keybindings().unwrap(),
+        enamel_config,
+        notmuch_config_path: "notmuch-config".into(),
+        notmuch_config: NotMuchConfig::parse(notmuch_config)
+    }
+}
+
+#[test]
+fn test_changes_between() {
+    let old = test_settings(r#"
+[crypto]
+decrypt = true
+
+[[searches]]
+name = "Inbox"
+query = "tag:inbox"
+"#, "[database]\npath=/home/me/mail\n");
+
+    assert!(SettingsChanges::between(&old, &old.clone()).is_empty());
+
+    let new = test_settings(r#"
+[crypto]
+decrypt = false
+
+[[searches]]
+name = "Inbox"
+query = "tag:inbox and tag:unread"
+"#, "[database]\npath=/home/me/mail\n");
+    assert_eq!(SettingsChanges::between(&old, &new),
+               SettingsChanges{ searches: true, other: true, ..Default::default() });
+
+    // named queries are searches, the rest of the notmuch config is not
+    let new = test_settings(r#"
+[crypto]
+decrypt = true
+
+[[searches]]
+name = "Inbox"
+query = "tag:inbox"
+"#, "[database]\npath=/home/me/mail\n[query]\nwork=tag:work\n");
+    assert_eq!(SettingsChanges::between(&old, &new),
+               SettingsChanges{ searches: true, ..Default::default() });
+
+    let mut new = old.clone();
+    new.notmuch_config_path = "other-notmuch-config".into();
+    assert_eq!(SettingsChanges::between(&old, &new),
+               SettingsChanges{ notmuch: true, ..Default::default() });
+}
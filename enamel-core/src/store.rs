@@ -0,0 +1,39 @@
+//! The json files the address book and the Autocrypt peer store are kept in
+
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use log::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+
+/// Load a json file. A missing or broken file gives an empty value.
+pub(crate) fn load<T>(path: &Path) -> T
+    where T: DeserializeOwned + Default
+{
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return T::default()
+    };
+
+    serde_json::from_reader(io::BufReader::new(file)).unwrap_or_else(|err| {
+        warn!("Ignoring broken {:?}: {:?}", path, err);
+        T::default()
+    })
+}
+
+/// Write a json file, creating its directory if needed
+pub(crate) fn store<T>(value: &T, path: &Path) -> io::Result<()>
+    where T: Serialize
+{
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let file = File::create(path)?;
+    serde_json::to_writer(io::BufWriter::new(file), value)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+}
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+use dirs;
+
+use enamel_core::settings::{NotMuchConfig, IndexDecrypt};
+
+
+#[test]
+fn test_setup_config() {
+    // as written by `notmuch setup`
+    let config = NotMuchConfig::parse(include_str!("notmuch-config/setup"));
+
+    assert_eq!(config.database.path.as_ref().unwrap(), "/home/jane/mail");
+    assert_eq!(config.mail_root(), PathBuf::from("/home/jane/mail"));
+    assert_eq!(config.database_path(), PathBuf::from("/home/jane/mail"));
+    assert_eq!(config.user.name, "Jane Doe");
+    assert_eq!(config.user.primary_email, "jane@example.org");
+    assert_eq!(config.user.other_email, vec!["jane@work.example.com", "jdoe@example.net"]);
+    assert_eq!(config.new.tags, vec!["unread", "inbox"]);
+    assert!(config.new.ignore.is_empty());
+    assert_eq!(config.search.exclude_tags, vec!["deleted", "spam"]);
+    assert!(config.maildir.synchronize_flags);
+    assert!(config.query.is_empty());
+}
+
+#[test]
+fn test_minimal_config() {
+    let config = NotMuchConfig::parse(include_str!("notmuch-config/minimal"));
+
+    // relative to the home directory
+    assert_eq!(config.mail_root(), dirs::home_dir().unwrap().join("mail"));
+    assert_eq!(config.new.tags, vec!["unread", "inbox"]);
+    assert!(config.user.other_email.is_empty());
+    assert!(config.search.exclude_tags.is_empty());
+    assert!(config.maildir.synchronize_flags);
+    assert_eq!(config.index.decrypt, IndexDecrypt::Auto);
+    assert!(config.show.extra_headers.is_empty());
+}
+
+#[test]
+fn test_empty_config() {
+    let config = NotMuchConfig::parse("");
+
+    assert!(config.database.path.is_none());
+    assert_eq!(config.new.tags, vec!["unread", "inbox"]);
+    assert!(config.maildir.synchronize_flags);
+}
+
+#[test]
+fn test_split_database_config() {
+    let config = NotMuchConfig::parse(include_str!("notmuch-config/split-database"));
+
+    assert_eq!(config.mail_root(), PathBuf::from("/home/jane/Maildir"));
+    // the configured path is the database itself, even before it exists
+    assert_eq!(config.database_path(), PathBuf::from("/var/lib/notmuch/jane"));
+    assert_eq!(config.hook_dir(), PathBuf::from("/home/jane/.config/notmuch/hooks"));
+    assert!(config.user.other_email.is_empty());
+    assert_eq!(config.new.tags, vec!["new"]);
+    assert_eq!(config.new.ignore, vec![".mbsyncstate", ".uidvalidity", "/^\\.lock/"]);
+    assert!(!config.maildir.synchronize_flags);
+
+    assert_eq!(config.index.decrypt, IndexDecrypt::NoStash);
+    assert_eq!(config.index.as_text, vec!["text/", "application/x-sh"]);
+    assert_eq!(config.index.headers["List"], "List-Id");
+    assert_eq!(config.index.headers["Spam"], "X-Spam-Flag");
+
+    assert_eq!(config.query.len(), 2);
+    assert_eq!(config.query["inbox"], "tag:inbox and not tag:archived");
+    assert_eq!(config.query["work"], "to:jane@work.example.com or from:work.example.com");
+
+    assert_eq!(config.show.extra_headers, vec!["X-Mailer", "List-Id"]);
+}
+
+#[test]
+fn test_messy_config() {
+    let config = NotMuchConfig::parse(include_str!("notmuch-config/messy"));
+
+    assert_eq!(config.user.name, "Jane Doe");
+    assert!(config.user.other_email.is_empty());
+    // invalid values fall back to the defaults
+    assert!(config.maildir.synchronize_flags);
+    assert_eq!(config.index.decrypt, IndexDecrypt::Auto);
+}
@@ -1,11 +1,13 @@
 #![allow(new_without_default)]
 use std;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
+use std::thread;
+use std::process;
 
 use log::*;
 use gio::{
-    ApplicationExt, ApplicationExtManual, ApplicationFlags,
+    ActionMapExt, ApplicationExt, ApplicationExtManual, ApplicationFlags,
 };
 use glib;
 use gtk;
@@ -14,13 +16,17 @@ use gtk::prelude::*;
 use relm::init as relm_init;
 use relm::Component;
 
-use enamel_core::settings::Settings;
-use enamel_core::database::Manager as DBManager;
+use enamel_core::settings::{self as core_settings, Settings, SettingsChanges, SettingsEvent, Context};
+use enamel_core::database::{self, Manager as DBManager};
+use enamel_core::addressbook::{self, AddressBook};
+use enamel_core::autocrypt::{self, PeerStore};
 
 use crate::constants;
-use crate::main_window::MainWindow;
-use crate::clone;
+use crate::keybindings;
+use crate::main_window::{MainWindow, Msg as MainWindowMsg};
+use crate::{clone, action};
 use crate::static_resource::new_builder;
+use crate::widgets;
 
 #[derive(Debug, Clone)]
 pub enum Action {
@@ -48,8 +54,16 @@ pub struct EnamelApp {
     pub builder: gtk::Builder,
     window: RefCell<Option<Component<MainWindow>>>,
     // overlay: gtk::Overlay,
-    pub settings: Rc<Settings>,
+    settings: RefCell<Rc<Settings>>,
     pub dbmanager: Rc<DBManager>,
+    pub addressbook: Rc<RefCell<AddressBook>>,
+    pub autocrypt: Rc<RefCell<PeerStore>>,
+
+    /// bumped to stop the running poll timer
+    poll_generation: Rc<Cell<u32>>,
+
+    /// an address book update is running
+    addressbook_updating: Rc<Cell<bool>>,
 
     // gio_settings: gio::Settings,
     // content: Rc<Content>,
@@ -69,6 +83,12 @@ impl EnamelApp {
         let builder = new_builder().unwrap();
         let dbmanager = Rc::new(DBManager::new(&settings));
 
+        // start with the cached address book, it gets updated in the background
+        let addressbook = AddressBook::cache_path().map(|path| AddressBook::load(&path)).unwrap_or_default();
+        let addressbook = Rc::new(RefCell::new(addressbook));
+        let autocrypt = PeerStore::store_path().map(|path| PeerStore::load(&path)).unwrap_or_default();
+        let autocrypt = Rc::new(RefCell::new(autocrypt));
+
 
         //let weak_s = settings.downgrade();
         // let weak_app = application.downgrade();
@@ -130,10 +150,14 @@ impl EnamelApp {
 
         let app = EnamelApp {
             instance: application.clone(),
-            settings,
+            settings: RefCell::new(settings),
             window: RefCell::new(None),
             builder,
-            dbmanager
+            dbmanager,
+            addressbook,
+            autocrypt,
+            poll_generation: Rc::new(Cell::new(0)),
+            addressbook_updating: Rc::new(Cell::new(false))
             // overlay,
             // headerbar: header,
             // content,
@@ -156,17 +180,107 @@ impl EnamelApp {
 
         app.setup_gactions();
         app.setup_timed_callbacks();
+        app.setup_polling();
+        Self::setup_settings_watch(app);
+        app.setup_database_watch();
+        app.update_addressbook();
+        app.setup_autocrypt();
 
         app.instance.connect_activate(clone!(app => move |_| app.activate()));
 
 
-        // Retrieve the previous window position and size.
-        // WindowGeometry::from_settings(&app.settings).apply(&app.window);
+        // The main window restores its position, size and pages itself.
 
         // Setup the Action channel
         //gtk::timeout_add(25, clone!(app => move || app.setup_action_channel()));
     }
 
+    /// The settings as they are now. They change when the preferences are
+    /// saved, so hold on to them only for as long as they are used.
+    pub fn settings(&self) -> Rc<Settings> {
+        self.settings.borrow().clone()
+    }
+
+    /// Switch to changed settings, and bring everything that depends on
+    /// them up to date
+    pub fn set_settings(&self, settings: Settings) {
+        let changes = SettingsChanges::between(&self.settings(), &settings);
+        if changes.is_empty() {
+            return;
+        }
+        debug!("settings changed: {:?}", changes);
+
+        self.settings.replace(Rc::new(settings));
+        let settings = self.settings();
+
+        if changes.keybindings {
+            if let Some(window) = self.window.borrow().as_ref() {
+                widgets::set_shortcuts_window(&window.widget(), &settings.keybindings);
+            }
+            keybindings::apply(&self.instance, &settings.keybindings);
+        }
+
+        if changes.poll {
+            self.setup_polling();
+        }
+
+        if changes.notmuch {
+            warn!("The notmuch config changed, restart to open another database");
+        }
+
+        if let Some(window) = self.window.borrow().as_ref() {
+            window.emit(MainWindowMsg::SettingsChanged(changes));
+        }
+    }
+
+    /// Apply changes to the config files while running. A config that does
+    /// not load is reported, and the settings stay as they were.
+    fn setup_settings_watch(app: &Rc<Self>) {
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT_IDLE);
+        core_settings::watch(&app.settings(), move |event| sender.send(event).is_ok());
+
+        receiver.attach(None, clone!(app => move |event| {
+            match event {
+                SettingsEvent::Changed(settings, _) => {
+                    info!("Reloaded settings from {:?}", settings.config_path);
+                    app.set_settings(settings);
+                },
+                SettingsEvent::Failed(err) => app.show_error(&format!("Could not reload the settings, keeping the old ones.\n\n{}", err))
+            }
+            glib::Continue(true)
+        }));
+    }
+
+    /// Tell the main window when the database changes, so the tag counts
+    /// keep up with mail that other programs index or tag
+    fn setup_database_watch(&self) {
+        let stream = match self.window.borrow().as_ref() {
+            Some(window) => window.stream().clone(),
+            None => return
+        };
+
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT_IDLE);
+        database::watch(&self.settings().notmuch_config.database_path(), move |revision| sender.send(revision).is_ok());
+
+        receiver.attach(None, move |revision| {
+            stream.emit(MainWindowMsg::DatabaseChanged(revision));
+            glib::Continue(true)
+        });
+    }
+
+    /// Tell about a problem without stopping the user
+    pub fn show_error(&self, text: &str) {
+        let window: gtk::Window = self.builder.get_object("main_window")
+                                              .expect("Couldn't find main_window in ui file.");
+        let dialog = gtk::MessageDialog::new(Some(&window),
+                                             gtk::DialogFlags::DESTROY_WITH_PARENT,
+                                             gtk::MessageType::Error,
+                                             gtk::ButtonsType::Close,
+                                             text);
+        dialog.connect_response(|dialog, _| dialog.destroy());
+        dialog.show();
+    }
+
     pub fn activate(&self) {
         // TODO: broadcast activate signal
         let window: gtk::Window = self.builder.get_object("main_window")
@@ -176,6 +290,114 @@ impl EnamelApp {
     }
 
 
+    /// Bring the address book up to date with the database on a background
+    /// thread. Runs at startup and whenever mail came in or was sent; while
+    /// an update runs, the next one waits for the next change.
+    pub fn update_addressbook(&self) {
+        if self.addressbook_updating.replace(true) {
+            return;
+        }
+
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT_IDLE);
+        let settings = (*self.settings()).clone();
+
+        thread::spawn(move || {
+            // a failed update still has to say it is done
+            let updated = addressbook::build(&settings).map_err(|err| {
+                error!("Could not update address book: {:?}", err);
+            });
+            let _ = sender.send(updated.ok());
+        });
+
+        let addressbook = self.addressbook.clone();
+        let updating = self.addressbook_updating.clone();
+        receiver.attach(None, move |updated| {
+            updating.set(false);
+            if let Some(updated) = updated {
+                addressbook.replace(updated);
+            }
+            glib::Continue(false)
+        });
+    }
+
+    /// Collect Autocrypt headers from new mail on a background thread.
+    fn setup_autocrypt(&self) {
+        if !self.settings().enamel_config.crypto.autocrypt {
+            return;
+        }
+
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT_IDLE);
+        let settings = (*self.settings()).clone();
+
+        thread::spawn(move || {
+            match autocrypt::build(&settings) {
+                Ok(peers) => {
+                    let _ = sender.send(peers);
+                },
+                Err(err) => error!("Could not update autocrypt peers: {:?}", err)
+            }
+        });
+
+        let peers = self.autocrypt.clone();
+        receiver.attach(None, move |updated| {
+            peers.replace(updated);
+            glib::Continue(false)
+        });
+    }
+
+    /// Run the configured poll command every so often on a background
+    /// thread, and refresh the views when it is done. Calling this again
+    /// replaces the timer.
+    fn setup_polling(&self) {
+        let generation = self.poll_generation.get() + 1;
+        self.poll_generation.set(generation);
+
+        let poll = self.settings().enamel_config.poll.clone();
+        let command = match poll.command {
+            Some(ref command) if poll.interval > 0 && !command.trim().is_empty() => command.clone(),
+            _ => return
+        };
+        let stream = match self.window.borrow().as_ref() {
+            Some(window) => window.stream().clone(),
+            None => return
+        };
+
+        info!("Polling with {:?} every {} seconds", command, poll.interval);
+
+        let current_generation = self.poll_generation.clone();
+        let running = Rc::new(Cell::new(false));
+        gtk::timeout_add_seconds(poll.interval, move || {
+            if current_generation.get() != generation {
+                return glib::Continue(false);
+            }
+            // a slow command is not started twice
+            if running.get() {
+                return glib::Continue(true);
+            }
+            running.set(true);
+
+            let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT_IDLE);
+            let command = command.clone();
+            thread::spawn(move || {
+                let _ = sender.send(process::Command::new("sh").arg("-c").arg(&command).status());
+            });
+
+            let stream = stream.clone();
+            let running = running.clone();
+            receiver.attach(None, move |status| {
+                running.set(false);
+                match status {
+                    Ok(ref status) if status.success() => stream.emit(MainWindowMsg::TagsChanged(None)),
+                    Ok(status) => error!("Poll command failed: {}", status),
+                    Err(err) => error!("Could not run poll command: {}", err)
+                }
+                glib::Continue(false)
+            });
+
+            glib::Continue(true)
+        });
+    }
+
     fn setup_timed_callbacks(&self) {
         // self.setup_dark_theme();
         // self.setup_refresh_on_startup();
@@ -226,6 +448,41 @@ impl EnamelApp {
         //let sender = &self.sender;
         let _win = &self.window;
         let _instance = &self.instance;
+
+        if let Some(window) = self.window.borrow().as_ref() {
+            let win = window.widget();
+
+            // Global actions live on the window, the rest in a group per
+            // context, like `thread-list.archive`
+            let mut groups: Vec<(Context, gio::SimpleActionGroup)> = vec![];
+
+            // composers register their own actions
+            for action in core_settings::ACTIONS.iter().filter(|action| action.context != Context::Compose) {
+                let (context, name) = (action.context, action.name);
+                let stream = window.stream().clone();
+                let handler = move |_: &gio::SimpleAction, _: Option<&glib::Variant>| {
+                    stream.emit(MainWindowMsg::RunAction(context, name))
+                };
+
+                if context == Context::Global {
+                    action!(win, name, handler);
+                    continue;
+                }
+
+                if !groups.iter().any(|(group_context, _)| *group_context == context) {
+                    groups.push((context, gio::SimpleActionGroup::new()));
+                }
+                let group = &groups.iter().find(|(group_context, _)| *group_context == context).unwrap().1;
+                action!(group, name, handler);
+            }
+
+            for (context, group) in groups {
+                win.insert_action_group(keybindings::action_prefix(context), Some(&group));
+            }
+
+            // the help overlay lists what was registered above
+            widgets::set_shortcuts_window(&win, &self.settings().keybindings);
+        }
         // let header = &self.headerbar;
 
         // Create the `refresh` action.
@@ -250,13 +507,12 @@ impl EnamelApp {
 
         // Create the quit action
         //action!(win, "quit", clone!(instance => move |_, _| instance.quit()));
-        
-        self.instance.set_accels_for_action("win.quit", &["<primary>q"]);
 
         // Create the menu action
         // action!(win, "menu",clone!(header => move |_, _| header.open_menu()));
-        // Bind the hamburger menu button to `F10`
-        self.instance.set_accels_for_action("win.menu", &["F10"]);
+
+        // Accelerators for all actions come from the key bindings
+        keybindings::apply(&self.instance, &self.settings().keybindings);
     }
 
     fn setup_action_channel(&self) -> glib::Continue {
@@ -0,0 +1,292 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use log::*;
+use gio;
+use gio::ActionGroupExt;
+use gdk;
+use gtk;
+use gtk::prelude::*;
+use relm::{Relm, Update, Widget, connect};
+use relm_derive::Msg;
+
+use enamel_core::command::Command;
+use enamel_core::settings::{Context, ACTIONS};
+
+use crate::app::EnamelApp;
+use crate::keybindings;
+
+
+#[derive(Msg)]
+pub enum Msg {
+    // inbound
+    Show,
+
+    // private
+    Filter,
+    Activate,
+    RowActivated(gtk::ListBoxRow),
+    Hide,
+
+    // outbound
+    /// a command with arguments was typed, like `search tag:inbox`
+    RunCommand(Command),
+}
+
+/// An action listed in the palette
+struct PaletteItem {
+    context: Context,
+    name: &'static str,
+
+    /// lowercase name and description, matched against the filter
+    haystack: String,
+}
+
+pub struct CommandPalette {
+    model: CommandPaletteModel,
+    dialog: gtk::Window,
+    entry: gtk::SearchEntry,
+    list: gtk::ListBox,
+}
+
+pub struct CommandPaletteModel {
+    relm: Relm<CommandPalette>,
+    app: Rc<EnamelApp>,
+
+    /// the window whose actions are listed and run
+    window: gtk::ApplicationWindow,
+
+    /// in the order of the rows
+    items: Rc<RefCell<Vec<PaletteItem>>>,
+    filter: Rc<RefCell<String>>,
+}
+
+
+impl CommandPalette {
+
+    fn action_group(&self, context: Context) -> Option<gio::ActionGroup> {
+        self.model.window.get_action_group(keybindings::action_prefix(context))
+    }
+
+    /// List the actions the window has right now, with their current keys
+    fn populate(&mut self) {
+        for row in self.list.get_children() {
+            self.list.remove(&row);
+        }
+
+        // the filter func reads the items while rows are added
+        let mut items = vec![];
+
+        for action in ACTIONS {
+            let registered = self.action_group(action.context)
+                                 .map(|group| group.has_action(action.name))
+                                 .unwrap_or(false);
+            if !registered {
+                continue;
+            }
+
+            let keys = keybindings::labels(&self.model.app.settings().keybindings, action.context, action.name);
+
+            let row = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+            row.set_border_width(6);
+
+            let description = gtk::Label::new(Some(action.description));
+            description.set_halign(gtk::Align::Start);
+            description.set_hexpand(true);
+            row.pack_start(&description, true, true, 0);
+
+            let name = gtk::Label::new(Some(action.name));
+            name.get_style_context().add_class("dim-label");
+            row.pack_start(&name, false, false, 0);
+
+            let keys = gtk::Label::new(Some(keys.join(", ").as_str()));
+            keys.get_style_context().add_class("dim-label");
+            row.pack_end(&keys, false, false, 0);
+
+            self.list.add(&row);
+
+            items.push(PaletteItem{
+                context: action.context,
+                name: action.name,
+                haystack: format!("{} {}", action.name, action.description).to_lowercase()
+            });
+        }
+
+        self.model.items.replace(items);
+        self.list.show_all();
+    }
+
+    fn show(&mut self) {
+        self.populate();
+        self.entry.set_text("");
+        self.filter();
+
+        self.dialog.show();
+        self.entry.grab_focus();
+    }
+
+    fn filter(&mut self) {
+        let text = self.entry.get_text().map(|s| s.to_string()).unwrap_or_default();
+        self.model.filter.replace(text.trim().to_lowercase());
+        self.list.invalidate_filter();
+
+        // keep the first match selected, so enter runs it
+        let first = self.list.get_children().into_iter()
+                        .filter_map(|row| row.downcast::<gtk::ListBoxRow>().ok())
+                        .find(|row| row.get_child_visible());
+        self.list.select_row(first.as_ref());
+    }
+
+    /// Enter in the search entry: run typed commands, otherwise the selected
+    /// action
+    fn activate(&mut self) {
+        let text = self.entry.get_text().map(|s| s.to_string()).unwrap_or_default();
+
+        match Command::parse(&text) {
+            Ok(Command::Action(_)) | Err(_) => {
+                if let Some(row) = self.list.get_selected_row() {
+                    self.row_activated(row);
+                }
+            },
+            Ok(command) => {
+                self.hide();
+                self.model.relm.stream().emit(Msg::RunCommand(command));
+            }
+        }
+    }
+
+    fn row_activated(&mut self, row: gtk::ListBoxRow) {
+        let (context, name) = match self.model.items.borrow().get(row.get_index() as usize) {
+            Some(item) => (item.context, item.name),
+            None => return
+        };
+
+        self.hide();
+
+        match self.action_group(context) {
+            Some(group) => group.activate_action(name, None),
+            None => warn!("palette: no action group for {:?}", context)
+        }
+    }
+
+    fn hide(&mut self) {
+        self.dialog.hide();
+    }
+}
+
+
+impl Update for CommandPalette {
+    type Model = CommandPaletteModel;
+    type ModelParam = (Rc<EnamelApp>, gtk::ApplicationWindow);
+    type Msg = Msg;
+
+    fn model(relm: &Relm<Self>, (app, window): Self::ModelParam) -> Self::Model {
+        CommandPaletteModel {
+            relm: relm.clone(),
+            app,
+            window,
+            items: Rc::new(RefCell::new(vec![])),
+            filter: Rc::new(RefCell::new(String::new()))
+        }
+    }
+
+    fn update(&mut self, msg: Self::Msg) {
+        match msg {
+            Msg::Show => self.show(),
+            Msg::Filter => self.filter(),
+            Msg::Activate => self.activate(),
+            Msg::RowActivated(row) => self.row_activated(row),
+            Msg::Hide => self.hide(),
+            Msg::RunCommand(_) => ()
+        }
+    }
+}
+
+
+impl Widget for CommandPalette {
+
+    type Root = gtk::Window;
+
+    fn root(&self) -> Self::Root {
+        self.dialog.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self
+    {
+        let dialog = gtk::Window::new(gtk::WindowType::Toplevel);
+        dialog.set_transient_for(Some(&model.window));
+        dialog.set_modal(true);
+        dialog.set_decorated(false);
+        dialog.set_position(gtk::WindowPosition::CenterOnParent);
+        dialog.set_default_size(500, 400);
+
+        let entry = gtk::SearchEntry::new();
+        entry.set_placeholder_text(Some("Type an action or a command like \u{201c}search tag:inbox\u{201d}"));
+
+        let list = gtk::ListBox::new();
+        list.set_selection_mode(gtk::SelectionMode::Browse);
+        list.set_activate_on_single_click(true);
+
+        let items = model.items.clone();
+        let filter = model.filter.clone();
+        list.set_filter_func(Some(Box::new(move |row| {
+            let filter = filter.borrow();
+            items.borrow()
+                 .get(row.get_index() as usize)
+                 .map(|item| filter.split_whitespace().all(|word| item.haystack.contains(word)))
+                 .unwrap_or(false)
+        })));
+
+        let scrolled_window = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        scrolled_window.set_vexpand(true);
+        scrolled_window.add(&list);
+
+        let container = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        container.set_border_width(6);
+        container.pack_start(&entry, false, false, 0);
+        container.pack_start(&scrolled_window, true, true, 0);
+        dialog.add(&container);
+        container.show_all();
+
+        connect!(relm, entry, connect_search_changed(_), Msg::Filter);
+        connect!(relm, entry, connect_activate(_), Msg::Activate);
+        connect!(relm, entry, connect_stop_search(_), Msg::Hide);
+        connect!(relm, list, connect_row_activated(_, row), Msg::RowActivated(row.clone()));
+        connect!(relm, dialog, connect_delete_event(_, _), return (Some(Msg::Hide), gtk::Inhibit(true)));
+
+        // arrow keys in the entry move through the list
+        let keys_list = list.clone();
+        entry.connect_key_press_event(move |_, event| {
+            let step = match event.get_keyval() {
+                gdk::enums::key::Down => 1,
+                gdk::enums::key::Up => -1,
+                _ => return gtk::Inhibit(false)
+            };
+
+            let rows: Vec<gtk::ListBoxRow> = keys_list.get_children().into_iter()
+                .filter_map(|row| row.downcast::<gtk::ListBoxRow>().ok())
+                .filter(|row| row.get_child_visible())
+                .collect();
+
+            let current = keys_list.get_selected_row()
+                                   .and_then(|selected| rows.iter().position(|row| *row == selected));
+            let next = match current {
+                Some(i) => (i as i32 + step).max(0).min(rows.len() as i32 - 1) as usize,
+                None => 0
+            };
+
+            if let Some(row) = rows.get(next) {
+                keys_list.select_row(Some(row));
+                row.grab_focus();
+            }
+            gtk::Inhibit(true)
+        });
+
+        CommandPalette {
+            model,
+            dialog,
+            entry,
+            list
+        }
+    }
+}
@@ -0,0 +1,590 @@
+use std::rc::Rc;
+use std::thread;
+use std::path::PathBuf;
+use std::collections::HashMap;
+
+use log::*;
+use gio;
+use gio::ActionMapExt;
+use glib;
+use gtk;
+use gtk::prelude::*;
+use relm::{Relm, Update, Widget, connect};
+use relm_derive::Msg;
+
+use enamel_core::autocrypt;
+use enamel_core::compose::{self, ComposeMessage, Draft, Recommendation};
+use enamel_core::mime::crypto::CryptoProtocol;
+use enamel_core::settings::{AccountConfig, Context};
+
+use crate::action;
+use crate::app::EnamelApp;
+use crate::keybindings;
+use crate::widgets::address_completion::{attach_address_completion, CompletionMode};
+
+
+/// What a composer starts with
+pub enum ComposeStart {
+    New,
+    /// resume a draft
+    Draft(PathBuf),
+    /// a message prepared elsewhere, like a digest of forwarded messages
+    Message(ComposeMessage)
+}
+
+
+#[derive(Msg)]
+pub enum Msg {
+    Changed,
+    SubjectChanged,
+    RecipientsChanged,
+    /// public key lookups finished, by cache key
+    KeysFound(Vec<(String, bool)>),
+    SignToggled,
+    EncryptToggled,
+    Autosave,
+    SaveDraft,
+    Send,
+    /// save unsent changes as draft and stop autosaving
+    Close,
+
+    // outbound
+    /// the message was sent or closed, and the page can go
+    Closed,
+    Title(String)
+}
+
+pub struct Compose {
+    model: ComposeModel,
+    container: gtk::Box,
+    widgets: Widgets
+}
+
+pub struct ComposeModel {
+    relm: Relm<Compose>,
+    app: Rc<EnamelApp>,
+
+    message: ComposeMessage,
+    draft: Draft,
+
+    /// true if there are changes that haven't been saved as draft yet
+    dirty: bool,
+    autosave_handle: Option<glib::SourceId>,
+
+    /// public key lookups, by protocol and address. None while gpg is still
+    /// looking.
+    keys: HashMap<String, Option<bool>>,
+
+    /// the user picked encryption by hand, so stop following the
+    /// recommendation
+    encrypt_chosen: bool,
+
+    /// set while the toggle buttons are changed from code
+    updating_toggles: bool
+}
+
+struct Widgets {
+    from: gtk::ComboBoxText,
+    to: gtk::Entry,
+    cc: gtk::Entry,
+    bcc: gtk::Entry,
+    subject: gtk::Entry,
+    body: gtk::TextView,
+    sign: gtk::ToggleButton,
+    encrypt: gtk::ToggleButton,
+    key_warning: gtk::InfoBar,
+    key_warning_label: gtk::Label
+}
+
+
+fn attach_row(grid: &gtk::Grid, row: i32, label: &str, widget: &impl IsA<gtk::Widget>) {
+    let label = gtk::Label::new(Some(label));
+    label.set_halign(gtk::Align::End);
+
+    grid.attach(&label, 0, row, 1, 1);
+    grid.attach(widget, 1, row, 1, 1);
+}
+
+
+impl Compose {
+
+    fn account(&self) -> Option<AccountConfig> {
+        let settings = self.model.app.settings();
+
+        // the combo box uses the account addresses as ids
+        self.widgets.from.get_active_id()
+            .and_then(|id| settings.account_for(id.as_str()))
+            .or_else(|| settings.account_for(&self.model.message.from))
+            .cloned()
+    }
+
+    fn read_message(&mut self) {
+        let message = &mut self.model.message;
+
+        message.from = self.widgets.from.get_active_text().map(|s| s.to_string()).unwrap_or_default();
+        message.to = self.widgets.to.get_text().map(|s| s.to_string()).unwrap_or_default();
+        message.cc = self.widgets.cc.get_text().map(|s| s.to_string()).unwrap_or_default();
+        message.bcc = self.widgets.bcc.get_text().map(|s| s.to_string()).unwrap_or_default();
+        message.subject = self.widgets.subject.get_text().map(|s| s.to_string()).unwrap_or_default();
+
+        let buffer = self.widgets.body.get_buffer().unwrap();
+        let (start, end) = buffer.get_bounds();
+        message.body = buffer.get_text(&start, &end, false).map(|s| s.to_string()).unwrap_or_default();
+
+        message.sign = self.widgets.sign.get_active();
+        message.encrypt = self.widgets.encrypt.get_active();
+    }
+
+    fn write_message(&mut self) {
+        let message = self.model.message.clone();
+
+        if let Some(account) = self.model.app.settings().account_for(&message.from) {
+            self.widgets.from.set_active_id(Some(account.email.as_str()));
+        }
+        self.widgets.to.set_text(&message.to);
+        self.widgets.cc.set_text(&message.cc);
+        self.widgets.bcc.set_text(&message.bcc);
+        self.widgets.subject.set_text(&message.subject);
+        self.widgets.body.get_buffer().unwrap().set_text(&message.body);
+        self.set_toggles(message.sign, message.encrypt);
+    }
+
+    fn set_toggles(&mut self, sign: bool, encrypt: bool) {
+        self.model.updating_toggles = true;
+        self.widgets.sign.set_active(sign);
+        self.widgets.encrypt.set_active(encrypt);
+        self.model.updating_toggles = false;
+    }
+
+    /// The recommendation for the current recipients. Keys that haven't
+    /// been looked up yet are looked up on a background thread, unless `wait`
+    /// is set, and the recommendation is None until they are known.
+    fn recommendation(&mut self, wait: bool) -> Option<Recommendation> {
+        self.read_message();
+
+        let account = self.account()?;
+        let recipients = self.model.message.recipients();
+        let keys = &mut self.model.keys;
+        let protocol = account.crypto_protocol;
+        let peers = self.model.app.autocrypt.borrow();
+
+        let mut lookups = vec![];
+        let mut pending = false;
+        let recommendation = compose::recommend(&account, &recipients, &peers, |address| {
+            let key = format!("{:?}:{}", protocol, address.to_lowercase());
+            match keys.get(&key) {
+                Some(Some(found)) => return *found,
+                Some(None) if !wait => {
+                    pending = true;
+                    return false;
+                },
+                _ => ()
+            }
+
+            if wait {
+                let found = compose::has_public_key(protocol, address);
+                keys.insert(key, Some(found));
+                found
+            } else {
+                keys.insert(key.clone(), None);
+                lookups.push((key, address.to_string()));
+                pending = true;
+                false
+            }
+        });
+
+        if !lookups.is_empty() {
+            self.look_up_keys(protocol, lookups);
+        }
+
+        if pending {
+            None
+        } else {
+            Some(recommendation)
+        }
+    }
+
+    /// Run gpg for the given (cache key, address) pairs off the main loop
+    fn look_up_keys(&self, protocol: CryptoProtocol, lookups: Vec<(String, String)>) {
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT_IDLE);
+
+        thread::spawn(move || {
+            let found = lookups.into_iter()
+                               .map(|(key, address)| (key, compose::has_public_key(protocol, &address)))
+                               .collect();
+            let _ = sender.send(found);
+        });
+
+        let stream = self.model.relm.stream().clone();
+        receiver.attach(None, move |found| {
+            stream.emit(Msg::KeysFound(found));
+            glib::Continue(false)
+        });
+    }
+
+    fn keys_found(&mut self, found: Vec<(String, bool)>) {
+        for (key, found) in found {
+            self.model.keys.insert(key, Some(found));
+        }
+        self.update_crypto();
+    }
+
+    /// Follow the account policy for the current recipients, unless the user
+    /// chose otherwise, and warn about missing keys. Does nothing while keys
+    /// are still being looked up.
+    fn update_crypto(&mut self) {
+        let recommendation = match self.recommendation(false) {
+            Some(recommendation) => recommendation,
+            None => return
+        };
+
+        if !self.model.encrypt_chosen {
+            let sign = self.widgets.sign.get_active();
+            self.set_toggles(sign, recommendation.encrypt);
+        }
+
+        let encrypt = self.widgets.encrypt.get_active();
+        let smime = self.account().map(|account| account.crypto_protocol == CryptoProtocol::Smime);
+        if encrypt && !recommendation.missing_keys.is_empty() {
+            self.widgets.key_warning_label.set_text(
+                &format!("No public key for: {}", recommendation.missing_keys.join(", ")));
+            self.widgets.key_warning.show();
+        } else if encrypt && smime == Some(true) && !self.model.message.bcc_recipients().is_empty() {
+            // OpenPGP hides the key ids, S/MIME lists every recipient
+            self.widgets.key_warning_label.set_text(
+                "S/MIME encryption shows the Bcc recipients to everyone who receives this message.");
+            self.widgets.key_warning.show();
+        } else {
+            self.widgets.key_warning.hide();
+        }
+    }
+
+    fn encrypt_toggled(&mut self) {
+        if !self.model.updating_toggles {
+            self.model.encrypt_chosen = true;
+            self.model.dirty = true;
+        }
+        self.update_crypto();
+    }
+
+    fn save_draft(&mut self) {
+        self.read_message();
+
+        let account = match self.account() {
+            Some(account) => account,
+            None => {
+                warn!("compose: no account to save draft for");
+                return;
+            }
+        };
+
+        let app = self.model.app.clone();
+        match self.model.draft.save(&app.settings(), &app.dbmanager, &account, &self.model.message) {
+            Ok(true) => self.model.dirty = false,
+            Ok(false) => debug!("compose: account {} has no drafts folder", account.email),
+            Err(err) => error!("compose: could not save draft: {:?}", err)
+        }
+    }
+
+    fn send(&mut self) {
+        self.read_message();
+
+        let account = match self.account() {
+            Some(account) => account,
+            None => {
+                self.show_error("No account configured to send this message from.");
+                return;
+            }
+        };
+
+        let mut imported = vec![];
+        self.model.message.trust_recipients = false;
+
+        if self.model.message.encrypt {
+            let recommendation = self.recommendation(true).unwrap_or_default();
+            if !recommendation.missing_keys.is_empty() {
+                self.show_error(&format!("Can not encrypt, there is no public key for: {}",
+                                         recommendation.missing_keys.join(", ")));
+                return;
+            }
+
+            // keys learned from Autocrypt have to be in the keyring for gpg,
+            // but only while this message is encrypted
+            match self.model.app.autocrypt.borrow().import_keys(&recommendation.autocrypt_keys) {
+                Ok(fingerprints) => imported = fingerprints,
+                Err(err) => {
+                    self.show_error(&format!("Could not import Autocrypt keys: {:?}", err));
+                    return;
+                }
+            }
+            self.model.message.trust_recipients = !recommendation.autocrypt_keys.is_empty();
+        }
+
+        let app = self.model.app.clone();
+        let sent = compose::send_message(&app.settings(), &app.dbmanager, &account, &self.model.message, &mut self.model.draft);
+
+        if let Err(err) = autocrypt::remove_keys(&imported) {
+            warn!("compose: could not remove imported Autocrypt keys: {:?}", err);
+        }
+
+        match sent {
+            Ok(()) => {
+                self.model.dirty = false;
+                app.update_addressbook();
+                self.close();
+            },
+            Err(err) => {
+                error!("compose: could not send message: {:?}", err);
+                self.show_error(&format!("Could not send message: {:?}", err));
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(handle) = self.model.autosave_handle.take() {
+            glib::source::source_remove(handle);
+        }
+
+        if self.model.dirty {
+            self.save_draft();
+        }
+
+        self.model.relm.stream().emit(Msg::Closed);
+    }
+
+    /// Tell the page the subject, to use as title
+    fn subject_changed(&mut self) {
+        let subject = self.widgets.subject.get_text().map(|s| s.to_string()).unwrap_or_default();
+        let title = if subject.trim().is_empty() {
+            "New message".to_string()
+        } else {
+            subject
+        };
+        self.model.relm.stream().emit(Msg::Title(title));
+    }
+
+    fn show_error(&self, text: &str) {
+        let window = self.container.get_toplevel()
+                                   .and_then(|toplevel| toplevel.downcast::<gtk::Window>().ok());
+        let dialog = gtk::MessageDialog::new(window.as_ref(),
+                                             gtk::DialogFlags::MODAL,
+                                             gtk::MessageType::Error,
+                                             gtk::ButtonsType::Close,
+                                             text);
+        dialog.run();
+        dialog.destroy();
+    }
+}
+
+
+impl Update for Compose {
+    type Model = ComposeModel;
+    type ModelParam = (Rc<EnamelApp>, ComposeStart);
+    type Msg = Msg;
+
+    fn model(relm: &Relm<Self>, (app, start): Self::ModelParam) -> Self::Model {
+
+        // a prepared message is not saved anywhere yet
+        let mut dirty = false;
+        let mut from_draft = false;
+        let resumed = match start {
+            ComposeStart::New => None,
+            ComposeStart::Draft(path) => {
+                from_draft = true;
+                Draft::open(&path)
+            },
+            ComposeStart::Message(message) => {
+                dirty = true;
+                Some((Draft::new(), message))
+            }
+        };
+
+        let (draft, message) = match resumed {
+            Some((draft, mut message)) => {
+                // drafts are never signed, so take the policy again
+                if let Some(account) = app.settings().account_for(&message.from) {
+                    message.sign = account.sign;
+                }
+                (draft, message)
+            },
+            None => {
+                let message = app.settings().default_account()
+                                            .map(ComposeMessage::new)
+                                            .unwrap_or_default();
+                (Draft::new(), message)
+            }
+        };
+
+        // an encrypted draft stays encrypted
+        let encrypt_chosen = from_draft && message.encrypt;
+
+        ComposeModel {
+            relm: relm.clone(),
+            app,
+            message,
+            draft,
+            dirty,
+            autosave_handle: None,
+            keys: HashMap::new(),
+            encrypt_chosen,
+            updating_toggles: false
+        }
+    }
+
+    fn update(&mut self, msg: Self::Msg) {
+        match msg {
+            Msg::Changed => self.model.dirty = true,
+            Msg::SubjectChanged => {
+                self.model.dirty = true;
+                self.subject_changed();
+            },
+            Msg::RecipientsChanged => {
+                self.model.dirty = true;
+                self.update_crypto();
+            },
+            Msg::KeysFound(found) => self.keys_found(found),
+            Msg::SignToggled => if !self.model.updating_toggles {
+                self.model.dirty = true
+            },
+            Msg::EncryptToggled => self.encrypt_toggled(),
+            Msg::Autosave => if self.model.dirty {
+                self.save_draft()
+            },
+            Msg::SaveDraft => self.save_draft(),
+            Msg::Send => self.send(),
+            Msg::Close => self.close(),
+            Msg::Closed | Msg::Title(_) => ()
+        }
+    }
+}
+
+
+impl Widget for Compose {
+
+    type Root = gtk::Box;
+
+    fn root(&self) -> Self::Root {
+        self.container.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self
+    {
+        let container = gtk::Box::new(gtk::Orientation::Vertical, 0);
+
+        // the compose actions of the focused page get the accelerators
+        let actions = gio::SimpleActionGroup::new();
+        let stream = relm.stream().clone();
+        action!(actions, "send", move |_, _| stream.emit(Msg::Send));
+        let stream = relm.stream().clone();
+        action!(actions, "save-draft", move |_, _| stream.emit(Msg::SaveDraft));
+        container.insert_action_group(keybindings::action_prefix(Context::Compose), Some(&actions));
+
+        let action_bar = gtk::ActionBar::new();
+
+        let send_button = gtk::Button::new_with_label("Send");
+        send_button.get_style_context().add_class("suggested-action");
+        action_bar.pack_end(&send_button);
+
+        let sign = gtk::ToggleButton::new_with_label("Sign");
+        sign.set_tooltip_text(Some("Sign this message"));
+        let encrypt = gtk::ToggleButton::new_with_label("Encrypt");
+        encrypt.set_tooltip_text(Some("Encrypt this message"));
+        action_bar.pack_end(&encrypt);
+        action_bar.pack_end(&sign);
+        container.pack_start(&action_bar, false, false, 0);
+
+        let grid = gtk::Grid::new();
+        grid.set_row_spacing(6);
+        grid.set_column_spacing(6);
+        grid.set_border_width(6);
+
+        let from = gtk::ComboBoxText::new();
+        for account in model.app.settings().enamel_config.accounts.values() {
+            from.append(Some(account.email.as_str()), &account.mailbox());
+        }
+
+        let to = gtk::Entry::new();
+        let cc = gtk::Entry::new();
+        let bcc = gtk::Entry::new();
+        let subject = gtk::Entry::new();
+        to.set_hexpand(true);
+
+        for entry in &[&to, &cc, &bcc] {
+            attach_address_completion(entry, model.app.addressbook.clone(), CompletionMode::Recipients);
+        }
+
+        attach_row(&grid, 0, "From", &from);
+        attach_row(&grid, 1, "To", &to);
+        attach_row(&grid, 2, "Cc", &cc);
+        attach_row(&grid, 3, "Bcc", &bcc);
+        attach_row(&grid, 4, "Subject", &subject);
+
+        let key_warning = gtk::InfoBar::new();
+        key_warning.set_message_type(gtk::MessageType::Warning);
+        key_warning.set_no_show_all(true);
+        let key_warning_label = gtk::Label::new(None);
+        key_warning_label.show();
+        key_warning.get_content_area().unwrap().add(&key_warning_label);
+        grid.attach(&key_warning, 0, 5, 2, 1);
+
+        let body = gtk::TextView::new();
+        body.set_monospace(true);
+        body.set_wrap_mode(gtk::WrapMode::WordChar);
+
+        let scrolled_window = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        scrolled_window.set_vexpand(true);
+        scrolled_window.add(&body);
+        grid.attach(&scrolled_window, 0, 6, 2, 1);
+
+        container.pack_start(&grid, true, true, 0);
+
+        connect!(relm, from, connect_changed(_), Msg::RecipientsChanged);
+        // keys are only looked up for finished addresses
+        for entry in &[&to, &cc, &bcc] {
+            connect!(relm, entry, connect_changed(_), Msg::Changed);
+            connect!(relm, entry, connect_focus_out_event(_, _), return (Some(Msg::RecipientsChanged), gtk::Inhibit(false)));
+        }
+        connect!(relm, sign, connect_toggled(_), Msg::SignToggled);
+        connect!(relm, encrypt, connect_toggled(_), Msg::EncryptToggled);
+        connect!(relm, subject, connect_changed(_), Msg::SubjectChanged);
+        connect!(relm, body.get_buffer().unwrap(), connect_changed(_), Msg::Changed);
+        connect!(relm, send_button, connect_clicked(_), Msg::Send);
+
+        Compose {
+            model,
+            container,
+            widgets: Widgets {
+                from,
+                to,
+                cc,
+                bcc,
+                subject,
+                body,
+                sign,
+                encrypt,
+                key_warning,
+                key_warning_label
+            }
+        }
+    }
+
+    fn init_view(&mut self) {
+        self.write_message();
+        self.update_crypto();
+
+        // filling in the fields is not a change
+        self.model.dirty = false;
+        self.subject_changed();
+
+        let interval = self.model.app.settings().enamel_config.compose.autosave_interval;
+        if interval > 0 {
+            let stream = self.model.relm.stream().clone();
+            self.model.autosave_handle = Some(gtk::timeout_add_seconds(interval, move || {
+                stream.emit(Msg::Autosave);
+                Continue(true)
+            }));
+        }
+
+        self.container.show_all();
+    }
+}
@@ -1,3 +1,8 @@
+pub mod command_palette;
+pub mod compose;
+pub mod preferences;
+pub mod search_bar;
+pub mod search_page;
 pub mod tag_list;
 pub mod thread_list;
 pub mod thread_view;
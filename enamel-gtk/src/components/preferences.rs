@@ -0,0 +1,536 @@
+use std::rc::Rc;
+use std::str::FromStr;
+
+use log::*;
+use gdk;
+use gtk;
+use gtk::prelude::*;
+use relm::{Relm, Update, Widget, connect};
+use relm_derive::Msg;
+
+use enamel_core::settings::{Settings, ConfigDocument, SavedSearch};
+
+use crate::app::EnamelApp;
+
+
+#[derive(Msg)]
+pub enum Msg {
+    // inbound
+    Show,
+
+    // private
+    AddSearch,
+    RemoveSearch(gtk::ListBoxRow),
+    Save,
+    Hide,
+}
+
+struct SearchRow {
+    row: gtk::ListBoxRow,
+    name: gtk::Entry,
+    query: gtk::Entry
+}
+
+struct AccountRow {
+    /// the table name in `[accounts.<key>]`
+    key: String,
+    name: gtk::Entry,
+    email: gtk::Entry,
+    sign: gtk::Switch
+}
+
+struct Widgets {
+    restore_pages: gtk::Switch,
+    decrypt: gtk::Switch,
+    index_session_keys: gtk::Switch,
+    autocrypt: gtk::Switch,
+    autosave_interval: gtk::SpinButton,
+
+    poll_command: gtk::Entry,
+    poll_interval: gtk::SpinButton,
+
+    tags_upper_color: gtk::ColorButton,
+    tags_lower_color: gtk::ColorButton,
+    tags_alpha: gtk::SpinButton,
+    hidden_tags: gtk::Entry,
+    pinned_tags: gtk::Entry,
+
+    search_list: gtk::ListBox,
+
+    default_account: gtk::ComboBoxText,
+    account_box: gtk::Box
+}
+
+/// Edits the options of config.toml that are worth a user interface. The
+/// rest of the file, comments included, is left as it is.
+pub struct Preferences {
+    model: PreferencesModel,
+    dialog: gtk::Window,
+    widgets: Widgets,
+
+    /// in the order of the rows
+    searches: Vec<SearchRow>,
+    accounts: Vec<AccountRow>
+}
+
+pub struct PreferencesModel {
+    relm: Relm<Preferences>,
+    app: Rc<EnamelApp>,
+    window: gtk::ApplicationWindow
+}
+
+
+fn attach_row(grid: &gtk::Grid, row: i32, label: &str, widget: &impl IsA<gtk::Widget>) {
+    let label = gtk::Label::new(Some(label));
+    label.set_halign(gtk::Align::End);
+
+    grid.attach(&label, 0, row, 1, 1);
+    grid.attach(widget, 1, row, 1, 1);
+}
+
+fn new_grid() -> gtk::Grid {
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(12);
+    grid.set_border_width(12);
+    grid
+}
+
+fn new_switch() -> gtk::Switch {
+    let switch = gtk::Switch::new();
+    switch.set_halign(gtk::Align::Start);
+    switch
+}
+
+fn new_spin_button(max: f64) -> gtk::SpinButton {
+    let spin = gtk::SpinButton::new_with_range(0.0, max, 1.0);
+    spin.set_halign(gtk::Align::Start);
+    spin
+}
+
+fn parse_color(color: &str) -> Option<gdk::RGBA> {
+    gdk::RGBA::from_str(color).ok()
+}
+
+fn to_hex(color: &gdk::RGBA) -> String {
+    format!("#{:02x}{:02x}{:02x}",
+            (color.red * 255.0).round() as u8,
+            (color.green * 255.0).round() as u8,
+            (color.blue * 255.0).round() as u8)
+}
+
+/// Tags are typed separated by spaces or commas
+fn split_tags(text: &str) -> Vec<String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| tag.to_string())
+        .collect()
+}
+
+fn entry_text(entry: &gtk::Entry) -> String {
+    entry.get_text().map(|s| s.trim().to_string()).unwrap_or_default()
+}
+
+
+impl Preferences {
+
+    /// Fill in the current settings
+    fn show(&mut self) {
+        let settings = self.model.app.settings();
+        let config = &settings.enamel_config;
+
+        self.widgets.restore_pages.set_active(config.ui.restore_pages);
+        self.widgets.decrypt.set_active(config.crypto.decrypt);
+        self.widgets.index_session_keys.set_active(config.crypto.index_session_keys);
+        self.widgets.autocrypt.set_active(config.crypto.autocrypt);
+        self.widgets.autosave_interval.set_value(f64::from(config.compose.autosave_interval));
+
+        self.widgets.poll_command.set_text(config.poll.command.as_ref().map(|s| s.as_str()).unwrap_or(""));
+        self.widgets.poll_interval.set_value(f64::from(config.poll.interval));
+
+        if let Some(color) = parse_color(&config.tags.upper_color) {
+            self.widgets.tags_upper_color.set_rgba(&color);
+        }
+        if let Some(color) = parse_color(&config.tags.lower_color) {
+            self.widgets.tags_lower_color.set_rgba(&color);
+        }
+        self.widgets.tags_alpha.set_value(config.tags.alpha);
+        self.widgets.hidden_tags.set_text(&config.tags.hidden.join(" "));
+        self.widgets.pinned_tags.set_text(&config.tags.pinned.join(" "));
+
+        for search in self.searches.drain(..) {
+            self.widgets.search_list.remove(&search.row);
+        }
+        for search in &config.searches {
+            self.add_search(&search.name, &search.query);
+        }
+
+        self.show_accounts(&settings);
+
+        self.dialog.show_all();
+        self.dialog.present();
+    }
+
+    fn show_accounts(&mut self, settings: &Settings) {
+        let accounts = &settings.enamel_config.accounts;
+
+        for child in self.widgets.account_box.get_children() {
+            self.widgets.account_box.remove(&child);
+        }
+        self.accounts.clear();
+        self.widgets.default_account.remove_all();
+
+        for (key, account) in accounts {
+            self.widgets.default_account.append(Some(key.as_str()), &account.mailbox());
+
+            let grid = new_grid();
+
+            let name = gtk::Entry::new();
+            name.set_hexpand(true);
+            name.set_text(&account.name);
+            attach_row(&grid, 0, "Name", &name);
+
+            let email = gtk::Entry::new();
+            email.set_text(&account.email);
+            attach_row(&grid, 1, "Address", &email);
+
+            let sign = new_switch();
+            sign.set_active(account.sign);
+            attach_row(&grid, 2, "Sign messages", &sign);
+
+            let frame = gtk::Frame::new(Some(key.as_str()));
+            frame.add(&grid);
+            self.widgets.account_box.pack_start(&frame, false, false, 0);
+
+            self.accounts.push(AccountRow{
+                key: key.clone(),
+                name,
+                email,
+                sign
+            });
+        }
+
+        let default_key = accounts.iter()
+                                  .find(|(_, account)| account.default)
+                                  .or_else(|| accounts.iter().next())
+                                  .map(|(key, _)| key.as_str());
+        self.widgets.default_account.set_active_id(default_key);
+    }
+
+    fn add_search(&mut self, name: &str, query: &str) {
+        let name_entry = gtk::Entry::new();
+        name_entry.set_placeholder_text(Some("Name"));
+        name_entry.set_text(name);
+
+        let query_entry = gtk::Entry::new();
+        query_entry.set_placeholder_text(Some("notmuch query"));
+        query_entry.set_text(query);
+        query_entry.set_hexpand(true);
+
+        let remove = gtk::Button::new_from_icon_name(Some("list-remove-symbolic"), gtk::IconSize::Button);
+        remove.set_tooltip_text(Some("Remove"));
+
+        let container = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        container.set_border_width(6);
+        container.pack_start(&name_entry, false, false, 0);
+        container.pack_start(&query_entry, true, true, 0);
+        container.pack_start(&remove, false, false, 0);
+
+        let row = gtk::ListBoxRow::new();
+        row.set_activatable(false);
+        row.add(&container);
+        row.show_all();
+        self.widgets.search_list.add(&row);
+
+        let remove_row = row.clone();
+        connect!(self.model.relm, remove, connect_clicked(_), Msg::RemoveSearch(remove_row.clone()));
+
+        self.searches.push(SearchRow{
+            row,
+            name: name_entry,
+            query: query_entry
+        });
+    }
+
+    fn remove_search(&mut self, row: gtk::ListBoxRow) {
+        if let Some(index) = self.searches.iter().position(|search| search.row == row) {
+            self.widgets.search_list.remove(&row);
+            self.searches.remove(index);
+        }
+    }
+
+    /// Write the changes to config.toml, and use them right away
+    fn save(&mut self) {
+        let settings = self.model.app.settings();
+
+        let mut document = match ConfigDocument::load(&settings.config_path) {
+            Ok(document) => document,
+            Err(err) => {
+                self.show_error(&err.to_string());
+                return;
+            }
+        };
+        self.update_document(&settings, &mut document);
+
+        if let Err(err) = document.store(&settings.config_path) {
+            self.show_error(&err.to_string());
+            return;
+        }
+
+        match Settings::new(&settings.config_path) {
+            Ok(settings) => {
+                info!("Saved preferences to {:?}", settings.config_path);
+                self.model.app.set_settings(settings);
+                self.hide();
+            },
+            Err(err) => self.show_error(&err.to_string())
+        }
+    }
+
+    fn update_document(&self, settings: &Settings, document: &mut ConfigDocument) {
+        let config = &settings.enamel_config;
+        let widgets = &self.widgets;
+
+        document.set_if_changed(&["ui", "restore_pages"], config.ui.restore_pages, widgets.restore_pages.get_active());
+        document.set_if_changed(&["crypto", "decrypt"], config.crypto.decrypt, widgets.decrypt.get_active());
+        document.set_if_changed(&["crypto", "index_session_keys"], config.crypto.index_session_keys,
+                                widgets.index_session_keys.get_active());
+        document.set_if_changed(&["crypto", "autocrypt"], config.crypto.autocrypt, widgets.autocrypt.get_active());
+        document.set_if_changed(&["compose", "autosave_interval"], i64::from(config.compose.autosave_interval),
+                                i64::from(widgets.autosave_interval.get_value_as_int()));
+
+        let command = entry_text(&widgets.poll_command);
+        match config.poll.command {
+            Some(_) if command.is_empty() => document.remove(&["poll", "command"]),
+            Some(ref current) => document.set_if_changed(&["poll", "command"], current.as_str(), command.as_str()),
+            None if !command.is_empty() => document.set(&["poll", "command"], command.as_str()),
+            None => ()
+        }
+        document.set_if_changed(&["poll", "interval"], i64::from(config.poll.interval),
+                                i64::from(widgets.poll_interval.get_value_as_int()));
+
+        // colours that did not parse are only replaced when picked anew
+        let colors = [(&config.tags.upper_color, &widgets.tags_upper_color, "upper_color"),
+                      (&config.tags.lower_color, &widgets.tags_lower_color, "lower_color")];
+        for (current, button, key) in colors.iter() {
+            let current = parse_color(current).map(|color| to_hex(&color)).unwrap_or_default();
+            document.set_if_changed(&["tags", *key], current, to_hex(&button.get_rgba()));
+        }
+        document.set_if_changed(&["tags", "alpha"], config.tags.alpha, widgets.tags_alpha.get_value());
+
+        let hidden = split_tags(&entry_text(&widgets.hidden_tags));
+        if hidden != config.tags.hidden {
+            document.set_list(&["tags", "hidden"], &hidden);
+        }
+
+        let pinned = split_tags(&entry_text(&widgets.pinned_tags));
+        if pinned != config.tags.pinned {
+            document.set_list(&["tags", "pinned"], &pinned);
+        }
+
+        let searches: Vec<SavedSearch> = self.searches.iter()
+            .map(|search| SavedSearch{
+                name: entry_text(&search.name),
+                query: entry_text(&search.query)
+            })
+            .filter(|search| !search.name.is_empty() || !search.query.is_empty())
+            .collect();
+        let searches_changed = searches.len() != config.searches.len() ||
+            searches.iter().zip(&config.searches).any(|(new, current)| new.name != current.name || new.query != current.query);
+        if searches_changed {
+            document.set_searches(&searches);
+        }
+
+        let default_key = widgets.default_account.get_active_id().map(|id| id.to_string());
+        for row in &self.accounts {
+            let account = match config.accounts.get(&row.key) {
+                Some(account) => account,
+                None => continue
+            };
+            let key = row.key.as_str();
+
+            document.set_if_changed(&["accounts", key, "name"], account.name.as_str(), entry_text(&row.name).as_str());
+            document.set_if_changed(&["accounts", key, "email"], account.email.as_str(), entry_text(&row.email).as_str());
+            document.set_if_changed(&["accounts", key, "sign"], account.sign, row.sign.get_active());
+
+            let default = default_key.as_ref().map(|id| id == key).unwrap_or(false);
+            if default && !account.default {
+                document.set(&["accounts", key, "default"], true);
+            } else if !default && account.default {
+                document.remove(&["accounts", key, "default"]);
+            }
+        }
+    }
+
+    fn show_error(&self, text: &str) {
+        let dialog = gtk::MessageDialog::new(Some(&self.dialog),
+                                             gtk::DialogFlags::MODAL,
+                                             gtk::MessageType::Error,
+                                             gtk::ButtonsType::Close,
+                                             text);
+        dialog.run();
+        dialog.destroy();
+    }
+
+    fn hide(&mut self) {
+        self.dialog.hide();
+    }
+}
+
+
+impl Update for Preferences {
+    type Model = PreferencesModel;
+    type ModelParam = (Rc<EnamelApp>, gtk::ApplicationWindow);
+    type Msg = Msg;
+
+    fn model(relm: &Relm<Self>, (app, window): Self::ModelParam) -> Self::Model {
+        PreferencesModel {
+            relm: relm.clone(),
+            app,
+            window
+        }
+    }
+
+    fn update(&mut self, msg: Self::Msg) {
+        match msg {
+            Msg::Show => self.show(),
+            Msg::AddSearch => self.add_search("", ""),
+            Msg::RemoveSearch(row) => self.remove_search(row),
+            Msg::Save => self.save(),
+            Msg::Hide => self.hide()
+        }
+    }
+}
+
+
+impl Widget for Preferences {
+
+    type Root = gtk::Window;
+
+    fn root(&self) -> Self::Root {
+        self.dialog.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self
+    {
+        let dialog = gtk::Window::new(gtk::WindowType::Toplevel);
+        dialog.set_transient_for(Some(&model.window));
+        dialog.set_modal(true);
+        dialog.set_position(gtk::WindowPosition::CenterOnParent);
+        dialog.set_default_size(600, 450);
+
+        let stack = gtk::Stack::new();
+        let switcher = gtk::StackSwitcher::new();
+        switcher.set_stack(Some(&stack));
+
+        let cancel = gtk::Button::new_with_label("Cancel");
+        let save = gtk::Button::new_with_label("Save");
+        save.get_style_context().add_class("suggested-action");
+
+        let headerbar = gtk::HeaderBar::new();
+        headerbar.set_custom_title(Some(&switcher));
+        headerbar.pack_start(&cancel);
+        headerbar.pack_end(&save);
+        dialog.set_titlebar(Some(&headerbar));
+
+        // General
+        let grid = new_grid();
+        let restore_pages = new_switch();
+        attach_row(&grid, 0, "Reopen the pages of the last session", &restore_pages);
+        let decrypt = new_switch();
+        attach_row(&grid, 1, "Decrypt messages", &decrypt);
+        let index_session_keys = new_switch();
+        attach_row(&grid, 2, "Remember session keys of decrypted messages", &index_session_keys);
+        let autocrypt = new_switch();
+        attach_row(&grid, 3, "Use Autocrypt", &autocrypt);
+        let autosave_interval = new_spin_button(3600.0);
+        attach_row(&grid, 4, "Save drafts every (seconds)", &autosave_interval);
+        stack.add_titled(&grid, "general", "General");
+
+        // Accounts
+        let default_account = gtk::ComboBoxText::new();
+        let account_box = gtk::Box::new(gtk::Orientation::Vertical, 12);
+        let accounts_grid = new_grid();
+        attach_row(&accounts_grid, 0, "Default account", &default_account);
+        accounts_grid.attach(&account_box, 0, 1, 2, 1);
+        let scrolled_window = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        scrolled_window.add(&accounts_grid);
+        stack.add_titled(&scrolled_window, "accounts", "Accounts");
+
+        // Searches
+        let search_list = gtk::ListBox::new();
+        search_list.set_selection_mode(gtk::SelectionMode::None);
+        let add_search = gtk::Button::new_from_icon_name(Some("list-add-symbolic"), gtk::IconSize::Button);
+        add_search.set_tooltip_text(Some("Add a search"));
+        add_search.set_halign(gtk::Align::Start);
+        let scrolled_window = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        scrolled_window.set_vexpand(true);
+        scrolled_window.add(&search_list);
+        let searches_box = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        searches_box.set_border_width(12);
+        searches_box.pack_start(&scrolled_window, true, true, 0);
+        searches_box.pack_start(&add_search, false, false, 0);
+        stack.add_titled(&searches_box, "searches", "Searches");
+
+        // Polling
+        let grid = new_grid();
+        let poll_command = gtk::Entry::new();
+        poll_command.set_hexpand(true);
+        poll_command.set_placeholder_text(Some("mbsync -a && notmuch new"));
+        attach_row(&grid, 0, "Fetch mail with", &poll_command);
+        let poll_interval = new_spin_button(86400.0);
+        attach_row(&grid, 1, "Every (seconds, 0 to disable)", &poll_interval);
+        stack.add_titled(&grid, "polling", "Polling");
+
+        // Tags
+        let grid = new_grid();
+        let tags_lower_color = gtk::ColorButton::new();
+        tags_lower_color.set_halign(gtk::Align::Start);
+        attach_row(&grid, 0, "Darkest tag colour", &tags_lower_color);
+        let tags_upper_color = gtk::ColorButton::new();
+        tags_upper_color.set_halign(gtk::Align::Start);
+        attach_row(&grid, 1, "Lightest tag colour", &tags_upper_color);
+        let tags_alpha = gtk::SpinButton::new_with_range(0.0, 1.0, 0.05);
+        tags_alpha.set_digits(2);
+        tags_alpha.set_halign(gtk::Align::Start);
+        attach_row(&grid, 2, "Tag opacity", &tags_alpha);
+        let hidden_tags = gtk::Entry::new();
+        hidden_tags.set_hexpand(true);
+        attach_row(&grid, 3, "Hidden in the thread list", &hidden_tags);
+        let pinned_tags = gtk::Entry::new();
+        pinned_tags.set_hexpand(true);
+        attach_row(&grid, 4, "First in the tag list", &pinned_tags);
+        stack.add_titled(&grid, "tags", "Tags");
+
+        dialog.add(&stack);
+
+        connect!(relm, cancel, connect_clicked(_), Msg::Hide);
+        connect!(relm, save, connect_clicked(_), Msg::Save);
+        connect!(relm, add_search, connect_clicked(_), Msg::AddSearch);
+        connect!(relm, dialog, connect_delete_event(_, _), return (Some(Msg::Hide), gtk::Inhibit(true)));
+
+        Preferences {
+            model,
+            dialog,
+            widgets: Widgets {
+                restore_pages,
+                decrypt,
+                index_session_keys,
+                autocrypt,
+                autosave_interval,
+                poll_command,
+                poll_interval,
+                tags_upper_color,
+                tags_lower_color,
+                tags_alpha,
+                hidden_tags,
+                pinned_tags,
+                search_list,
+                default_account,
+                account_box
+            },
+            searches: vec![],
+            accounts: vec![]
+        }
+    }
+}
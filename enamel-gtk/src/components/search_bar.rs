@@ -0,0 +1,108 @@
+use std::rc::Rc;
+
+use glib;
+use gtk;
+use gtk::prelude::*;
+use relm::{Relm, Update, Widget, connect};
+use relm_derive::Msg;
+
+use crate::app::EnamelApp;
+use crate::widgets::address_completion::{attach_address_completion, CompletionMode};
+
+
+#[derive(Msg)]
+pub enum Msg {
+    // inbound
+    Show(Option<String>),
+
+    // private
+    Activate,
+
+    // outbound
+    Search(String)
+}
+
+pub struct SearchBar {
+    model: SearchBarModel,
+    search_bar: gtk::SearchBar,
+    entry: gtk::SearchEntry
+}
+
+pub struct SearchBarModel {
+    relm: Relm<SearchBar>,
+    app: Rc<EnamelApp>
+}
+
+
+impl SearchBar {
+
+    fn show(&mut self, query: Option<String>) {
+        if let Some(query) = query {
+            self.entry.set_text(&query);
+        }
+        self.search_bar.set_search_mode(true);
+        self.entry.grab_focus();
+    }
+
+    fn activate(&mut self) {
+        let query = self.entry.get_text().map(|s| s.to_string()).unwrap_or_default();
+        self.model.relm.stream().emit(Msg::Search(query));
+    }
+}
+
+
+impl Update for SearchBar {
+    type Model = SearchBarModel;
+    type ModelParam = Rc<EnamelApp>;
+    type Msg = Msg;
+
+    fn model(relm: &Relm<Self>, app: Self::ModelParam) -> Self::Model {
+        SearchBarModel {
+            relm: relm.clone(),
+            app
+        }
+    }
+
+    fn update(&mut self, msg: Self::Msg) {
+        match msg {
+            Msg::Show(query) => self.show(query),
+            Msg::Activate => self.activate(),
+            Msg::Search(_) => ()
+        }
+    }
+}
+
+
+impl Widget for SearchBar {
+
+    type Root = gtk::SearchBar;
+
+    fn root(&self) -> Self::Root {
+        self.search_bar.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self
+    {
+        let search_bar = model.app.builder.get_object::<gtk::SearchBar>("search_bar")
+                                          .expect("Couldn't find search_bar in ui file.");
+        let entry = model.app.builder.get_object::<gtk::SearchEntry>("search_entry")
+                                     .expect("Couldn't find search_entry in ui file.");
+        let button = model.app.builder.get_object::<gtk::ToggleButton>("search_conversations_button")
+                                      .expect("Couldn't find search_conversations_button in ui file.");
+
+        search_bar.connect_entry(&entry);
+        button.bind_property("active", &search_bar, "search-mode-enabled")
+              .flags(glib::BindingFlags::BIDIRECTIONAL | glib::BindingFlags::SYNC_CREATE)
+              .build();
+
+        attach_address_completion(entry.upcast_ref::<gtk::Entry>(), model.app.addressbook.clone(), CompletionMode::Query);
+
+        connect!(relm, entry, connect_activate(_), Msg::Activate);
+
+        SearchBar {
+            model,
+            search_bar,
+            entry
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use std::fs;
+use std::rc::Rc;
+use std::path::{Path, PathBuf};
+
+use log::*;
+use gtk;
+use gtk::prelude::*;
+use relm::{Relm, Component, Update, Widget, connect, connect_stream};
+use relm::init as relm_init;
+use relm_derive::Msg;
+
+use notmuch::DatabaseMode;
+use enamel_core::compose::{self, ComposeMessage};
+use enamel_core::database::{Thread, ThreadExtra};
+use enamel_core::settings::Context;
+
+use crate::app::EnamelApp;
+use crate::components::thread_list::{ThreadList, CursorMove, Msg as ThreadListMsg};
+use crate::components::thread_view::{ThreadView, Msg as ThreadViewMsg};
+
+
+#[derive(Msg)]
+pub enum Msg {
+    // inbound
+    /// show the threads of another notmuch query
+    Search(String),
+    /// run the query again, after tags changed
+    Refresh,
+    /// select a thread by id once the thread list has it
+    SelectThread(String),
+    RunAction(Context, &'static str),
+    /// add and remove tags of the marked threads, or the selected thread
+    Tag(Vec<String>, Vec<String>),
+    /// mark the threads that also match a notmuch query
+    Mark(String),
+    SettingsChanged,
+
+    // private
+    ThreadSelect(Thread),
+    MarkedChanged(Vec<String>),
+
+    // outbound
+    OpenThread(Thread),
+    EditDraft(PathBuf),
+    /// open a composer with this message, like a digest of forwarded
+    /// messages
+    Compose(ComposeMessage),
+    /// the tags that were added or removed
+    TagsChanged(Vec<String>),
+}
+
+/// A page with the threads matching a query, and the selected thread next
+/// to them
+pub struct SearchPage {
+    model: SearchPageModel,
+    container: gtk::Paned,
+    threadlist: Component<ThreadList>,
+    threadview: Component<ThreadView>
+}
+
+pub struct SearchPageModel {
+    relm: Relm<SearchPage>,
+    app: Rc<EnamelApp>,
+    query: String,
+    selected_thread: Option<Thread>,
+    /// ids of the marked threads, which bulk actions work on
+    marked: Vec<String>
+}
+
+
+impl SearchPage {
+
+    /// Show the threads matching the query of this page. When the query
+    /// fails, the current list is kept.
+    fn search(&mut self, keep_selection: bool) {
+        let dbman = self.model.app.dbmanager.clone();
+        let db = match dbman.get(DatabaseMode::ReadOnly) {
+            Ok(db) => db,
+            Err(err) => {
+                error!("Could not open database: {:?}", err);
+                self.model.app.show_error(&format!("Could not open the database: {:?}", err));
+                return;
+            }
+        };
+
+        let qs = self.model.app.settings().expand_query(&self.model.query);
+        debug!("qs: {:?}", qs);
+
+        let query = match <notmuch::Database as notmuch::DatabaseExt>::create_query(db, &qs) {
+            Ok(query) => query,
+            Err(err) => {
+                error!("Could not create query {:?}: {:?}", self.model.query, err);
+                self.model.app.show_error(&format!("Could not search for {:?}: {:?}", self.model.query, err));
+                return;
+            }
+        };
+        let threads = match <notmuch::Query<'_> as notmuch::QueryExt>::search_threads(query) {
+            Ok(threads) => threads,
+            Err(err) => {
+                error!("Could not search for {:?}: {:?}", self.model.query, err);
+                self.model.app.show_error(&format!("Could not search for {:?}: {:?}", self.model.query, err));
+                return;
+            }
+        };
+
+        self.threadlist.emit(ThreadListMsg::Update(Some(threads)));
+
+        match self.model.selected_thread.take() {
+            Some(ref thread) if keep_selection => self.threadlist.emit(ThreadListMsg::Select(thread.id().to_string())),
+            _ => ()
+        }
+    }
+
+    fn on_thread_selected(&mut self, thread: Thread) {
+        self.model.selected_thread = Some(thread.clone());
+        self.threadview.emit(ThreadViewMsg::ShowThread(thread))
+    }
+
+    fn run_action(&mut self, context: Context, name: &str) {
+        match (context, name) {
+            (Context::ThreadList, "archive") => self.tag_selected(&[], &["inbox"]),
+            (Context::ThreadList, "toggle-unread") => self.toggle_tag("unread"),
+            (Context::ThreadList, "toggle-flagged") => self.toggle_tag("flagged"),
+            (Context::ThreadList, "delete") => self.tag_selected(&["deleted"], &["inbox"]),
+            (Context::ThreadList, "mark-read") => self.tag_selected(&[], &["unread"]),
+            (Context::ThreadList, "toggle-mark") => self.threadlist.emit(ThreadListMsg::ToggleMark),
+            (Context::ThreadList, "toggle-mark-all") => {
+                if self.model.marked.is_empty() {
+                    self.mark(None);
+                } else {
+                    self.threadlist.emit(ThreadListMsg::UnmarkAll);
+                }
+            },
+            (Context::ThreadList, "save") => self.save(),
+            (Context::ThreadList, "forward-digest") => self.forward_digest(),
+            (Context::ThreadList, "next-thread") | (Context::Global, "down") => self.move_cursor(CursorMove::By(1)),
+            (Context::ThreadList, "previous-thread") | (Context::Global, "up") => self.move_cursor(CursorMove::By(-1)),
+            (Context::ThreadList, "first-thread") => self.move_cursor(CursorMove::First),
+            (Context::ThreadList, "last-thread") => self.move_cursor(CursorMove::Last),
+            (Context::Global, "page-down") => self.move_cursor(CursorMove::Pages(1)),
+            (Context::Global, "page-up") => self.move_cursor(CursorMove::Pages(-1)),
+            (Context::Global, "open") => self.threadlist.emit(ThreadListMsg::OpenSelected),
+            (Context::ThreadList, "forward") => self.forward(),
+            _ => warn!("search page: action {:?} {} is not implemented", context, name)
+        }
+    }
+
+    fn move_cursor(&mut self, movement: CursorMove) {
+        self.threadlist.emit(ThreadListMsg::MoveCursor(movement));
+    }
+
+    /// The threads bulk actions work on: the marked ones, or else the
+    /// selected one
+    fn target_query(&self) -> Option<String> {
+        if !self.model.marked.is_empty() {
+            let threads: Vec<String> = self.model.marked.iter()
+                                                        .map(|thread_id| format!("thread:{}", thread_id))
+                                                        .collect();
+            return Some(threads.join(" or "));
+        }
+
+        self.model.selected_thread.as_ref().map(|thread| format!("thread:{}", thread.id()))
+    }
+
+    /// Mark the threads of this page that also match `query`, or all of
+    /// them
+    fn mark(&mut self, query: Option<&str>) {
+        let settings = self.model.app.settings();
+        let page_query = settings.expand_query(&self.model.query);
+        let qs = match query.map(|query| settings.expand_query(query)) {
+            Some(query) if is_match_all(&page_query) => query,
+            Some(query) => format!("({}) and ({})", page_query, query),
+            None => page_query
+        };
+
+        let dbman = self.model.app.dbmanager.clone();
+        let db = match dbman.get(DatabaseMode::ReadOnly) {
+            Ok(db) => db,
+            Err(err) => {
+                error!("Could not open database: {:?}", err);
+                return;
+            }
+        };
+
+        let query = match db.create_query(&qs) {
+            Ok(query) => query,
+            Err(err) => {
+                error!("Could not create query {:?}: {:?}", qs, err);
+                return;
+            }
+        };
+        let thread_ids = query.search_threads()
+                              .map(|threads| threads.map(|thread| thread.id().to_string()).collect());
+        match thread_ids {
+            Ok(thread_ids) => self.threadlist.emit(ThreadListMsg::Mark(thread_ids)),
+            Err(err) => error!("Could not mark {:?}: {:?}", qs, err)
+        }
+    }
+
+    /// Remove the tag if any of the threads has it, add it otherwise. The
+    /// tags are read from the database, since the threads on the page may
+    /// be older than the last change.
+    fn toggle_tag(&mut self, tag: &str) {
+        let query = match self.target_query() {
+            Some(query) => query,
+            None => return
+        };
+
+        let qs = format!("({}) and tag:{}", query, tag);
+        let count = self.model.app.dbmanager.get(DatabaseMode::ReadOnly).and_then(|db| {
+            let count = db.create_query(&qs)?.count_messages()?;
+            Ok(count)
+        });
+        let has_tag = match count {
+            Ok(count) => count > 0,
+            Err(err) => {
+                error!("Could not look up tags of {:?}: {:?}", query, err);
+                return;
+            }
+        };
+
+        if has_tag {
+            self.tag_selected(&[], &[tag]);
+        } else {
+            self.tag_selected(&[tag], &[]);
+        }
+    }
+
+    /// Change the tags of the marked or selected threads and refresh the
+    /// views
+    fn tag_selected(&mut self, add: &[&str], remove: &[&str]) {
+        let query = match self.target_query() {
+            Some(query) => query,
+            None => return
+        };
+
+        let add: Vec<String> = add.iter().map(|tag| tag.to_string()).collect();
+        let remove: Vec<String> = remove.iter().map(|tag| tag.to_string()).collect();
+
+        match self.model.app.dbmanager.tag(&query, &add, &remove) {
+            Ok(count) => {
+                debug!("changed tags of {} messages", count);
+                // the main window refreshes all pages, this one included
+                self.model.relm.stream().emit(Msg::TagsChanged(add.into_iter().chain(remove).collect()));
+            },
+            Err(err) => error!("Could not change tags: {:?}", err)
+        }
+    }
+
+    /// The message files of the marked or selected threads, oldest first
+    fn target_files(&self) -> Vec<PathBuf> {
+        let query = match self.target_query() {
+            Some(query) => query,
+            None => return vec![]
+        };
+
+        self.model.app.dbmanager.message_files(&query).unwrap_or_else(|err| {
+            error!("Could not find the messages of {:?}: {:?}", query, err);
+            vec![]
+        })
+    }
+
+    /// Copy the message files into a folder the user picks
+    fn save(&mut self) {
+        let files = self.target_files();
+        if files.is_empty() {
+            return;
+        }
+
+        let window = self.container.get_toplevel()
+                                   .and_then(|toplevel| toplevel.downcast::<gtk::Window>().ok());
+        let dialog = gtk::FileChooserDialog::with_buttons(Some("Save messages to"),
+                                                          window.as_ref(),
+                                                          gtk::FileChooserAction::SelectFolder,
+                                                          &[("_Cancel", gtk::ResponseType::Cancel),
+                                                            ("_Save", gtk::ResponseType::Accept)]);
+        let folder = if dialog.run() == gtk::ResponseType::Accept.into() {
+            dialog.get_filename()
+        } else {
+            None
+        };
+        dialog.destroy();
+
+        let folder = match folder {
+            Some(folder) => folder,
+            None => return
+        };
+
+        let mut problems = vec![];
+        for file in &files {
+            if let Err(err) = save_message(file, &folder) {
+                error!("Could not save {:?} to {:?}: {}", file, folder, err);
+                problems.push(format!("{}: {}", file.display(), err));
+            }
+        }
+
+        let saved = files.len() - problems.len();
+        info!("saved {} of {} messages to {:?}", saved, files.len(), folder);
+        if !problems.is_empty() {
+            self.model.app.show_error(&format!("Saved {} of {} messages to {}.\n\n{}",
+                                               saved, files.len(), folder.display(), problems.join("\n")));
+        }
+    }
+
+    fn forward_digest(&mut self) {
+        let files = self.target_files();
+        self.forward_files(&files);
+    }
+
+    /// Open a composer that forwards the messages in `files`
+    fn forward_files(&mut self, files: &[PathBuf]) {
+        if files.is_empty() {
+            return;
+        }
+
+        let settings = self.model.app.settings();
+        let account = match settings.default_account() {
+            Some(account) => account,
+            None => {
+                warn!("No account to forward from");
+                return;
+            }
+        };
+
+        match compose::forward_digest(account, files) {
+            Some(message) => self.model.relm.stream().emit(Msg::Compose(message)),
+            None => self.model.app.show_error(&format!("None of the {} messages could be opened to forward.", files.len()))
+        }
+    }
+
+    /// The message files of the selected thread, oldest first
+    fn thread_files(&self) -> Vec<PathBuf> {
+        let query = match self.model.selected_thread.as_ref() {
+            Some(thread) => format!("thread:{}", thread.id()),
+            None => return vec![]
+        };
+
+        self.model.app.dbmanager.message_files(&query).unwrap_or_else(|err| {
+            error!("Could not find the messages of {:?}: {:?}", query, err);
+            vec![]
+        })
+    }
+
+    /// Forward all messages of the selected thread
+    fn forward(&mut self) {
+        let files = self.thread_files();
+        self.forward_files(&files);
+    }
+}
+
+
+fn is_match_all(query: &str) -> bool {
+    let query = query.trim();
+    query.is_empty() || query == "*"
+}
+
+/// Copy a message file into a folder, under its maildir name. A file that
+/// is there already is left alone.
+fn save_message(file: &Path, folder: &Path) -> std::io::Result<()> {
+    let name = file.file_name().unwrap_or_else(|| file.as_os_str());
+    let mut source = fs::File::open(file)?;
+    let mut target = fs::OpenOptions::new().write(true).create_new(true).open(folder.join(name))?;
+    std::io::copy(&mut source, &mut target)?;
+    Ok(())
+}
+
+
+impl Update for SearchPage {
+    type Model = SearchPageModel;
+    type ModelParam = (Rc<EnamelApp>, String);
+    type Msg = Msg;
+
+    fn model(relm: &Relm<Self>, (app, query): Self::ModelParam) -> Self::Model {
+        SearchPageModel {
+            relm: relm.clone(),
+            app,
+            query,
+            selected_thread: None,
+            marked: vec![]
+        }
+    }
+
+    fn update(&mut self, msg: Self::Msg) {
+        match msg {
+            Msg::Search(query) => {
+                self.model.query = query;
+                self.threadlist.emit(ThreadListMsg::UnmarkAll);
+                self.search(false);
+            },
+            Msg::Refresh => self.search(true),
+            Msg::SelectThread(thread_id) => self.threadlist.emit(ThreadListMsg::Select(thread_id)),
+            Msg::RunAction(context, name) => self.run_action(context, name),
+            Msg::Tag(add, remove) => {
+                let add: Vec<&str> = add.iter().map(|tag| tag.as_str()).collect();
+                let remove: Vec<&str> = remove.iter().map(|tag| tag.as_str()).collect();
+                self.tag_selected(&add, &remove);
+            },
+            Msg::Mark(query) => self.mark(Some(&query)),
+            Msg::SettingsChanged => self.threadlist.emit(ThreadListMsg::SettingsChanged),
+            Msg::ThreadSelect(thread) => self.on_thread_selected(thread),
+            Msg::MarkedChanged(thread_ids) => self.model.marked = thread_ids,
+            Msg::OpenThread(_) | Msg::EditDraft(_) | Msg::Compose(_) | Msg::TagsChanged(_) => ()
+        }
+    }
+}
+
+
+impl Widget for SearchPage {
+
+    type Root = gtk::Paned;
+
+    fn root(&self) -> Self::Root {
+        self.container.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self
+    {
+        let container = gtk::Paned::new(gtk::Orientation::Horizontal);
+
+        let threadlist = relm_init::<ThreadList>(model.app.clone()).unwrap();
+        let threadview = relm_init::<ThreadView>(model.app.clone()).unwrap();
+
+        container.pack1(threadlist.widget(), false, false);
+        container.pack2(threadview.widget(), true, true);
+
+        use self::ThreadListMsg::ThreadSelect as ThreadList_ThreadSelect;
+        connect!(threadlist@ThreadList_ThreadSelect(ref thread), relm, Msg::ThreadSelect(thread.as_ref().unwrap().clone()));
+
+        use self::ThreadListMsg::ThreadOpen as ThreadList_ThreadOpen;
+        connect!(threadlist@ThreadList_ThreadOpen(ref thread), relm, Msg::OpenThread(thread.clone()));
+
+        use self::ThreadListMsg::MarkedChanged as ThreadList_MarkedChanged;
+        connect!(threadlist@ThreadList_MarkedChanged(ref thread_ids), relm, Msg::MarkedChanged(thread_ids.clone()));
+
+        use self::ThreadViewMsg::EditDraft as ThreadView_EditDraft;
+        connect!(threadview@ThreadView_EditDraft(ref path), relm, Msg::EditDraft(path.clone()));
+
+        use self::ThreadViewMsg::Compose as ThreadView_Compose;
+        connect!(threadview@ThreadView_Compose(ref message), relm, Msg::Compose(message.clone()));
+
+        SearchPage {
+            model,
+            container,
+            threadlist,
+            threadview
+        }
+    }
+
+    fn init_view(&mut self) {
+        self.search(false);
+    }
+}
@@ -1,11 +1,18 @@
 use std::rc::Rc;
+use std::thread;
+use std::path::Path;
+use std::collections::{BTreeMap, HashSet};
 
+use log::*;
+use glib;
 use gtk;
 use gtk::prelude::*;
 use relm::{Relm, Update, Widget, connect};
 use relm_derive::Msg;
 
+use notmuch;
 use notmuch::DatabaseMode;
+use enamel_core::settings::{TagDisplay, TagRules};
 
 use crate::app::EnamelApp;
 // pub struct TagList {
@@ -26,59 +33,335 @@ fn append_text_column(tree: &gtk::TreeView, id: i32) {
     column.pack_start(&cell, true);
     // Association of the view's column with the model's `id` column.
     column.add_attribute(&cell, "text", id);
+    column.add_attribute(&cell, "foreground", COLUMN_FOREGROUND as i32);
+    column.add_attribute(&cell, "background", COLUMN_BACKGROUND as i32);
+    column.set_expand(true);
     tree.append_column(&column);
 }
 
+fn append_counts_column(tree: &gtk::TreeView, id: i32) {
+    let column = gtk::TreeViewColumn::new();
+    let cell = gtk::CellRendererText::new();
+    cell.set_alignment(1.0, 0.5);
+
+    column.pack_start(&cell, true);
+    column.add_attribute(&cell, "text", id);
+    tree.append_column(&column);
+}
+
+
+const COLUMN_LABEL:u8 = 0;
+const COLUMN_QUERY:u8 = 1;
+/// colours from `[tags.display]`, if any
+const COLUMN_FOREGROUND:u8 = 2;
+const COLUMN_BACKGROUND:u8 = 3;
+/// unread and total messages, like "3 / 120"
+const COLUMN_COUNTS:u8 = 4;
+/// names a row across refreshes, to expand it again
+const COLUMN_KEY:u8 = 5;
+
+
+/// One level of slash separated tags, like the `foo` in `lists/foo/bar`
+#[derive(Default)]
+struct TagNode {
+    /// whether the path up to here is a tag, not only a group of tags
+    is_tag: bool,
+    children: BTreeMap<String, TagNode>
+}
+
+impl TagNode {
+    fn insert(&mut self, tag: &str) {
+        let node = tag.split('/').fold(self, |node, name| node.children.entry(name.to_string()).or_default());
+        node.is_tag = true;
+    }
+
+    /// This tag and all tags below it
+    fn tags(&self, path: &str) -> Vec<String> {
+        let mut tags = vec![];
+        if self.is_tag {
+            tags.push(path.to_string());
+        }
+        for (name, child) in &self.children {
+            tags.extend(child.tags(&format!("{}/{}", path, name)));
+        }
+        tags
+    }
+}
+
+/// The alias of a tag, or the last part of its name
+fn node_label(display: &TagDisplay, name: &str) -> String {
+    let mut display = display.clone();
+    if display.label == display.tag {
+        display.label = name.to_string();
+    }
+    display.text()
+}
+
+fn tags_query(tags: &[String]) -> String {
+    tags.iter()
+        .map(|tag| tag_term(tag))
+        .collect::<Vec<String>>()
+        .join(" or ")
+}
+
+/// The `tag:` term for `tag`, quoted so tags with spaces, parentheses or
+/// quotes of their own stay one term
+fn tag_term(tag: &str) -> String {
+    format!("tag:\"{}\"", tag.replace('"', "\"\""))
+}
+
+/// Whether a query has a `tag:` term for `tag`, quoted or not
+fn mentions_tag(query: &str, tag: &str) -> bool {
+    let terms = [format!("tag:{}", tag), tag_term(tag)];
+    terms.iter().any(|term| query.match_indices(term.as_str()).any(|(start, _)| {
+        let end = start + term.len();
+        let starts_word = query[..start].chars().last().map_or(true, |c| c.is_whitespace() || c == '(');
+        let ends_word = query[end..].chars().next().map_or(true, |c| c.is_whitespace() || c == ')');
+        starts_word && ends_word
+    }))
+}
+
+/// The unread and total number of messages matching a query
+fn counts(db: &notmuch::Database, query: &str) -> String {
+    let count = |qs: &str| db.create_query(qs).and_then(|query| query.count_messages()).unwrap_or_else(|err| {
+        debug!("taglist: could not count {:?}: {:?}", qs, err);
+        0
+    });
+
+    let total = count(query);
+    let unread = count(&format!("({}) and tag:unread", query));
+
+    if unread > 0 {
+        format!("{} / {}", unread, total)
+    } else {
+        total.to_string()
+    }
+}
+
+/// Messages counted on a background thread
+#[derive(Debug)]
+pub struct Counts {
+    /// the database revision the counts are from
+    revision: u64,
+
+    /// all tags at that revision
+    tags: Vec<String>,
+
+    /// the counts column, by the query of the row
+    counts: Vec<(String, String)>
+}
+
+/// Count the messages of each `(query, expanded query)` with a database
+/// handle of its own
+fn count_queries(db_path: &Path, queries: &[(String, String)]) -> Result<Counts, notmuch::Error> {
+    let db = notmuch::Database::open(&db_path, DatabaseMode::ReadOnly)?;
+
+    Ok(Counts {
+        revision: db.revision().revision as u64,
+        tags: db.all_tags()?.collect(),
+        counts: queries.iter()
+                       .map(|(query, expanded)| (query.clone(), counts(&db, expanded)))
+                       .collect()
+    })
+}
+
 
 #[derive(Msg)]
 pub enum Msg {
     Refresh,
+    /// count the messages again after these tags changed, or all of them
+    UpdateCounts(Option<Vec<String>>),
+    /// the database is at a new revision, maybe changed by another program
+    DatabaseChanged(u64),
+    Counted(Counts),
     SelectionChanged,
-    ItemSelect(Option<String>)
+    /// the query of the selected search or tag
+    ItemSelect(String)
 }
 
 pub struct TagList {
     model: TagListModel,
     scrolled_window: gtk::ScrolledWindow,
     tree_view: gtk::TreeView,
-    tree_model: gtk::ListStore
+    tree_model: gtk::TreeStore
 }
 
 pub struct TagListModel {
     relm: Relm<TagList>,
-    app: Rc<EnamelApp>
+    app: Rc<EnamelApp>,
+
+    /// all tags in the database, as of the last refresh
+    tags: Vec<String>,
+
+    /// the newest database revision messages were counted at
+    counted_revision: u64
 }
 
 impl TagList{
+    /// List the saved searches, then the pinned tags, followed by all other
+    /// tags grouped by their slashes. The tags are those of the last count,
+    /// which rebuilds the list when they changed.
     fn refresh(&mut self){
-        let dbman = self.model.app.dbmanager.clone();
-        let db = dbman.get(DatabaseMode::ReadOnly).unwrap();
-        let mut tags = db.all_tags().unwrap();
-
-        loop {
-         match tags.next() {
-             Some(tag) => {
-                 self.add_tag(&tag);
-             },
-             None => { break }
-         }
+        let expanded = self.expanded_keys();
+        self.tree_model.clear();
+
+        let settings = self.model.app.settings();
+        for search in &settings.saved_searches() {
+            let key = format!("search:{}", search.name);
+            self.add_item(None, &search.name, &search.query, None, &key);
+        }
+
+        let rules = TagRules::new(&settings.enamel_config.tags);
+        let pinned = &settings.enamel_config.tags.pinned;
+
+        let pinned_tags: Vec<&String> = pinned.iter().filter(|tag| self.model.tags.contains(tag)).collect();
+        for tag in pinned_tags {
+            let display = rules.display(tag);
+            if !display.hidden {
+                self.add_item(None, &display.text(), &tag_term(tag), Some(&display), &format!("pinned:{}", tag));
+            }
+        }
+
+        let mut root = TagNode::default();
+        for tag in &self.model.tags {
+            if !pinned.contains(tag) && !rules.display(tag).hidden {
+                root.insert(tag);
+            }
+        }
+
+        for (name, node) in &root.children {
+            self.add_node(None, name, name, node, &rules);
         }
+
+        self.expand_keys(&expanded);
+        self.update_counts(None);
     }
 
+    fn add_node(&self, parent: Option<&gtk::TreeIter>, path: &str, name: &str, node: &TagNode, rules: &TagRules) {
+        let display = rules.display(path);
+        let query = tags_query(&node.tags(path));
+        let iter = self.add_item(parent, &node_label(&display, name), &query, Some(&display), &format!("tag:{}", path));
 
-    fn add_tag(self: &mut Self, tag: &String){
-        let it = self.tree_model.append();
-        self.tree_model.set_value(&it, 0, &tag.to_value());
+        for (child_name, child) in &node.children {
+            self.add_node(Some(&iter), &format!("{}/{}", path, child_name), child_name, child, rules);
+        }
     }
 
-    fn on_selection_changed(self: &mut Self){
-        let (model, iter) = self.tree_view.get_selection().get_selected().unwrap();
+    fn add_item(&self, parent: Option<&gtk::TreeIter>, label: &str, query: &str,
+                display: Option<&TagDisplay>, key: &str) -> gtk::TreeIter {
+        let foreground = display.and_then(|display| display.foreground.as_ref()).map(|color| color.as_str());
+        let background = display.and_then(|display| display.background.as_ref()).map(|color| color.as_str());
+
+        let it = self.tree_model.append(parent);
+        self.tree_model.set_value(&it, COLUMN_LABEL as u32, &label.to_value());
+        self.tree_model.set_value(&it, COLUMN_QUERY as u32, &query.to_value());
+        self.tree_model.set_value(&it, COLUMN_FOREGROUND as u32, &foreground.to_value());
+        self.tree_model.set_value(&it, COLUMN_BACKGROUND as u32, &background.to_value());
+        self.tree_model.set_value(&it, COLUMN_KEY as u32, &key.to_value());
+        it
+    }
+
+    /// Count the messages of the rows with any of `tags` again, or of all
+    /// rows, on a background thread. Unread counts change with any row.
+    fn update_counts(&mut self, tags: Option<Vec<String>>) {
+        let tags = tags.filter(|tags| !tags.iter().any(|tag| tag == "unread"));
+        let settings = self.model.app.settings();
+
+        let mut queries = vec![];
+        self.tree_model.foreach(|model, _path, iter| {
+            let query: Option<String> = model.get_value(iter, COLUMN_QUERY as i32).get();
+            if let Some(query) = query {
+                let expanded = settings.expand_query(&query);
+                let changed = match tags {
+                    Some(ref tags) => tags.iter().any(|tag| mentions_tag(&expanded, tag)),
+                    None => true
+                };
+                if changed {
+                    queries.push((query, expanded));
+                }
+            }
+            false
+        });
+
+        // tags may have come or gone all the same, so the count runs anyway
+        let db_path = settings.notmuch_config.database_path();
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT_IDLE);
+        thread::spawn(move || {
+            let _ = sender.send(count_queries(&db_path, &queries));
+        });
+
+        let stream = self.model.relm.stream().clone();
+        receiver.attach(None, move |counted| {
+            match counted {
+                Ok(counts) => stream.emit(Msg::Counted(counts)),
+                Err(err) => error!("taglist: could not count messages: {:?}", err)
+            }
+            glib::Continue(false)
+        });
+    }
+
+    fn database_changed(&mut self, revision: u64) {
+        if revision > self.model.counted_revision {
+            self.update_counts(None);
+        }
+    }
+
+    /// Show the counts, or build the rows again when tags came or went
+    fn counted(&mut self, counts: Counts) {
+        // a count that started earlier may finish after a newer one
+        if counts.revision < self.model.counted_revision {
+            return;
+        }
+        self.model.counted_revision = counts.revision;
+
+        if counts.tags != self.model.tags {
+            self.model.tags = counts.tags;
+            self.refresh();
+            return;
+        }
+
+        let store = self.tree_model.clone();
+        self.tree_model.foreach(|model, _path, iter| {
+            let query: Option<String> = model.get_value(iter, COLUMN_QUERY as i32).get();
+            let count = query.and_then(|query| counts.counts.iter().find(|(q, _)| *q == query));
+            if let Some((_, count)) = count {
+                store.set_value(iter, COLUMN_COUNTS as u32, &count.to_value());
+            }
+            false
+        });
+    }
 
-        if self.tree_model.iter_is_valid(&iter){
-            let val: String = model.get_value(&iter, 0).get().unwrap();
-            self.model.relm.stream().emit(Msg::ItemSelect(Some(val)));
-        }else{
-            self.model.relm.stream().emit(Msg::ItemSelect(None));
+    /// The keys of the expanded rows
+    fn expanded_keys(&self) -> HashSet<String> {
+        let mut keys = HashSet::new();
+        self.tree_view.map_expanded_rows(|_view, path| {
+            let key: Option<String> = self.tree_model.get_iter(path)
+                                                     .and_then(|iter| self.tree_model.get_value(&iter, COLUMN_KEY as i32).get());
+            keys.extend(key);
+        });
+        keys
+    }
+
+    fn expand_keys(&self, keys: &HashSet<String>) {
+        if keys.is_empty() {
+            return;
+        }
+
+        // parents come before their children, so those can expand too
+        self.tree_model.foreach(|model, path, iter| {
+            let key: Option<String> = model.get_value(iter, COLUMN_KEY as i32).get();
+            if key.map(|key| keys.contains(&key)).unwrap_or(false) {
+                self.tree_view.expand_row(path, false);
+            }
+            false
+        });
+    }
+
+    fn on_selection_changed(self: &mut Self){
+        // nothing is selected while the list is refreshed
+        if let Some((model, iter)) = self.tree_view.get_selection().get_selected() {
+            let query: String = model.get_value(&iter, COLUMN_QUERY as i32).get().unwrap();
+            self.model.relm.stream().emit(Msg::ItemSelect(query));
         }
     }
 }
@@ -92,13 +375,18 @@ impl Update for TagList {
     fn model(relm: &Relm<Self>, app: Self::ModelParam) -> Self::Model {
         TagListModel {
             relm: relm.clone(),
-            app
+            app,
+            tags: vec![],
+            counted_revision: 0
         }
     }
 
     fn update(&mut self, event: Self::Msg) {
         match event {
             Msg::Refresh => self.refresh(),
+            Msg::UpdateCounts(tags) => self.update_counts(tags),
+            Msg::DatabaseChanged(revision) => self.database_changed(revision),
+            Msg::Counted(counts) => self.counted(counts),
             Msg::SelectionChanged => self.on_selection_changed(),
             Msg::ItemSelect(_) => ()
         }
@@ -120,10 +408,13 @@ impl Widget for TagList {
         let scrolled_window = model.app.builder.get_object::<gtk::ScrolledWindow>("tag_list_scrolled")
                                                .expect("Couldn't find tag_list_scrolled in ui file.");
 
-        let tree_model = gtk::ListStore::new(&[String::static_type()]);
+        let tree_model = gtk::TreeStore::new(&[String::static_type(), String::static_type(),
+                                               String::static_type(), String::static_type(),
+                                               String::static_type(), String::static_type()]);
         let tree_view = gtk::TreeView::new_with_model(&tree_model);
         tree_view.set_headers_visible(false);
-        append_text_column(&tree_view, 0);
+        append_text_column(&tree_view, COLUMN_LABEL as i32);
+        append_counts_column(&tree_view, COLUMN_COUNTS as i32);
 
         scrolled_window.add(&tree_view);
 
@@ -138,3 +429,15 @@ impl Widget for TagList {
     }
 }
 
+
+#[test]
+fn test_tags_query() {
+    let tags = vec!["lists/rust".to_string(), "to do".to_string(), "say \"hi\"".to_string()];
+    let query = tags_query(&tags);
+    assert_eq!(query, "tag:\"lists/rust\" or tag:\"to do\" or tag:\"say \"\"hi\"\"\"");
+
+    assert!(mentions_tag(&query, "to do"));
+    assert!(mentions_tag(&query, "say \"hi\""));
+    assert!(mentions_tag("tag:inbox and not tag:spam", "spam"));
+    assert!(!mentions_tag(&query, "rust"));
+}
@@ -1,5 +1,6 @@
 use std::rc::Rc;
 use std::sync::{Arc};
+use std::collections::HashSet;
 
 use log::*;
 use glib;
@@ -22,17 +23,20 @@ use crate::widgets::thread_list_cell_renderer::CellRendererThread;
 
 const COLUMN_ID:u8 = 0;
 const COLUMN_THREAD:u8 = 1;
-const COLUMN_AUTHORS:u8 = 2;
+/// whether the thread is marked for a bulk action
+const COLUMN_MARKED:u8 = 2;
 
 
-fn append_text_column(tree: &gtk::TreeView, id: i32, title: &str) {
+fn append_text_column(tree: &gtk::TreeView, id: i32, title: &str) -> CellRendererThread {
     let column = gtk::TreeViewColumn::new();
     let cell = CellRendererThread::new();
     column.pack_start(&cell, false);
     // Association of the view's column with the model's `id` column.
     column.add_attribute(&cell, "thread", id);
+    column.add_attribute(&cell, "marked", COLUMN_MARKED as i32);
     column.set_title(&title);
     tree.append_column(&column);
+    cell
 }
 
 pub fn gtk_idle_add<F: Fn() -> MSG + 'static, MSG: 'static>(stream: &EventStream<MSG>, constructor: F, single_shot:Option<bool>) -> glib::source::SourceId {
@@ -52,13 +56,30 @@ pub fn gtk_idle_add<F: Fn() -> MSG + 'static, MSG: 'static>(stream: &EventStream
 pub enum Msg {
     // outbound
     ThreadSelect(Option<Thread>),
+    /// double click or enter on a thread
+    ThreadOpen(Thread),
+    /// the ids of the marked threads, after marks changed
+    MarkedChanged(Vec<String>),
 
     // inbound
     /// signals a request to update the event list. String is a notmuch query string
     Update(Option<Threads>),
+    /// select the thread with this id, as soon as it is loaded
+    Select(String),
+    /// draw the threads with the current tag settings
+    SettingsChanged,
+    /// mark the selected thread, or unmark it
+    ToggleMark,
+    /// mark the threads with these ids, once they are loaded
+    Mark(Vec<String>),
+    UnmarkAll,
+    MoveCursor(CursorMove),
+    /// open the selected thread, like a double click
+    OpenSelected,
 
     // private
     ItemSelect,
+    ItemActivate(gtk::TreePath),
     AsyncFetch(AsyncFetchEvent)
 }
 
@@ -71,12 +92,25 @@ pub enum AsyncFetchEvent{
 }
 
 
+/// Where to move the cursor, which selects the thread there
+#[derive(Clone, Copy, Debug)]
+pub enum CursorMove {
+    /// by a number of threads, negative to move up
+    By(i32),
+    /// by a number of screens full of threads
+    Pages(i32),
+    First,
+    Last,
+}
+
+
 pub struct ThreadList{
     model: ThreadListModel,
     scrolled_window: gtk::ScrolledWindow,
     tree_view: gtk::TreeView,
     tree_filter: gtk::TreeModelFilter,
-    tree_model: gtk::ListStore
+    tree_model: gtk::ListStore,
+    cell: CellRendererThread
 
 }
 
@@ -86,6 +120,12 @@ pub struct ThreadListModel {
 
     idle_handle: Option<glib::SourceId>,
     thread_list: Option<Arc<Threads>>,
+    select_thread: Option<String>,
+    /// kept across updates, so marks survive a refresh after tagging
+    marked: HashSet<String>,
+    /// ids of the threads added since the last update, to drop the marks
+    /// of threads that no longer match once all are loaded
+    loaded: HashSet<String>,
 
     num_threads: u32,
     num_threads_loaded: u32
@@ -94,7 +134,7 @@ pub struct ThreadListModel {
 
 
 fn create_liststore() -> gtk::ListStore{
-    gtk::ListStore::new(&[String::static_type(), Thread::static_type()])
+    gtk::ListStore::new(&[String::static_type(), Thread::static_type(), bool::static_type()])
 }
 
 impl ThreadList{
@@ -108,6 +148,10 @@ impl ThreadList{
         self.tree_view.set_model(Some(&self.tree_model));
 
         self.model.thread_list = threads.map(Arc::new);
+        self.model.loaded.clear();
+        if self.model.thread_list.is_none() {
+            self.prune_marks();
+        }
 
 
         // // let do_run = run.clone();
@@ -125,14 +169,93 @@ impl ThreadList{
     fn add_thread(&mut self, thread: Thread){
 
         let thread_id = thread.id().clone();
+        self.model.loaded.insert(thread_id.clone());
 
-        self.tree_model.insert_with_values(None,
+        let iter = self.tree_model.insert_with_values(None,
             &[COLUMN_ID as u32,
-              COLUMN_THREAD as u32
+              COLUMN_THREAD as u32,
+              COLUMN_MARKED as u32
             ],
             &[&thread_id.to_value(),
-              &thread
+              &thread,
+              &self.model.marked.contains(&thread_id)
             ]);
+
+        if self.model.select_thread.as_ref() == Some(&thread_id) {
+            self.model.select_thread = None;
+
+            // moving the cursor selects the thread like a click would
+            if let Some(path) = self.tree_model.get_path(&iter) {
+                self.tree_view.set_cursor(&path, None::<&gtk::TreeViewColumn>, false);
+            }
+        }
+    }
+
+    fn toggle_mark(&mut self){
+        let thread_id: String = match self.tree_view.get_selection().get_selected() {
+            Some((list_model, iter)) => list_model.get_value(&iter, COLUMN_ID as i32).get().unwrap(),
+            None => return
+        };
+
+        if !self.model.marked.remove(&thread_id) {
+            self.model.marked.insert(thread_id);
+        }
+        self.set_marks();
+    }
+
+    fn mark(&mut self, thread_ids: Vec<String>){
+        self.model.marked.extend(thread_ids);
+        self.set_marks();
+    }
+
+    /// Unmark the threads that are gone from the list, like those archived
+    /// from the inbox by a bulk action
+    fn prune_marks(&mut self){
+        let loaded = &self.model.loaded;
+        let count = self.model.marked.len();
+        self.model.marked.retain(|thread_id| loaded.contains(thread_id));
+        if self.model.marked.len() != count {
+            self.set_marks();
+        }
+    }
+
+    /// Show the marks on the loaded threads and tell which threads are marked
+    fn set_marks(&self){
+        let marked = &self.model.marked;
+        self.tree_model.foreach(|list_model, _path, iter| {
+            let thread_id: Option<String> = list_model.get_value(iter, COLUMN_ID as i32).get();
+            let is_marked = thread_id.map(|id| marked.contains(&id)).unwrap_or(false);
+            self.tree_model.set_value(iter, COLUMN_MARKED as u32, &is_marked.to_value());
+            false
+        });
+
+        let thread_ids = marked.iter().cloned().collect();
+        self.model.relm.stream().emit(Msg::MarkedChanged(thread_ids));
+    }
+
+    /// Move the cursor within the loaded threads
+    fn move_cursor(&mut self, movement: CursorMove){
+        let count = self.tree_model.iter_n_children(None);
+        if count == 0 {
+            return;
+        }
+
+        let current = self.tree_view.get_cursor().0
+                          .and_then(|path| path.get_indices().first().cloned());
+        let page = self.tree_view.get_visible_range()
+                       .and_then(|(start, end)| Some(end.get_indices().first()? - start.get_indices().first()?))
+                       .unwrap_or(1)
+                       .max(1);
+
+        let row = match movement {
+            CursorMove::By(n) => current.map_or(0, |row| row + n),
+            CursorMove::Pages(n) => current.map_or(0, |row| row + n * page),
+            CursorMove::First => 0,
+            CursorMove::Last => count - 1
+        };
+
+        let path = gtk::TreePath::new_from_indicesv(&[row.max(0).min(count - 1)]);
+        self.tree_view.set_cursor(&path, None::<&gtk::TreeViewColumn>, false);
     }
 
     fn next_thread(&mut self){
@@ -143,6 +266,8 @@ impl ThreadList{
         if let Some(thread) = Arc::get_mut(self.model.thread_list.as_mut().unwrap()).unwrap().next(){
             gtk_idle_add(self.model.relm.stream(), || Msg::AsyncFetch(AsyncFetchEvent::Init), Some(true));
             self.add_thread(Thread::new(thread));
+        } else {
+            self.prune_marks();
         }
 
     }
@@ -162,6 +287,9 @@ impl Update for ThreadList {
             app,
 
             thread_list: None,
+            select_thread: None,
+            marked: HashSet::new(),
+            loaded: HashSet::new(),
             idle_handle: None,
             num_threads: 0,
             num_threads_loaded: 0
@@ -171,6 +299,23 @@ impl Update for ThreadList {
     fn update(&mut self, msg: Self::Msg) {
         match msg {
             Msg::Update(threads) => self.update(threads),
+            Msg::Select(thread_id) => self.model.select_thread = Some(thread_id),
+            Msg::SettingsChanged => {
+                self.cell.set_tags_config(self.model.app.settings().enamel_config.tags.clone());
+                self.tree_view.queue_draw();
+            },
+            Msg::ToggleMark => self.toggle_mark(),
+            Msg::Mark(thread_ids) => self.mark(thread_ids),
+            Msg::UnmarkAll => {
+                self.model.marked.clear();
+                self.set_marks();
+            },
+            Msg::MoveCursor(movement) => self.move_cursor(movement),
+            Msg::OpenSelected => {
+                if let (Some(path), _) = self.tree_view.get_cursor() {
+                    self.model.relm.stream().emit(Msg::ItemActivate(path));
+                }
+            },
             Msg::ItemSelect => {
                 let selection = self.tree_view.get_selection();
                 if let Some((list_model, iter)) = selection.get_selected() {
@@ -181,7 +326,17 @@ impl Update for ThreadList {
                     self.model.relm.stream().clone().emit(Msg::ThreadSelect(Some(thread.clone())));
                 }
             },
+            Msg::ItemActivate(path) => {
+                if let Some(iter) = self.tree_model.get_iter(&path) {
+                    let lval = self.tree_model.get_value(&iter, COLUMN_THREAD as i32);
+                    let thread = lval.get::<&Thread>().unwrap();
+
+                    self.model.relm.stream().clone().emit(Msg::ThreadOpen(thread.clone()));
+                }
+            },
             Msg::ThreadSelect(ref _thread_id) => (),
+            Msg::ThreadOpen(ref _thread) => (),
+            Msg::MarkedChanged(_) => (),
             Msg::AsyncFetch(AsyncFetchEvent::Init) => self.next_thread(),
             Msg::AsyncFetch(AsyncFetchEvent::Complete) => ()
 
@@ -200,8 +355,9 @@ impl Widget for ThreadList {
 
     fn view(relm: &Relm<Self>, model: Self::Model) -> Self
     {
-        let scrolled_window = model.app.builder.get_object::<gtk::ScrolledWindow>("thread_list_scrolled")
-                                               .expect("Couldn't find thread_list_scrolled in ui file.");
+        // every search page has its own thread list
+        let scrolled_window = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        scrolled_window.set_size_request(250, -1);
 
         let tree_model = create_liststore();
         let tree_filter = gtk::TreeModelFilter::new(&tree_model, None);
@@ -209,18 +365,21 @@ impl Widget for ThreadList {
 
 
         tree_view.set_headers_visible(false);
-        append_text_column(&tree_view, COLUMN_THREAD as i32, "Thread");
+        let cell = append_text_column(&tree_view, COLUMN_THREAD as i32, "Thread");
+        cell.set_tags_config(model.app.settings().enamel_config.tags.clone());
 
         scrolled_window.add(&tree_view);
 
         connect!(relm, tree_view, connect_cursor_changed(_), Msg::ItemSelect);
+        connect!(relm, tree_view, connect_row_activated(_, path, _), Msg::ItemActivate(path.clone()));
 
         ThreadList {
             model,
             scrolled_window,
             tree_view,
             tree_filter,
-            tree_model
+            tree_model,
+            cell
         }
     }
 }
@@ -0,0 +1,70 @@
+use glib;
+
+use enamel_core::mime::crypto::{Crypto, CryptoProtocol};
+
+
+/// Status banner shown above a signed or encrypted part, filled into the
+/// `encrypt_template` of thread_view.html
+#[derive(Clone, Debug)]
+pub struct CryptoBanner {
+    /// id of the chunk the banner belongs to
+    pub chunk_id: usize,
+
+    /// classes for the container, styled in thread_view.scss
+    pub classes: Vec<&'static str>,
+    pub html: String,
+}
+
+
+impl CryptoBanner {
+
+    pub fn new(chunk_id: usize, crypto: &Crypto) -> Self {
+        let mut classes = vec!["encrypt_container"];
+        let mut title = vec![];
+
+        if crypto.encrypted {
+            classes.push("encrypted");
+            if crypto.decrypted {
+                title.push("Encrypted");
+            } else {
+                classes.push("decrypt_failed");
+                title.push("Encrypted, could not decrypt");
+            }
+        }
+
+        if crypto.signed {
+            classes.push("signed");
+            if crypto.verified {
+                title.push("Signed");
+            } else {
+                classes.push("verify_failed");
+                title.push("Signature not verified");
+            }
+        }
+
+        let lines: Vec<String> = crypto.describe()
+                                       .iter()
+                                       .map(|line| glib::markup_escape_text(line).to_string())
+                                       .collect();
+
+        let protocol = match crypto.protocol {
+            CryptoProtocol::OpenPgp => "OpenPGP",
+            CryptoProtocol::Smime => "S/MIME"
+        };
+
+        let html = format!("<span class=\"header\">{}: {}</span>{}",
+                           protocol,
+                           title.join(" and "),
+                           lines.join("<br />"));
+
+        CryptoBanner{
+            chunk_id,
+            classes,
+            html
+        }
+    }
+
+    pub fn class_name(&self) -> String {
+        self.classes.join(" ")
+    }
+}
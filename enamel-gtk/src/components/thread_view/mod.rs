@@ -1,21 +1,25 @@
 use std::rc::Rc;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::thread;
 use std::process;
 use std::cell::Cell;
 use std::path::Path;
+use std::str::FromStr;
 use serde_derive::{Serialize, Deserialize};
 use log::*;
 use gio;
 use gio::SocketListenerExt;
 
 use glib;
+use gdk;
 use gtk;
 use gtk::prelude::*;
 use webkit2gtk;
-use webkit2gtk::{SettingsExt, WebViewExt, WebContextExt, PolicyDecisionExt, NavigationPolicyDecisionExt, URIRequestExt};
+use webkit2gtk::{SettingsExt, WebViewExt, WebContextExt, PolicyDecisionExt, NavigationPolicyDecisionExt, URIRequestExt, FindControllerExt};
 use std::sync::mpsc::{channel, Receiver};
 use gmime;
-use gmime::{ParserExt, PartExt};
+use gmime::{ParserExt, PartExt, MessageExt, ObjectExt, InternetAddressListExt};
 use bincode;
 use relm::{Relm, Widget, Update, connect, connect_stream, connect_async, connect_async_full};
 use relm_derive::Msg;
@@ -27,23 +31,54 @@ use toml;
 use notmuch;
 
 use enamel_core::database::Thread;
+use enamel_core::compose::{self, ComposeMessage, TAG_DRAFT};
+use enamel_core::mime::{MimeMessage, ParseOptions};
+use enamel_core::mime::render;
+use enamel_core::settings::{Context, TagRules};
 use crate::app::EnamelApp;
+use crate::widgets::util::concat_tags_color;
 
 mod page_client;
 use page_client::PageClient;
 
+mod crypto;
+use self::crypto::CryptoBanner;
+
 
 pub struct ThreadView{
     model: ThreadViewModel,
     container: gtk::Box,
-    webview: webkit2gtk::WebView
+    webview: webkit2gtk::WebView,
+    find_bar: gtk::SearchBar,
+    find_entry: gtk::SearchEntry
 }
 
 pub struct ThreadViewModel {
     relm: Relm<ThreadView>,
     app: Rc<EnamelApp>,
     webcontext: webkit2gtk::WebContext,
-    page_client: Option<PageClient>
+    page_client: Option<PageClient>,
+
+    /// thread_view.html is loaded, so messages can be put in it
+    page_loaded: bool,
+
+    /// drafts in the current thread, by message id
+    drafts: HashMap<String, PathBuf>,
+
+    /// parsed messages of the current thread, by message id
+    messages: Vec<(String, MimeMessage)>,
+
+    /// message files of the current thread, by message id
+    files: HashMap<String, PathBuf>,
+
+    /// index in `messages` of the message actions work on
+    focused: usize,
+
+    /// signature and encryption banners, by message id
+    crypto_banners: HashMap<String, Vec<CryptoBanner>>,
+
+    /// the tags shown in the message headers, as html, by message id
+    tag_strings: HashMap<String, String>
 }
 
 
@@ -58,7 +93,15 @@ pub enum Msg {
     ReadyToRender,
     DecidePolicy(webkit2gtk::PolicyDecision, webkit2gtk::PolicyDecisionType),
     
-    ShowThread(Thread)
+    ShowThread(Thread),
+    RunAction(Context, &'static str),
+    FindChanged,
+    FindStopped,
+
+    // outbound
+    EditDraft(PathBuf),
+    /// open a composer with this message, like a forward
+    Compose(ComposeMessage)
 }
 
 
@@ -97,6 +140,73 @@ impl ThreadViewModel{
 }
 
 
+/// The tags of a message as html, coloured for the white page background
+fn tag_string(tags: &[String], rules: &TagRules) -> String {
+    let canvas = gdk::RGBA::from_str("#ffffff").unwrap();
+    concat_tags_color(tags, false, 0, rules, &canvas)
+}
+
+
+fn escape(text: &str) -> String {
+    glib::markup_escape_text(text).to_string()
+}
+
+/// `text` as a javascript string literal
+fn js_string(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\u{2028}' => literal.push_str("\\u2028"),
+            '\u{2029}' => literal.push_str("\\u2029"),
+            '<' => literal.push_str("\\x3c"),
+            c => literal.push(c)
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// A segment of a body as html. Quotes and the signature are folded, and
+/// shown with toggle-quotes or by clicking them.
+fn segment_html(segment: &render::Segment) -> String {
+    let text = escape(&segment.lines().join("\n"));
+    let (kind, text) = match segment {
+        render::Segment::Text(_) => return format!("<pre>{}</pre>", text),
+        render::Segment::Quote{ .. } => ("quote", text),
+        render::Segment::Signature(_) => ("signature", format!("<span class=\"signature\">{}</span>", text))
+    };
+
+    format!("<div class=\"quote_container controllable\" onclick=\"this.classList.toggle('show')\">\
+             <div class=\"shower\">[ show {kind} ]</div><div class=\"hider\">[ hide {kind} ]</div>\
+             <div class=\"quote\"><pre>{text}</pre></div></div>",
+            kind = kind, text = text)
+}
+
+/// The header fields shown above a message body
+fn header_fields(message: &MimeMessage) -> Vec<(&'static str, String)> {
+    let addresses = |address_type| {
+        message.message.get_addresses(address_type)
+                       .and_then(|list| list.to_string(None, false))
+                       .map(|s| s.to_string())
+                       .unwrap_or_default()
+    };
+
+    let fields = vec![
+        ("From", addresses(gmime::AddressType::From)),
+        ("To", addresses(gmime::AddressType::To)),
+        ("Cc", addresses(gmime::AddressType::Cc)),
+        ("Date", message.message.get_header("Date").map(|s| s.to_string()).unwrap_or_default()),
+    ];
+
+    fields.into_iter().filter(|(_, value)| !value.is_empty()).collect()
+}
+
+
 impl ThreadView{
 
     fn extension_connected(&mut self, conn: gio::SocketConnection, obj: glib::Object){
@@ -110,9 +220,8 @@ impl ThreadView{
 
         match event{
             webkit2gtk::LoadEvent::Finished => {
-                if self.model.page_client.is_some(){
-                    self.model.relm.stream().emit(Msg::ReadyToRender);
-                }
+                self.model.page_loaded = true;
+                self.model.relm.stream().emit(Msg::ReadyToRender);
             },
             _ => ()
         }
@@ -152,43 +261,251 @@ impl ThreadView{
 
 
         debug!("Showing thread {:?}", thread);
+        self.model.drafts.clear();
+        self.model.messages.clear();
+        self.model.files.clear();
+        self.model.crypto_banners.clear();
+        self.model.tag_strings.clear();
         let messages = thread.messages();
+        let tag_rules = TagRules::new(&self.model.app.settings().enamel_config.tags);
 
         debug!("Showing thread {:?} > messages {:?}", thread, messages);
+        let mut first_unread = None;
         for msg in messages{
             let fname = msg.filename();
             info!("message: {:?}", fname);
 
-            let stream = gmime::StreamFile::open(&fname.to_string_lossy(), &"r").unwrap();
-            let parser = gmime::Parser::new_with_stream(&stream);
-            let mmsg = parser.construct_message(None);
+            if msg.tags().any(|tag| tag == TAG_DRAFT) {
+                self.model.drafts.insert(msg.id().to_string(), fname.clone());
+            }
+
+            let options = ParseOptions{
+                decrypt: self.model.app.settings().enamel_config.crypto.decrypt,
+                session_keys: msg.properties("session-key", true)
+                                 .map(|(_, value)| value)
+                                 .collect()
+            };
+
+            let message = match MimeMessage::open(&fname, &options) {
+                Some(message) => message,
+                None => {
+                    error!("tv: could not parse message {:?}", fname);
+                    continue;
+                }
+            };
+
+            let message_id = msg.id().to_string();
+            if first_unread.is_none() && msg.tags().any(|tag| tag == "unread") {
+                first_unread = Some(self.model.messages.len());
+            }
+            let tags: Vec<String> = msg.tags().filter(|tag| !tag_rules.display(tag).hidden).collect();
+            self.model.tag_strings.insert(message_id.clone(), tag_string(&tags, &tag_rules));
+
+            self.add_crypto_banners(&message_id, &message);
+            self.store_session_keys(&message_id, &message);
+
+            self.model.files.insert(message_id.clone(), fname.clone());
+            self.model.messages.push((message_id, message));
+        }
+
+        // start at the first unread message, or else the newest
+        self.model.focused = first_unread.unwrap_or_else(|| self.model.messages.len().saturating_sub(1));
+        self.render_messages();
+    }
+
+    fn add_crypto_banners(&mut self, message_id: &str, message: &MimeMessage) {
+        let banners: Vec<CryptoBanner> = message.crypto_chunks()
+                                                .iter()
+                                                .filter_map(|chunk| chunk.crypto.as_ref().map(|c| CryptoBanner::new(chunk.id, c)))
+                                                .collect();
+
+        for banner in &banners {
+            debug!("tv: {} part {}: {}", message_id, banner.chunk_id, banner.class_name());
+        }
+
+        if !banners.is_empty() {
+            self.model.crypto_banners.insert(message_id.to_string(), banners);
+        }
+    }
+
+    /// Keep session keys of freshly decrypted messages, if configured
+    fn store_session_keys(&self, message_id: &str, message: &MimeMessage) {
+        if !self.model.app.settings().enamel_config.crypto.index_session_keys {
+            return;
+        }
+
+        for session_key in message.new_session_keys() {
+            if let Err(err) = self.model.app.dbmanager.add_session_key(message_id, &session_key) {
+                error!("tv: could not store session key for {}: {:?}", message_id, err);
+            }
+        }
+    }
+
 
-            info!("created mime message: {:?}", mmsg);
+    /// Put the messages of the thread in the page, once it is loaded
+    fn render_messages(&mut self){
+        if !self.model.page_loaded {
+            return;
+        }
+
+        let html: String = self.model.messages.iter()
+                                              .map(|(message_id, message)| self.message_html(message_id, message))
+                                              .collect();
+
+        let script = format!("document.getElementById('message_container').innerHTML = {};", js_string(&html));
+        self.run_script(&script);
+        self.focus_message(self.model.focused);
+    }
+
+    fn run_script(&self, script: &str) {
+        self.webview.run_javascript(script, None::<&gio::Cancellable>, |result| {
+            if let Err(err) = result {
+                error!("tv: could not run script: {:?}", err);
+            }
+        });
+    }
+
+    /// A message in the markup of the `email_template` of thread_view.html
+    fn message_html(&self, message_id: &str, message: &MimeMessage) -> String {
+        let index = self.model.messages.iter().position(|(id, _)| id == message_id).unwrap_or(0);
+        let mut html = String::new();
+        html.push_str(&format!("<div id=\"message_{}\" class=\"email\"><div class=\"email_container\">", index));
+
+        html.push_str("<div class=\"header_container\"><div class=\"header\">");
+        for (title, value) in header_fields(message) {
+            html.push_str(&format!("<div class=\"field\"><span class=\"title\">{}</span> <span class=\"value\">{}</span></div>",
+                                   title, escape(&value)));
+        }
+        html.push_str("</div>");
+        let subject = message.message.get_subject().map(|s| s.to_string()).unwrap_or_default();
+        if let Some(tags) = self.model.tag_strings.get(message_id) {
+            html.push_str(&format!("<div class=\"tags\">{}</div>", tags));
+        }
+        html.push_str(&format!("<div class=\"subject\">{}</div>", escape(&subject)));
+        html.push_str("</div>");
 
-            let mut partiter = gmime::PartIter::new(&mmsg.unwrap());
+        for banner in self.model.crypto_banners.get(message_id).into_iter().flatten() {
+            html.push_str(&format!("<div class=\"{}\"><div class=\"message\">{}</div></div>",
+                                   banner.class_name(), banner.html));
+        }
+
+        if let Some(text) = render::body_text(&message.root) {
+            html.push_str("<div class=\"body\"><div class=\"body_part\">");
+            for segment in render::segments(text.trim_end()) {
+                html.push_str(&segment_html(&segment));
+            }
+            html.push_str("</div></div>");
+        }
+
+        if self.model.drafts.contains_key(message_id) {
+            html.push_str(&format!("<div class=\"draft_edit\"><a class=\"draft_edit_button button\" href=\"draft:{}\">Edit draft</a></div>",
+                                   glib::uri_escape_string(message_id, None, false)));
+        }
 
-            let mut hasnext = partiter.next();
-            while hasnext {
-                let current = partiter.get_current().unwrap();
-                let parent = partiter.get_parent().unwrap();
+        html.push_str("</div></div>");
+        html
+    }
 
-                let p = parent.downcast::<gmime::Multipart>();
-                let part = current.downcast::<gmime::Part>();
 
-                if p.is_ok() && part.is_ok() {
-                    if part.unwrap().is_attachment(){
-                        debug!("Found attachment");
-                    }
+    fn run_action(&mut self, context: Context, name: &str) {
+        match (context, name) {
+            (Context::ThreadView, "next-message") => self.focus_message(self.model.focused + 1),
+            (Context::ThreadView, "previous-message") => {
+                if self.model.focused > 0 {
+                    self.focus_message(self.model.focused - 1);
+                }
+            },
+            (Context::ThreadView, "scroll-down") | (Context::Global, "down") => self.run_script("window.scrollBy(0, 40);"),
+            (Context::ThreadView, "scroll-up") | (Context::Global, "up") => self.run_script("window.scrollBy(0, -40);"),
+            (Context::Global, "page-down") => self.run_script("window.scrollBy(0, window.innerHeight * 0.9);"),
+            (Context::Global, "page-up") => self.run_script("window.scrollBy(0, -window.innerHeight * 0.9);"),
+            (Context::ThreadView, "toggle-expand") | (Context::Global, "open") => {
+                self.run_script(&format!("document.getElementById('message_{}').classList.toggle('hide');", self.model.focused));
+            },
+            (Context::ThreadView, "toggle-quotes") => {
+                self.run_script(&format!("document.querySelectorAll('#message_{} .quote_container.controllable')\
+                                          .forEach(function(e) {{ e.classList.toggle('show'); }});", self.model.focused));
+            },
+            (Context::ThreadView, "find") => {
+                self.find_bar.set_search_mode(true);
+                self.find_entry.grab_focus();
+            },
+            (Context::ThreadView, "find-next") => {
+                if let Some(controller) = self.webview.get_find_controller() {
+                    controller.search_next();
                 }
-                hasnext = partiter.next()
+            },
+            (Context::ThreadView, "find-previous") => {
+                if let Some(controller) = self.webview.get_find_controller() {
+                    controller.search_previous();
+                }
+            },
+            (Context::ThreadView, "forward") => self.forward(),
+            _ => warn!("tv: action {:?} {} is not implemented", context, name)
+        }
+    }
+
+    /// Move the focus to the message at `index`, and scroll to it
+    fn focus_message(&mut self, index: usize) {
+        if index >= self.model.messages.len() {
+            return;
+        }
+
+        self.model.focused = index;
+        self.run_script(&format!("document.querySelectorAll('.email.focused')\
+                                      .forEach(function(e) {{ e.classList.remove('focused'); }});\
+                                  var focused = document.getElementById('message_{}');\
+                                  focused.classList.add('focused');\
+                                  focused.scrollIntoView();", index));
+    }
+
+    fn focused_file(&self) -> Option<PathBuf> {
+        let (message_id, _) = self.model.messages.get(self.model.focused)?;
+        self.model.files.get(message_id).cloned()
+    }
+
+    fn forward(&mut self) {
+        let file = match self.focused_file() {
+            Some(file) => file,
+            None => return
+        };
+
+        let settings = self.model.app.settings();
+        let account = match settings.default_account() {
+            Some(account) => account,
+            None => {
+                warn!("tv: no account to forward from");
+                return;
             }
+        };
 
+        match compose::forward_digest(account, &[file.clone()]) {
+            Some(message) => self.model.relm.stream().emit(Msg::Compose(message)),
+            None => warn!("tv: could not forward {:?}", file)
         }
     }
 
+    fn find_changed(&mut self) {
+        let controller = match self.webview.get_find_controller() {
+            Some(controller) => controller,
+            None => return
+        };
 
-    fn render_messages(&mut self){
+        let text = self.find_entry.get_text().map(|s| s.to_string()).unwrap_or_default();
+        if text.is_empty() {
+            controller.search_finish();
+        } else {
+            let options = webkit2gtk::FindOptions::CASE_INSENSITIVE | webkit2gtk::FindOptions::WRAP_AROUND;
+            controller.search(&text, options.bits(), u32::max_value());
+        }
+    }
 
+    fn find_stopped(&mut self) {
+        if let Some(controller) = self.webview.get_find_controller() {
+            controller.search_finish();
+        }
+        self.find_bar.set_search_mode(false);
+        self.webview.grab_focus();
     }
 
 
@@ -221,6 +538,16 @@ impl ThreadView{
                         "id" | "mid" => {
                             //main_window->add_mode (new ThreadIndex (main_window, uri));
                         },
+                        "draft" => {
+                            // draft:<message id>, from the draft_edit button
+                            let message_id = glib::uri_unescape_string(&uri[scheme.len() + 1..], None)
+                                                 .map(|id| id.to_string())
+                                                 .unwrap_or_default();
+                            match self.model.drafts.get(&message_id) {
+                                Some(path) => self.model.relm.stream().emit(Msg::EditDraft(path.clone())),
+                                None => error!("tv: no draft with id '{}' in this thread", message_id)
+                            }
+                        },
                         "http" | "https" | "ftp" => {
                             //open_link (uri);
                         },
@@ -265,7 +592,14 @@ impl Update for ThreadView {
             relm: relm.clone(),
             app,
             webcontext: ctx,
-            page_client: None
+            page_client: None,
+            page_loaded: false,
+            drafts: HashMap::new(),
+            messages: vec![],
+            files: HashMap::new(),
+            focused: 0,
+            crypto_banners: HashMap::new(),
+            tag_strings: HashMap::new()
         }
     }
 
@@ -277,7 +611,11 @@ impl Update for ThreadView {
             Msg::LoadChanged(event) => self.load_changed(event), 
             Msg::ReadyToRender => self.ready_to_render(),
             Msg::DecidePolicy(decision, decision_type) => self.decide_policy(&decision, decision_type),
-            Msg::ShowThread(thread) => self.show_thread(thread)
+            Msg::ShowThread(thread) => self.show_thread(thread),
+            Msg::RunAction(context, name) => self.run_action(context, name),
+            Msg::FindChanged => self.find_changed(),
+            Msg::FindStopped => self.find_stopped(),
+            Msg::EditDraft(_) | Msg::Compose(_) => ()
         }
     }
 }
@@ -290,10 +628,19 @@ impl Widget for ThreadView {
         self.container.clone()
     }
 
-    fn view(_relm: &Relm<Self>, model: Self::Model) -> Self
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self
     {
-        let container = model.app.builder.get_object::<gtk::Box>("thread_view_box")
-                                               .expect("Couldn't find thread_list_scrolled in ui file.");
+        let container = gtk::Box::new(gtk::Orientation::Vertical, 0);
+
+        let find_bar = gtk::SearchBar::new();
+        let find_entry = gtk::SearchEntry::new();
+        find_bar.add(&find_entry);
+        find_bar.connect_entry(&find_entry);
+        container.pack_start(&find_bar, false, false, 0);
+
+        connect!(relm, find_entry, connect_search_changed(_), Msg::FindChanged);
+        connect!(relm, find_entry, connect_activate(_), Msg::RunAction(Context::ThreadView, "find-next"));
+        connect!(relm, find_entry, connect_stop_search(_), Msg::FindStopped);
 
         let ctx = model.webcontext.clone();
 
@@ -304,7 +651,9 @@ impl Widget for ThreadView {
         ThreadView {
             model,
             container,
-            webview
+            webview,
+            find_bar,
+            find_entry
         }
     }
 
@@ -0,0 +1,210 @@
+use gdk;
+use gio::ActionGroupExt;
+use gtk;
+use gtk::prelude::*;
+
+use enamel_core::settings::{Keybindings, KeySequence, KeyPress, KeyCode, Context, Lookup, ACTIONS, action_info};
+
+
+/// The action group prefix the actions of a context are registered under
+pub fn action_prefix(context: Context) -> &'static str {
+    match context {
+        Context::Global => "win",
+        Context::ThreadList => "thread-list",
+        Context::ThreadView => "thread-view",
+        Context::Compose => "compose"
+    }
+}
+
+/// The detailed GAction name for an action, like `win.quit`
+pub fn action_name(context: Context, name: &str) -> String {
+    format!("{}.{}", action_prefix(context), name)
+}
+
+/// The GDK key name of a key, like `Page_Up` or `question`
+fn key_name(code: KeyCode) -> String {
+    match code {
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::Char(c) if c.is_ascii_alphanumeric() => c.to_string(),
+        KeyCode::Char(c) => gdk::keyval_name(gdk::unicode_to_keyval(c as u32))
+                                .map(|name| name.to_string())
+                                .unwrap_or_else(|| c.to_string()),
+        KeyCode::Enter => "Return".to_string(),
+        KeyCode::Esc => "Escape".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::Backspace => "BackSpace".to_string(),
+        KeyCode::Delete => "Delete".to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::Home => "Home".to_string(),
+        KeyCode::End => "End".to_string(),
+        KeyCode::PageUp => "Page_Up".to_string(),
+        KeyCode::PageDown => "Page_Down".to_string()
+    }
+}
+
+/// A single key press in `gtk_accelerator_parse` syntax
+fn key_accelerator(key: &KeyPress) -> String {
+    let mut accel = String::new();
+    if key.ctrl {
+        accel.push_str("<primary>");
+    }
+    if key.alt {
+        accel.push_str("<alt>");
+    }
+    if key.shift {
+        accel.push_str("<shift>");
+    }
+    accel.push_str(&key_name(key.code));
+    accel
+}
+
+/// The GTK accelerator for a key sequence. GTK has no multi-key
+/// accelerators, and plain keys would be taken away from text entries, so
+/// only single keys with a modifier and function keys qualify. The main
+/// window's key handler does the rest.
+pub fn accelerator(sequence: &KeySequence) -> Option<String> {
+    match sequence.0.as_slice() {
+        [key] if key.ctrl || key.alt => Some(key_accelerator(key)),
+        [key @ KeyPress{ code: KeyCode::F(_), .. }] => Some(key_accelerator(key)),
+        _ => None
+    }
+}
+
+/// The key press of a key event, or None for modifier keys and keys that
+/// can't be bound. Characters already have shift applied, so `?` and `G`
+/// come without it.
+pub fn key_press(event: &gdk::EventKey) -> Option<KeyPress> {
+    let keyval = event.get_keyval();
+    let state = event.get_state();
+
+    let code = match gdk::keyval_name(keyval)?.as_str() {
+        "Return" | "KP_Enter" => KeyCode::Enter,
+        "Escape" => KeyCode::Esc,
+        "Tab" | "ISO_Left_Tab" => KeyCode::Tab,
+        "BackSpace" => KeyCode::Backspace,
+        "Delete" => KeyCode::Delete,
+        "Up" => KeyCode::Up,
+        "Down" => KeyCode::Down,
+        "Left" => KeyCode::Left,
+        "Right" => KeyCode::Right,
+        "Home" => KeyCode::Home,
+        "End" => KeyCode::End,
+        "Page_Up" => KeyCode::PageUp,
+        "Page_Down" => KeyCode::PageDown,
+        name if name.starts_with('F') && name[1..].parse::<u8>().is_ok() => KeyCode::F(name[1..].parse().ok()?),
+        _ => KeyCode::Char(gdk::keyval_to_unicode(keyval).filter(|c| !c.is_control())?)
+    };
+
+    let key = KeyPress{
+        code,
+        ctrl: state.contains(gdk::ModifierType::CONTROL_MASK),
+        alt: state.contains(gdk::ModifierType::MOD1_MASK),
+        shift: match code {
+            KeyCode::Char(_) => false,
+            _ => state.contains(gdk::ModifierType::SHIFT_MASK)
+        }
+    };
+    Some(key.normalized())
+}
+
+
+/// The keys pressed so far of a sequence like `g g`, which GTK accelerators
+/// can't do
+#[derive(Debug, Default)]
+pub struct KeyChords {
+    pending: Vec<KeyPress>
+}
+
+impl KeyChords {
+
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Add a key press and look up the sequence. A key that doesn't continue
+    /// the sequence starts a new one.
+    pub fn press(&mut self, keybindings: &Keybindings, context: Context, key: KeyPress) -> Lookup {
+        self.pending.push(key);
+        let mut lookup = keybindings.lookup(context, &self.pending);
+
+        if lookup == Lookup::None && self.pending.len() > 1 {
+            self.pending = vec![key];
+            lookup = keybindings.lookup(context, &self.pending);
+        }
+
+        if lookup != Lookup::Prefix {
+            self.pending.clear();
+        }
+        lookup
+    }
+}
+
+/// Activate the action a key sequence was bound to. Actions of the context
+/// are looked up from the focused widget, since composers have their own.
+/// Returns false if nothing implements it.
+pub fn activate(window: &gtk::ApplicationWindow, context: Context, name: &str) -> bool {
+    let context = match action_info(context, name) {
+        Some(_) => context,
+        None => Context::Global
+    };
+
+    if context == Context::Global {
+        if window.has_action(name) {
+            window.activate_action(name, None);
+            return true;
+        }
+        return false;
+    }
+
+    let prefix = action_prefix(context);
+    let group = window.get_focus()
+                      .and_then(|focus| focus.get_action_group(prefix))
+                      .or_else(|| window.get_action_group(prefix));
+
+    match group {
+        Some(ref group) if group.has_action(name) => {
+            group.activate_action(name, None);
+            true
+        },
+        _ => false
+    }
+}
+
+/// Any key sequence in the syntax of `GtkShortcutsShortcut:accelerator`,
+/// where `&` separates keys pressed one after the other
+pub fn shortcut_accelerator(sequence: &KeySequence) -> String {
+    sequence.0.iter().map(key_accelerator).collect::<Vec<_>>().join("&")
+}
+
+/// Register the accelerators of all actions with the application
+pub fn apply(application: &gtk::Application, keybindings: &Keybindings) {
+    for action in ACTIONS {
+        let accels: Vec<String> = keybindings.keys(action.context, action.name)
+                                             .iter()
+                                             .filter_map(accelerator)
+                                             .collect();
+        let accels: Vec<&str> = accels.iter().map(|accel| accel.as_str()).collect();
+
+        application.set_accels_for_action(&action_name(action.context, action.name), &accels);
+    }
+}
+
+/// How a key sequence is shown to the user: GTK's own label for
+/// accelerators, like "Ctrl+Q", and the configured form for the rest
+pub fn label(sequence: &KeySequence) -> String {
+    if let Some(accel) = accelerator(sequence) {
+        let (key, mods) = gtk::accelerator_parse(&accel);
+        if let Some(label) = gtk::accelerator_get_label(key, mods) {
+            return label.to_string();
+        }
+    }
+    sequence.to_string()
+}
+
+/// All keys of an action, as shown to the user
+pub fn labels(keybindings: &Keybindings, context: Context, name: &str) -> Vec<String> {
+    keybindings.keys(context, name).iter().map(label).collect()
+}
@@ -10,6 +10,7 @@ use dirs;
 use pretty_assertions;
 
 use gtk;
+use gmime;
 
 use structopt::StructOpt;
 use structopt::clap::{App, Arg};
@@ -19,6 +20,7 @@ mod static_resource;
 mod constants;
 mod app;
 mod settings;
+mod keybindings;
 mod headerbar;
 mod widgets;
 mod components;
@@ -46,6 +48,9 @@ fn init() {
         // run initialization here
         gtk::init().expect("Error initializing gtk.");
         static_resource::init().expect("Error initializing static resources.");
+
+        // also registers the OpenPGP and S/MIME crypto contexts
+        gmime::init();
     });
 }
 
@@ -74,6 +79,20 @@ fn default_config_path() -> PathBuf{
     default_config
 }
 
+/// Show an error before the application is up and running
+fn show_error(text: &str) {
+    use gtk::prelude::*;
+
+    let dialog = gtk::MessageDialog::new(None::<&gtk::Window>,
+                                         gtk::DialogFlags::MODAL,
+                                         gtk::MessageType::Error,
+                                         gtk::ButtonsType::Close,
+                                         text);
+    dialog.set_title(constants::APPLICATION_NAME);
+    dialog.run();
+    dialog.destroy();
+}
+
 /// Main entry point
 fn main() {
     init();
@@ -110,7 +129,14 @@ fn main() {
 
     // load the settings
     let conf_path:PathBuf = PathBuf::from(conf_location);
-    let settings = Rc::new(Settings::new(&conf_path.as_path()));
+    let settings = match Settings::new(&conf_path.as_path()) {
+        Ok(settings) => Rc::new(settings),
+        Err(err) => {
+            error!("Failed to load settings: {}", err);
+            show_error(&format!("{}", err));
+            std::process::exit(1);
+        }
+    };
 
     EnamelApp::run(settings);
 }
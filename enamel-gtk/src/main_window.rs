@@ -1,7 +1,10 @@
 use std::rc::Rc;
+use std::cell::{Cell, RefCell};
 use gtk::GtkWindowExt;
+use gio::{ActionGroupExt, ApplicationExt};
 use gtk;
 use glib;
+use pango;
 use gtk::prelude::*;
 
 use log::*;
@@ -12,19 +15,44 @@ use relm_derive::Msg;
 
 use notmuch::DatabaseMode;
 use enamel_core::database::Thread;
+use enamel_core::command::Command;
+use enamel_core::settings::{Context, Lookup, KeyCode, SettingsChanges, ACTIONS};
 
 use crate::app::EnamelApp;
 use crate::headerbar::HeaderBar;
-
+use crate::keybindings;
+use crate::settings::{Session, PageState, WindowGeometry};
+
+use crate::components::command_palette::{CommandPalette, Msg as CommandPaletteMsg};
+use crate::components::compose::{Compose, ComposeStart, Msg as ComposeMsg};
+use crate::components::preferences::{Preferences, Msg as PreferencesMsg};
+use crate::components::search_bar::{SearchBar, Msg as SearchBarMsg};
+use crate::components::search_page::{SearchPage, Msg as SearchPageMsg};
 use crate::components::tag_list::{TagList, Msg as TagListMsg};
-use crate::components::thread_list::{ThreadList, Msg as ThreadListMsg};
 use crate::components::thread_view::{ThreadView, Msg as ThreadViewMsg};
 
 
 #[derive(Msg)]
 pub enum Msg {
-    TagSelect(Option<String>),
-    ThreadSelect(Thread),
+    /// a saved search or tag was picked in the tag list
+    QuerySelect(String),
+    Search(String),
+    OpenThread(Thread),
+    Compose(ComposeStart),
+    RunAction(Context, &'static str),
+    RunCommand(Command),
+    /// the tags of some threads changed, so all pages are outdated. Has the
+    /// tags that changed, if known.
+    TagsChanged(Option<Vec<String>>),
+    /// the database changed, maybe by another program
+    DatabaseChanged(u64),
+    /// the settings were saved or the config files changed
+    SettingsChanged(SettingsChanges),
+    PageTitle(gtk::Widget, String),
+    PageThreadSelected(gtk::Widget, String),
+    PageSwitched(gtk::Widget),
+    ClosePage(gtk::Widget),
+    RemovePage(gtk::Widget),
     Change,
     Quit,
 }
@@ -38,9 +66,40 @@ pub struct Model {
 #[derive(Clone)]
 struct Widgets {
     headerbar: Component<HeaderBar>,
+    searchbar: Component<SearchBar>,
     taglist: Component<TagList>,
-    threadlist: Component<ThreadList>,
-    threadview: Component<ThreadView>
+    palette: Component<CommandPalette>,
+    preferences: Component<Preferences>,
+    pages: gtk::Notebook
+}
+
+/// What a page in the main window shows. Each page owns its state, like
+/// the query and the selected thread of a search.
+enum PageKind {
+    Search(Component<SearchPage>, String),
+    Thread(Component<ThreadView>, String),
+    Compose(Component<Compose>)
+}
+
+impl PageKind {
+
+    /// Where the key bindings are looked up while the page is shown
+    fn context(&self) -> Context {
+        match self {
+            PageKind::Search(..) => Context::ThreadList,
+            PageKind::Thread(..) => Context::ThreadView,
+            PageKind::Compose(_) => Context::Compose
+        }
+    }
+}
+
+struct Page {
+    kind: PageKind,
+    widget: gtk::Widget,
+    label: gtk::Label,
+
+    /// the thread selected in a search, to restore it next time
+    selected_thread: Option<String>
 }
 
 
@@ -50,35 +109,511 @@ struct Widgets {
 pub struct MainWindow {
     model: Model,
     container: gtk::ApplicationWindow,
-    widgets: Widgets
+    widgets: Widgets,
+    pages: Vec<Page>,
+
+    /// the key binding context of the current page, for the key handler
+    key_context: Rc<Cell<Context>>,
+
+    /// waiting for composers to close before quitting
+    quitting: bool
 }
 
 impl MainWindow {
 
-    fn on_tag_changed(self: &mut Self, tag: Option<String>){
+    fn on_query_selected(self: &mut Self, qs: String){
+
+        // the tag list steers the current search, if there is one
+        let index = match self.current_index() {
+            Some(index) => index,
+            None => {
+                self.search(qs);
+                return;
+            }
+        };
+
+        let page = &mut self.pages[index];
+        match page.kind {
+            PageKind::Search(ref search, ref mut query) => {
+                search.emit(SearchPageMsg::Search(qs.clone()));
+                page.label.set_text(&search_title(&qs));
+                page.selected_thread = None;
+                *query = qs;
+            },
+            _ => {
+                self.search(qs);
+            }
+        }
+    }
+
+    /// Switch to the page for a query, or open a new one. Returns the index
+    /// of the page.
+    fn search(self: &mut Self, qs: String) -> usize {
+        debug!("qs: {:?}", qs);
+
+        let existing = self.pages.iter().position(|page| match page.kind {
+            PageKind::Search(_, ref query) => *query == qs,
+            _ => false
+        });
+        if let Some(index) = existing {
+            self.focus_page(index);
+            return index;
+        }
+
+        let search = relm_init::<SearchPage>((self.model.app.clone(), qs.clone())).unwrap();
+        let relm = &self.model.relm;
+
+        use self::SearchPageMsg::OpenThread as SearchPage_OpenThread;
+        connect!(search@SearchPage_OpenThread(ref thread), relm, Msg::OpenThread(thread.clone()));
+
+        use self::SearchPageMsg::EditDraft as SearchPage_EditDraft;
+        connect!(search@SearchPage_EditDraft(ref path), relm, Msg::Compose(ComposeStart::Draft(path.clone())));
+
+        use self::SearchPageMsg::Compose as SearchPage_Compose;
+        connect!(search@SearchPage_Compose(ref message), relm, Msg::Compose(ComposeStart::Message(message.clone())));
+
+        use self::SearchPageMsg::TagsChanged as SearchPage_TagsChanged;
+        connect!(search@SearchPage_TagsChanged(ref tags), relm, Msg::TagsChanged(Some(tags.clone())));
+
+        let widget = search.widget().clone().upcast::<gtk::Widget>();
+
+        let selected_widget = widget.clone();
+        use self::SearchPageMsg::ThreadSelect as SearchPage_ThreadSelect;
+        connect!(search@SearchPage_ThreadSelect(ref thread), relm,
+                 Msg::PageThreadSelected(selected_widget.clone(), thread.id().to_string()));
+
+        self.add_page(PageKind::Search(search, qs.clone()), widget, &search_title(&qs));
+        self.pages.len() - 1
+    }
+
+    /// Show a thread on a page of its own
+    fn open_thread(self: &mut Self, thread: Thread){
+        let thread_id = thread.id().to_string();
+
+        let existing = self.pages.iter().position(|page| match page.kind {
+            PageKind::Thread(_, ref id) => *id == thread_id,
+            _ => false
+        });
+        if let Some(index) = existing {
+            self.focus_page(index);
+            return;
+        }
+
+        let threadview = relm_init::<ThreadView>(self.model.app.clone()).unwrap();
+        let relm = &self.model.relm;
+
+        use self::ThreadViewMsg::EditDraft as ThreadView_EditDraft;
+        connect!(threadview@ThreadView_EditDraft(ref path), relm, Msg::Compose(ComposeStart::Draft(path.clone())));
+
+        use self::ThreadViewMsg::Compose as ThreadView_Compose;
+        connect!(threadview@ThreadView_Compose(ref message), relm, Msg::Compose(ComposeStart::Message(message.clone())));
+
+        let title = thread.subject().to_string();
+        threadview.emit(ThreadViewMsg::ShowThread(thread));
 
-        // TODO: build a new query and refresh the thread list.
+        let widget = threadview.widget().clone().upcast::<gtk::Widget>();
+        self.add_page(PageKind::Thread(threadview, thread_id), widget, &title);
+    }
+
+    /// Show the first thread matching a query
+    fn open(self: &mut Self, qs: &str){
         let dbman = self.model.app.dbmanager.clone();
-        let db = dbman.get(DatabaseMode::ReadOnly).unwrap();
+        let db = match dbman.get(DatabaseMode::ReadOnly) {
+            Ok(db) => db,
+            Err(err) => {
+                error!("Could not open database: {:?}", err);
+                self.model.app.show_error(&format!("Could not open the database: {:?}", err));
+                return;
+            }
+        };
 
-        let qs = match tag{
-            Some(tag) => format!("tag:{}", tag).to_string(),
-            None => "".to_string()
+        let expanded = self.model.app.settings().expand_query(qs);
+        let query = match <notmuch::Database as notmuch::DatabaseExt>::create_query(db, &expanded) {
+            Ok(query) => query,
+            Err(err) => {
+                error!("Could not create query {:?}: {:?}", qs, err);
+                return;
+            }
         };
-        debug!("qs: {:?}", qs);
+        let thread = <notmuch::Query<'_> as notmuch::QueryExt>::search_threads(query)
+                         .ok()
+                         .and_then(|mut threads| threads.next());
+
+        match thread {
+            Some(thread) => self.open_thread(Thread::new(thread)),
+            None => info!("No thread matches {:?}", qs)
+        }
+    }
+
+    /// Open a compose page, for a new message, a draft or a prepared message
+    fn compose(self: &mut Self, start: ComposeStart){
+        let composer = relm_init::<Compose>((self.model.app.clone(), start)).unwrap();
+        let widget = composer.widget().clone().upcast::<gtk::Widget>();
+        let relm = &self.model.relm;
+
+        let title_widget = widget.clone();
+        use self::ComposeMsg::Title as Compose_Title;
+        connect!(composer@Compose_Title(ref title), relm, Msg::PageTitle(title_widget.clone(), title.clone()));
+
+        let closed_widget = widget.clone();
+        use self::ComposeMsg::Closed as Compose_Closed;
+        connect!(composer@Compose_Closed, relm, Msg::RemovePage(closed_widget.clone()));
+
+        self.add_page(PageKind::Compose(composer), widget, "New message");
+    }
+
+    fn add_page(self: &mut Self, kind: PageKind, widget: gtk::Widget, title: &str){
+        let label = gtk::Label::new(Some(title));
+        label.set_ellipsize(pango::EllipsizeMode::End);
+        label.set_max_width_chars(30);
+
+        let close_button = gtk::Button::new_from_icon_name(Some("window-close-symbolic"), gtk::IconSize::Menu);
+        close_button.set_relief(gtk::ReliefStyle::None);
+        close_button.set_tooltip_text(Some("Close"));
+
+        let tab = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        tab.pack_start(&label, true, true, 0);
+        tab.pack_start(&close_button, false, false, 0);
+        tab.show_all();
+
+        let close_widget = widget.clone();
+        connect!(self.model.relm, close_button, connect_clicked(_), Msg::ClosePage(close_widget.clone()));
+
+        widget.show_all();
+        let index = self.widgets.pages.append_page(&widget, Some(&tab));
+        self.widgets.pages.set_tab_reorderable(&widget, true);
+        self.widgets.pages.set_current_page(Some(index));
+
+        self.key_context.set(kind.context());
+        self.pages.push(Page{ kind, widget, label, selected_thread: None });
+    }
+
+    /// Tabs can be reordered, so the notebook has its own page numbers
+    fn focus_page(&self, index: usize) {
+        let page_num = self.widgets.pages.page_num(&self.pages[index].widget);
+        self.widgets.pages.set_current_page(page_num);
+    }
+
+    fn page_index(&self, widget: &gtk::Widget) -> Option<usize> {
+        self.pages.iter().position(|page| page.widget == *widget)
+    }
+
+    fn current_index(&self) -> Option<usize> {
+        let widget = self.widgets.pages.get_nth_page(self.widgets.pages.get_current_page())?;
+        self.page_index(&widget)
+    }
+
+    fn current_page(&self) -> Option<&Page> {
+        self.current_index().map(|index| &self.pages[index])
+    }
+
+    fn page_switched(self: &mut Self, widget: gtk::Widget){
+        if let Some(index) = self.page_index(&widget) {
+            self.key_context.set(self.pages[index].kind.context());
+        }
+    }
+
+    fn set_page_title(self: &mut Self, widget: gtk::Widget, title: String){
+        if let Some(index) = self.page_index(&widget) {
+            self.pages[index].label.set_text(&title);
+        }
+    }
+
+    fn set_page_thread(self: &mut Self, widget: gtk::Widget, thread_id: String){
+        if let Some(index) = self.page_index(&widget) {
+            self.pages[index].selected_thread = Some(thread_id);
+        }
+    }
+
+    /// Close a page. Composers save their draft first, and come back with
+    /// `RemovePage` when done.
+    fn close_page(self: &mut Self, widget: gtk::Widget){
+        let index = match self.page_index(&widget) {
+            Some(index) => index,
+            None => return
+        };
+
+        match self.pages[index].kind {
+            PageKind::Compose(ref composer) => composer.emit(ComposeMsg::Close),
+            _ => self.remove_page(widget)
+        }
+    }
+
+    fn remove_page(self: &mut Self, widget: gtk::Widget){
+        if let Some(index) = self.page_index(&widget) {
+            if let Some(page_num) = self.widgets.pages.page_num(&widget) {
+                self.widgets.pages.remove_page(Some(page_num));
+            }
+            self.pages.remove(index);
+        }
+        if self.pages.is_empty() {
+            self.key_context.set(Context::Global);
+        }
+
+        let composing = self.pages.iter().any(|page| match page.kind {
+            PageKind::Compose(_) => true,
+            _ => false
+        });
+        if self.quitting && !composing {
+            self.model.app.instance.quit();
+        }
+    }
 
+    fn switch_page(self: &mut Self, offset: i32){
+        let count = self.widgets.pages.get_n_pages() as i32;
+        if count == 0 {
+            return;
+        }
 
-        let query = <notmuch::Database as notmuch::DatabaseExt>::create_query(db, &qs).unwrap();
-        let threads = <notmuch::Query<'_> as notmuch::QueryExt>::search_threads(query).unwrap();
+        let current = self.widgets.pages.get_current_page().unwrap_or(0) as i32;
+        let next = (current + offset + count) % count;
+        self.widgets.pages.set_current_page(Some(next as u32));
+    }
+
+    /// Run the query of every search page again, and update the tag counts
+    fn tags_changed(self: &mut Self, tags: Option<Vec<String>>){
+        for page in &self.pages {
+            if let PageKind::Search(ref search, _) = page.kind {
+                search.emit(SearchPageMsg::Refresh);
+            }
+        }
+        self.widgets.taglist.emit(TagListMsg::UpdateCounts(tags));
+        // new mail may come with new addresses
+        self.model.app.update_addressbook();
+    }
 
-        self.widgets.threadlist.emit(ThreadListMsg::Update(Some(threads)));
+    /// Bring the tag list and the thread lists up to date with changed
+    /// settings. Key bindings are handled by the application.
+    fn settings_changed(self: &mut Self, changes: SettingsChanges){
+        if changes.tags {
+            for page in &self.pages {
+                if let PageKind::Search(ref search, _) = page.kind {
+                    search.emit(SearchPageMsg::SettingsChanged);
+                }
+            }
+        }
+        if changes.searches || changes.tags {
+            self.widgets.taglist.emit(TagListMsg::Refresh);
+        }
     }
 
-    fn on_thread_selected(self: &mut Self, thread: Thread){
-        self.widgets.threadview.emit(ThreadViewMsg::ShowThread(thread))
+    /// The window, its panes and pages, in the order of the tabs
+    fn session(&self) -> Session {
+        let main_paned = self.model.app.builder.get_object::<gtk::Paned>("main_paned")
+                                   .expect("Couldn't find main_paned in ui file.");
+
+        let mut session = Session {
+            current_page: None,
+            sidebar_position: Some(main_paned.get_position()),
+            window: Some(WindowGeometry::from_window(&self.container)),
+            pages: vec![]
+        };
+
+        let current = self.widgets.pages.get_current_page();
+        for page_num in 0..self.widgets.pages.get_n_pages() {
+            let page = match self.widgets.pages.get_nth_page(Some(page_num))
+                                               .and_then(|widget| self.page_index(&widget)) {
+                Some(index) => &self.pages[index],
+                None => continue
+            };
+
+            let state = match page.kind {
+                PageKind::Search(ref search, ref query) => PageState::Search {
+                    query: query.clone(),
+                    selected_thread: page.selected_thread.clone(),
+                    thread_list_position: Some(search.widget().get_position())
+                },
+                PageKind::Thread(_, ref thread_id) => PageState::Thread {
+                    thread_id: thread_id.clone()
+                },
+                PageKind::Compose(_) => continue
+            };
+
+            if Some(page_num) == current {
+                session.current_page = Some(session.pages.len());
+            }
+            session.pages.push(state);
+        }
+
+        session
     }
 
+    fn restore_session(self: &mut Self, session: Session){
+        if let Some(ref geometry) = session.window {
+            geometry.apply(&self.container);
+        }
+
+        if let Some(position) = session.sidebar_position {
+            let main_paned = self.model.app.builder.get_object::<gtk::Paned>("main_paned")
+                                       .expect("Couldn't find main_paned in ui file.");
+            main_paned.set_position(position);
+        }
+
+        if !self.model.app.settings().enamel_config.ui.restore_pages {
+            return;
+        }
+
+        let mut restored = vec![];
+        for page in session.pages {
+            match page {
+                PageState::Search{query, selected_thread, thread_list_position} => {
+                    let index = self.search(query);
+                    if let PageKind::Search(ref search, _) = self.pages[index].kind {
+                        if let Some(thread_id) = selected_thread {
+                            search.emit(SearchPageMsg::SelectThread(thread_id));
+                        }
+                        if let Some(position) = thread_list_position {
+                            search.widget().set_position(position);
+                        }
+                    }
+                    restored.push(Some(index));
+                },
+                PageState::Thread{thread_id} => {
+                    let count = self.pages.len();
+                    self.open(&format!("thread:{}", thread_id));
+                    // the thread may be gone since
+                    restored.push(if self.pages.len() > count { Some(count) } else { None });
+                }
+            }
+        }
 
+        if let Some(Some(index)) = session.current_page.and_then(|current| restored.get(current).cloned()) {
+            self.focus_page(index);
+        }
+    }
+
+    fn quit(self: &mut Self){
+        match Session::path() {
+            Some(path) => {
+                if let Err(err) = self.session().store(&path) {
+                    error!("Could not save the session: {:?}", err);
+                }
+            },
+            None => error!("Could not save the session: no home directory")
+        }
+
+        // composers save their drafts on their way out, the last one to
+        // go ends the application
+        self.quitting = true;
+        let mut composing = false;
+        for page in &self.pages {
+            if let PageKind::Compose(ref composer) = page.kind {
+                composer.emit(ComposeMsg::Close);
+                composing = true;
+            }
+        }
+
+        if !composing {
+            self.model.app.instance.quit();
+        }
+    }
+
+    fn run_action(self: &mut Self, context: Context, name: &'static str){
+        match (context, name) {
+            (Context::Global, "quit") => self.quit(),
+            (Context::Global, "help") => self.container.activate_action("show-help-overlay", None),
+            (Context::Global, "compose") => self.compose(ComposeStart::New),
+            (Context::Global, "search") => self.widgets.searchbar.emit(SearchBarMsg::Show(None)),
+            (Context::Global, "command") => self.widgets.palette.emit(CommandPaletteMsg::Show),
+            (Context::Global, "next-page") => self.switch_page(1),
+            (Context::Global, "previous-page") => self.switch_page(-1),
+            (Context::Global, "close-page") => {
+                if let Some(widget) = self.current_page().map(|page| page.widget.clone()) {
+                    self.close_page(widget);
+                }
+            },
+            (Context::Global, "preferences") => self.widgets.preferences.emit(PreferencesMsg::Show),
+            (Context::Global, "menu") => self.show_menu(),
+            (Context::Global, "focus-next") => {
+                self.container.child_focus(gtk::DirectionType::TabForward);
+            },
+            (Context::Global, "focus-previous") => {
+                self.container.child_focus(gtk::DirectionType::TabBackward);
+            },
+            (Context::Global, "back") => self.back(),
+            (Context::ThreadList, "filter") => match self.current_page().map(|page| &page.kind) {
+                Some(PageKind::Search(_, query)) => self.widgets.searchbar.emit(SearchBarMsg::Show(Some(query.clone()))),
+                _ => debug!("no thread list to filter")
+            },
+            // moving around is up to the page
+            (Context::Global, _) | (Context::ThreadList, _) | (Context::ThreadView, _) => {
+                match self.current_page().map(|page| &page.kind) {
+                    Some(PageKind::Search(search, _)) if context != Context::ThreadView => {
+                        search.emit(SearchPageMsg::RunAction(context, name))
+                    },
+                    Some(PageKind::Thread(threadview, _)) if context != Context::ThreadList => {
+                        threadview.emit(ThreadViewMsg::RunAction(context, name))
+                    },
+                    _ => debug!("no page for {}.{}", keybindings::action_prefix(context), name)
+                }
+            },
+            _ => warn!("action {}.{} is not implemented", keybindings::action_prefix(context), name)
+        }
+    }
+
+    /// The main menu, below the header bar
+    fn show_menu(&self) {
+        let menu = gio::Menu::new();
+        menu.append(Some("Preferences"), Some("win.preferences"));
+        menu.append(Some("Keyboard Shortcuts"), Some("win.help"));
+        menu.append(Some("Quit"), Some("win.quit"));
+
+        let header = self.model.app.builder.get_object::<gtk::Widget>("main_header")
+                               .expect("Couldn't find main_header in ui file.");
+        let popover = gtk::Popover::new_from_model(Some(&header), &menu);
+        popover.show_all();
+    }
+
+    /// Close a thread page, or else go from the thread list to the tag list
+    fn back(self: &mut Self){
+        let widget = match self.current_page() {
+            Some(Page{ kind: PageKind::Thread(..), widget, .. }) => widget.clone(),
+            Some(Page{ kind: PageKind::Search(..), .. }) | None => {
+                self.widgets.taglist.widget().child_focus(gtk::DirectionType::TabForward);
+                return;
+            },
+            Some(Page{ kind: PageKind::Compose(_), .. }) => return
+        };
+        self.close_page(widget);
+    }
+
+    fn run_command(self: &mut Self, command: Command){
+        match command {
+            Command::Search(qs) => self.search(qs),
+            Command::Tag{add, remove} => match self.current_page().map(|page| &page.kind) {
+                Some(PageKind::Search(search, _)) => search.emit(SearchPageMsg::Tag(add, remove)),
+                _ => info!("No thread selected to tag")
+            },
+            Command::Mark(qs) => match self.current_page().map(|page| &page.kind) {
+                Some(PageKind::Search(search, _)) => search.emit(SearchPageMsg::Mark(qs)),
+                _ => info!("No thread list to mark threads in")
+            },
+            Command::Open(qs) => self.open(&qs),
+            Command::Quit => self.quit(),
+            Command::Action(name) => {
+                // the action of the current page goes first, as with keys
+                if keybindings::activate(&self.container, self.key_context.get(), name) {
+                    return;
+                }
+                match ACTIONS.iter().find(|action| action.name == name) {
+                    Some(action) => {
+                        if !keybindings::activate(&self.container, action.context, action.name) {
+                            info!("action {} does not apply here", name);
+                        }
+                    },
+                    None => warn!("unknown action {}", name)
+                }
+            }
+        }
+    }
+}
+
+fn search_title(qs: &str) -> String {
+    if qs.trim().is_empty() {
+        "All messages".to_string()
+    } else {
+        qs.to_string()
+    }
 }
 
 impl Update for MainWindow{
@@ -95,8 +630,20 @@ impl Update for MainWindow{
 
     fn update(&mut self, event: Msg) {
         match event {
-            Msg::TagSelect(tag) => self.on_tag_changed(tag),
-            Msg::ThreadSelect(thread) => self.on_thread_selected(thread),
+            Msg::QuerySelect(qs) => self.on_query_selected(qs),
+            Msg::Search(qs) => self.search(qs),
+            Msg::OpenThread(thread) => self.open_thread(thread),
+            Msg::Compose(start) => self.compose(start),
+            Msg::RunAction(context, name) => self.run_action(context, name),
+            Msg::RunCommand(command) => self.run_command(command),
+            Msg::TagsChanged(tags) => self.tags_changed(tags),
+            Msg::DatabaseChanged(revision) => self.widgets.taglist.emit(TagListMsg::DatabaseChanged(revision)),
+            Msg::SettingsChanged(changes) => self.settings_changed(changes),
+            Msg::PageTitle(widget, title) => self.set_page_title(widget, title),
+            Msg::PageThreadSelected(widget, thread_id) => self.set_page_thread(widget, thread_id),
+            Msg::PageSwitched(widget) => self.page_switched(widget),
+            Msg::ClosePage(widget) => self.close_page(widget),
+            Msg::RemovePage(widget) => self.remove_page(widget),
             Msg::Change => {
                 // self.model.content = self.widgets.input.get_text()
                 //                                        .expect("get_text failed")
@@ -105,7 +652,7 @@ impl Update for MainWindow{
                 //                                        .collect();
                 // self.widgets.label.set_text(&self.model.content);
             },
-            Msg::Quit => gtk::main_quit(),
+            Msg::Quit => self.quit(),
         }
     }
 }
@@ -118,24 +665,70 @@ impl Widget for MainWindow {
     }
 
     fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
-        
+
         let window = model.app.builder.get_object::<gtk::ApplicationWindow>("main_window")
                                   .expect("Couldn't find main_window in ui file.");
         window.set_application(Some(&model.app.instance));
 
+        let pages = model.app.builder.get_object::<gtk::Notebook>("pages")
+                                 .expect("Couldn't find pages in ui file.");
+
 
-        let headerbar = relm_init::<HeaderBar>(model.app.clone()).unwrap(); 
-        let taglist = relm_init::<TagList>(model.app.clone()).unwrap(); 
-        let threadlist = relm_init::<ThreadList>(model.app.clone()).unwrap(); 
-        let threadview = relm_init::<ThreadView>(model.app.clone()).unwrap(); 
+        let headerbar = relm_init::<HeaderBar>(model.app.clone()).unwrap();
+        let searchbar = relm_init::<SearchBar>(model.app.clone()).unwrap();
+        let taglist = relm_init::<TagList>(model.app.clone()).unwrap();
+        let palette = relm_init::<CommandPalette>((model.app.clone(), window.clone())).unwrap();
+        let preferences = relm_init::<Preferences>((model.app.clone(), window.clone())).unwrap();
 
 
         // TODO: what would be the best place to connect all UI signals?
         use self::TagListMsg::ItemSelect as TagList_ItemSelect;
-        connect!(taglist@TagList_ItemSelect(ref tag), relm, Msg::TagSelect(tag.clone()));
+        connect!(taglist@TagList_ItemSelect(ref qs), relm, Msg::QuerySelect(qs.clone()));
+
+        use self::SearchBarMsg::Search as SearchBar_Search;
+        connect!(searchbar@SearchBar_Search(ref qs), relm, Msg::Search(qs.clone()));
+
+        use self::CommandPaletteMsg::RunCommand as CommandPalette_RunCommand;
+        connect!(palette@CommandPalette_RunCommand(ref command), relm, Msg::RunCommand(command.clone()));
+
+        // keep the window until the session is saved
+        connect!(relm, window, connect_delete_event(_, _), return (Some(Msg::Quit), gtk::Inhibit(true)));
+
+        connect!(relm, pages, connect_switch_page(_, page, _), Msg::PageSwitched(page.clone()));
+
+        // plain keys and key sequences go through the key bindings here,
+        // since GTK accelerators only do single keys with a modifier
+        let key_context = Rc::new(Cell::new(Context::Global));
+        let chords = RefCell::new(keybindings::KeyChords::default());
+        let app = model.app.clone();
+        let context = key_context.clone();
+        window.connect_key_press_event(move |window, event| {
+            let key = match keybindings::key_press(event) {
+                Some(key) => key,
+                None => return gtk::Inhibit(false)
+            };
+
+            // typing goes to text fields, only keys with a modifier are
+            // taken from them
+            let typing = window.get_focus()
+                               .map(|focus| focus.is::<gtk::Editable>() || focus.is::<gtk::TextView>())
+                               .unwrap_or(false);
+            let plain = !key.ctrl && !key.alt && match key.code {
+                KeyCode::F(_) => false,
+                _ => true
+            };
+            if typing && plain && !chords.borrow().is_pending() {
+                return gtk::Inhibit(false);
+            }
 
-        use self::ThreadListMsg::ThreadSelect as ThreadList_ThreadSelect;
-        connect!(threadlist@ThreadList_ThreadSelect(ref thread), relm, Msg::ThreadSelect(thread.as_ref().unwrap().clone()));
+            let settings = app.settings();
+            let lookup = chords.borrow_mut().press(&settings.keybindings, context.get(), key);
+            match lookup {
+                Lookup::Action(name) => gtk::Inhibit(keybindings::activate(window, context.get(), name)),
+                Lookup::Prefix => gtk::Inhibit(true),
+                Lookup::None => gtk::Inhibit(false)
+            }
+        });
 
 
 
@@ -144,10 +737,15 @@ impl Widget for MainWindow {
             container: window,
             widgets: Widgets{
                 headerbar,
+                searchbar,
                 taglist,
-                threadlist,
-                threadview
-            }
+                palette,
+                preferences,
+                pages
+            },
+            pages: vec![],
+            key_context,
+            quitting: false
         }
 
     }
@@ -170,9 +768,13 @@ impl Widget for MainWindow {
         //                             })
         //                             .build();
 
+        if let Some(path) = Session::path() {
+            self.restore_session(Session::load(&path));
+        }
+
         self.container.show_all();
 
         self.widgets.taglist.emit(TagListMsg::Refresh);
     }
 
-}
\ No newline at end of file
+}
@@ -1,11 +1,20 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::*;
+use dirs;
 use gio;
 use gio::{Settings, SettingsExt};
 use gtk;
 use gtk::GtkWindowExt;
+use serde_derive::{Serialize, Deserialize};
+use toml;
 
 use chrono::prelude::*;
 use chrono::Duration;
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct WindowGeometry {
     left: i32,
     top: i32,
@@ -33,22 +42,6 @@ impl WindowGeometry {
         }
     }
 
-    pub fn from_settings(settings: &gio::Settings) -> WindowGeometry {
-        let top = settings.get_int("persist-window-geometry-top");
-        let left = settings.get_int("persist-window-geometry-left");
-        let width = settings.get_int("persist-window-geometry-width");
-        let height = settings.get_int("persist-window-geometry-height");
-        let is_maximized = settings.get_boolean("persist-window-geometry-maximized");
-
-        WindowGeometry {
-            left,
-            top,
-            width,
-            height,
-            is_maximized,
-        }
-    }
-
     pub fn apply(&self, window: &gtk::ApplicationWindow) {
         if self.width > 0 && self.height > 0 {
             window.resize(self.width, self.height);
@@ -60,13 +53,75 @@ impl WindowGeometry {
             window.move_(self.left, self.top);
         }
     }
+}
+
+
+/// A page of the main window, as it is restored on the next start
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum PageState {
+    Search {
+        query: String,
+        #[serde(default)]
+        selected_thread: Option<String>,
+        /// width of the thread list
+        #[serde(default)]
+        thread_list_position: Option<i32>
+    },
+    Thread {
+        thread_id: String
+    }
+}
+
+/// What the main window looked like when it was closed. Composers are not
+/// part of it, they leave a draft behind instead.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// index in `pages` of the page that had the focus
+    #[serde(default)]
+    pub current_page: Option<usize>,
+
+    /// width of the tag list
+    #[serde(default)]
+    pub sidebar_position: Option<i32>,
+
+    #[serde(default)]
+    pub window: Option<WindowGeometry>,
+
+    #[serde(default)]
+    pub pages: Vec<PageState>,
+}
+
+impl Session {
+    /// None when there is no home directory to keep the session in
+    pub fn path() -> Option<PathBuf> {
+        let mut path = dirs::data_dir()?;
+        path.push("enamel");
+        path.push("session.toml");
+        Some(path)
+    }
+
+    /// Load the last session. A missing or broken file gives an empty
+    /// session.
+    pub fn load(path: &Path) -> Self {
+        let mut contents = String::new();
+        if File::open(path).and_then(|mut file| file.read_to_string(&mut contents)).is_err() {
+            return Self::default();
+        }
+
+        toml::from_str(&contents).unwrap_or_else(|err| {
+            warn!("Ignoring broken session {:?}: {:?}", path, err);
+            Self::default()
+        })
+    }
+
+    pub fn store(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
 
-    pub fn write(&self, settings: &gio::Settings) {
-        settings.set_int("persist-window-geometry-left", self.left);
-        settings.set_int("persist-window-geometry-top", self.top);
-        settings.set_int("persist-window-geometry-width", self.width);
-        settings.set_int("persist-window-geometry-height", self.height);
-        settings.set_boolean("persist-window-geometry-maximized", self.is_maximized);
+        let contents = toml::to_string(self).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        File::create(path)?.write_all(contents.as_bytes())
     }
 }
 
@@ -113,6 +168,36 @@ fn test_time_period_to_duration() {
     assert_eq!(time, time_period_to_duration(time, "seconds").num_seconds());
 }
 
+#[test]
+fn test_session_roundtrip() {
+    let session = Session {
+        current_page: Some(1),
+        sidebar_position: Some(180),
+        window: Some(WindowGeometry {
+            left: 10,
+            top: 20,
+            width: 800,
+            height: 600,
+            is_maximized: false
+        }),
+        pages: vec![
+            PageState::Search {
+                query: "tag:inbox".to_string(),
+                selected_thread: Some("0000000000000001".to_string()),
+                thread_list_position: None
+            },
+            PageState::Thread {
+                thread_id: "0000000000000002".to_string()
+            }
+        ]
+    };
+
+    let contents = toml::to_string(&session).unwrap();
+    assert_eq!(toml::from_str::<Session>(&contents).unwrap(), session);
+
+    assert_eq!(toml::from_str::<Session>("").unwrap(), Session::default());
+}
+
 // #[test]
 // fn test_apply_window_geometry() {
 //     gtk::init().expect("Error initializing gtk.");
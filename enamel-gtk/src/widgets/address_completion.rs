@@ -0,0 +1,123 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use gtk;
+use gtk::prelude::*;
+
+use enamel_core::addressbook::AddressBook;
+
+const MAX_COMPLETIONS: usize = 20;
+
+const COLUMN_DISPLAY: u32 = 0;
+const COLUMN_INSERT: u32 = 1;
+
+
+#[derive(Clone, Copy, Debug)]
+pub enum CompletionMode {
+    /// A comma separated list of addresses, as in the To field
+    Recipients,
+
+    /// A notmuch query, completing 'from:' and 'to:' terms
+    Query
+}
+
+impl CompletionMode {
+
+    /// Find the part of the text that is being completed. Returns its start
+    /// offset and the text to look up.
+    fn token<'a>(&self, text: &'a str) -> Option<(usize, &'a str)> {
+        match self {
+            CompletionMode::Recipients => {
+                let start = text.rfind(',').map(|i| i + 1).unwrap_or(0);
+                let start = start + (text[start..].len() - text[start..].trim_start().len());
+
+                Some((start, &text[start..]))
+            },
+            CompletionMode::Query => {
+                let start = text.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+                let term = &text[start..];
+
+                ["from:", "to:"].iter()
+                                .find(|prefix| term.starts_with(*prefix))
+                                .map(|prefix| (start + prefix.len(), &term[prefix.len()..]))
+            }
+        }
+    }
+
+    /// Text to put in the entry for a completed address
+    fn completion(&self, text: &str, start: usize, address: &str) -> String {
+        match self {
+            CompletionMode::Recipients => {
+                let head = text[..start].trim_end();
+                if head.is_empty() {
+                    format!("{}, ", address)
+                } else {
+                    format!("{} {}, ", head, address)
+                }
+            },
+            CompletionMode::Query => format!("{}{} ", &text[..start], address)
+        }
+    }
+}
+
+
+/// Complete addresses in an entry from the address book
+pub fn attach_address_completion(entry: &gtk::Entry,
+                                 addressbook: Rc<RefCell<AddressBook>>,
+                                 mode: CompletionMode) {
+
+    let store = gtk::ListStore::new(&[String::static_type(), String::static_type()]);
+
+    let completion = gtk::EntryCompletion::new();
+    completion.set_model(Some(&store));
+    completion.set_text_column(COLUMN_DISPLAY as i32);
+    completion.set_popup_completion(true);
+    completion.set_inline_completion(false);
+
+    // the store only holds matches already
+    completion.set_match_func(|_, _, _| true);
+
+    // refill the store before the completion looks at it, so this has to be
+    // connected before the completion is set on the entry
+    let refill_store = store.clone();
+    entry.connect_changed(move |entry| {
+        refill_store.clear();
+
+        let text = entry.get_text().map(|s| s.to_string()).unwrap_or_default();
+        let query = match mode.token(&text) {
+            Some((_, query)) if query.len() >= 2 => query,
+            _ => return
+        };
+
+        for contact in addressbook.borrow().search(query, MAX_COMPLETIONS) {
+            let insert = match mode {
+                CompletionMode::Recipients => contact.mailbox(),
+                CompletionMode::Query => contact.address.clone()
+            };
+
+            refill_store.insert_with_values(None,
+                &[COLUMN_DISPLAY, COLUMN_INSERT],
+                &[&contact.mailbox(), &insert]);
+        }
+    });
+
+    let weak_entry = entry.downgrade();
+    completion.connect_match_selected(move |_, model, iter| {
+        let entry = match weak_entry.upgrade() {
+            Some(entry) => entry,
+            None => return Inhibit(false)
+        };
+
+        let address: String = model.get_value(iter, COLUMN_INSERT as i32).get().unwrap();
+        let text = entry.get_text().map(|s| s.to_string()).unwrap_or_default();
+
+        if let Some((start, _)) = mode.token(&text) {
+            entry.set_text(&mode.completion(&text, start, &address));
+            entry.set_position(-1);
+        }
+
+        Inhibit(true)
+    });
+
+    entry.set_completion(Some(&completion));
+}
@@ -1,5 +1,8 @@
 mod about_dialog;
+pub mod address_completion;
 pub mod thread_list_cell_renderer;
-mod util;
+mod shortcuts_window;
+pub mod util;
 
 pub use self::about_dialog::about_dialog;
+pub use self::shortcuts_window::set_shortcuts_window;
@@ -0,0 +1,102 @@
+use gio::ActionGroupExt;
+use gtk;
+use gtk::prelude::*;
+
+use enamel_core::settings::{Keybindings, KeySequence, Context, Lookup, ACTIONS};
+
+use crate::keybindings;
+
+const CONTEXTS: &[Context] = &[Context::Global, Context::ThreadList, Context::ThreadView, Context::Compose];
+
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Whether the client implements an action. Composer actions are registered
+/// on each compose window, so there is nothing to check in the main window.
+fn is_registered(window: &gtk::ApplicationWindow, context: Context, name: &str) -> bool {
+    if context == Context::Compose {
+        return true;
+    }
+    window.get_action_group(keybindings::action_prefix(context))
+          .map(|group| group.has_action(name))
+          .unwrap_or(false)
+}
+
+/// The keys the main window's key handler resolves to an action. Keys that
+/// resolve to something else in the same context never reach it.
+fn live_keys<'a>(bindings: &'a Keybindings, context: Context, name: &str) -> Vec<&'a KeySequence> {
+    bindings.keys(context, name)
+            .iter()
+            .filter(|sequence| match bindings.lookup(context, &sequence.0) {
+                Lookup::Action(action) => action == name,
+                _ => false
+            })
+            .collect()
+}
+
+/// The ui definition of the shortcuts window, with a group per context
+fn shortcuts_ui(window: &gtk::ApplicationWindow, bindings: &Keybindings) -> String {
+    let mut groups = String::new();
+
+    for context in CONTEXTS {
+        let mut shortcuts = String::new();
+
+        for action in ACTIONS.iter().filter(|action| action.context == *context) {
+            let keys = live_keys(bindings, action.context, action.name);
+            if keys.is_empty() || !is_registered(window, action.context, action.name) {
+                continue;
+            }
+
+            let accelerator: Vec<String> = keys.iter().map(|sequence| keybindings::shortcut_accelerator(sequence)).collect();
+            shortcuts.push_str(&format!(r#"
+          <child>
+            <object class="GtkShortcutsShortcut">
+              <property name="visible">True</property>
+              <property name="title">{}</property>
+              <property name="accelerator">{}</property>
+            </object>
+          </child>"#, escape(action.description), escape(&accelerator.join(" "))));
+        }
+
+        if shortcuts.is_empty() {
+            continue;
+        }
+
+        groups.push_str(&format!(r#"
+      <child>
+        <object class="GtkShortcutsGroup">
+          <property name="visible">True</property>
+          <property name="title">{}</property>{}
+        </object>
+      </child>"#, escape(context.title()), shortcuts));
+    }
+
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<interface>
+  <object class="GtkShortcutsWindow" id="shortcuts_window">
+    <property name="modal">True</property>
+    <child>
+      <object class="GtkShortcutsSection">
+        <property name="visible">True</property>
+        <property name="section-name">shortcuts</property>{}
+      </object>
+    </child>
+  </object>
+</interface>"#, groups)
+}
+
+/// Build a `gtk::ShortcutsWindow` for the configured key bindings of all
+/// actions the `window` has registered, and make it the help overlay that
+/// `win.show-help-overlay` opens.
+pub fn set_shortcuts_window(window: &gtk::ApplicationWindow, bindings: &Keybindings) {
+    let builder = gtk::Builder::new_from_string(&shortcuts_ui(window, bindings));
+    let shortcuts_window = builder.get_object::<gtk::ShortcutsWindow>("shortcuts_window")
+                                  .expect("Couldn't find shortcuts_window in ui definition.");
+
+    window.set_help_overlay(Some(&shortcuts_window));
+}
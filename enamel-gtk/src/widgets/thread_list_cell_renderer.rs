@@ -31,6 +31,7 @@ use glib::{glib_wrapper, glib_object_wrapper, glib_object_subclass, glib_object_
 use notmuch;
 
 use enamel_core::database::ThreadExtra;
+use enamel_core::settings::{TagsConfig, TagRules};
 
 use super::util::*;
 
@@ -129,10 +130,8 @@ pub struct CellRendererThreadSettings {
     background_color_marked : Option<String>,
     background_color_marked_selected : Option<String>,
 
-    tags_upper_color : Option<String>,
-    tags_lower_color : Option<String>,
-    tags_alpha : f32,
-    hidden_tags : Vec<String>
+    /// colours, aliases and hidden tags, from the config
+    tags : TagRules
 }
 
 impl Default for CellRendererThreadSettings{
@@ -158,12 +157,7 @@ impl Default for CellRendererThreadSettings{
             background_color_marked : Some("#fff584".to_string()),
             background_color_marked_selected : Some("#bcb559".to_string()),
 
-            tags_upper_color : Some("#e5e5e5".to_string()),
-            tags_lower_color : Some("#333333".to_string()),
-            tags_alpha : 0.5,
-            hidden_tags : ["attachment".to_string(),
-                           "flagged".to_string(),
-                           "unread".to_string()].to_vec(),
+            tags : TagRules::default(),
         }
     }
 }
@@ -181,7 +175,7 @@ mod imp {
     }
 
     // GObject property definitions for our two values
-    static PROPERTIES: [subclass::Property; 1] = [
+    static PROPERTIES: [subclass::Property; 2] = [
         subclass::Property(
             "thread",
             |thread| {
@@ -194,6 +188,18 @@ mod imp {
                 )
             }
         ),
+        subclass::Property(
+            "marked",
+            |marked| {
+            glib::ParamSpec::boolean(
+                    marked,
+                    "Marked",
+                    "Whether the thread is marked for a bulk action",
+                    false,
+                    glib::ParamFlags::READWRITE,
+                )
+            }
+        ),
     ];
 
     // Basic declaration of our type for the GObject type system
@@ -235,6 +241,9 @@ mod imp {
                     let thread = value.get::<&Thread>().expect("Value did not actually contain an AnyValue");
                     *(self.thread.borrow_mut()) = Some(thread.clone());
                 },
+                subclass::Property("marked", ..) => {
+                    self.cache.borrow_mut().marked = value.get::<bool>().unwrap_or(false);
+                },
                 _ => unimplemented!(),
             }
         }
@@ -246,6 +255,9 @@ mod imp {
                 subclass::Property("thread", ..) => {
                     Ok("1".to_value())
                 },
+                subclass::Property("marked", ..) => {
+                    Ok(self.cache.borrow().marked.to_value())
+                },
                 _ => unimplemented!(),
             }
         }
@@ -322,6 +334,10 @@ mod imp {
 
     impl CellRendererThread {
 
+        pub fn set_tags_config(&self, tags: TagsConfig) {
+            self.settings.borrow_mut().tags = TagRules::new(&tags);
+        }
+
         fn calculate_height(&self, widget: &gtk::Widget)
         {
             let settings = self.settings.borrow();
@@ -751,19 +767,20 @@ mod imp {
             cr.set_source_rgb(color.red, color.green, color.blue);
 
         /* subtract hidden tags */
-        // vector<ustring> tags;
-        // set_difference (thread->tags.begin(),
-        //                 thread->tags.end(),
-        //                 hidden_tags.begin (),
-        //                 hidden_tags.end (),
-        //                 back_inserter(tags));
 
         let tag_string: String;
 
         let mut bg: gdk::RGBA = gdk::RGBA::from_str("#ffffff").unwrap();
 
-        if flags.contains(gtk::CellRendererState::SELECTED){
-            bg = gdk::RGBA::from_str(settings.background_color_selected.as_ref().unwrap().as_str()).unwrap();
+        // the tag colours are worked out against the background of the row
+        let background = match (flags.contains(gtk::CellRendererState::SELECTED), cache.marked) {
+            (true, false) => settings.background_color_selected.as_ref(),
+            (true, true) => settings.background_color_marked_selected.as_ref(),
+            (false, true) => settings.background_color_marked.as_ref(),
+            (false, false) => None
+        };
+        if let Some(background) = background {
+            bg = gdk::RGBA::from_str(background.as_str()).unwrap();
             cr.set_source_rgb (bg.red, bg.green, bg.blue);
         }
 
@@ -772,8 +789,10 @@ mod imp {
     //     if (!thread_index->plugins->format_tags (tags, bg.to_string (), (flags & Gtk::CELL_RENDERER_SELECTED) != 0, tag_string)) {
     // # endif
 
-            let tags: Vec<String> = thread.tags().collect();
-            tag_string = concat_tags_color(&tags, true, settings.tags_length, &bg);
+            let tags: Vec<String> = thread.tags()
+                                          .filter(|tag| !settings.tags.hidden_in_list(tag))
+                                          .collect();
+            tag_string = concat_tags_color(&tags, true, settings.tags_length, &settings.tags, &bg);
     // # ifndef DISABLE_PLUGINS
     //     }
     // # endif
@@ -816,5 +835,11 @@ impl CellRendererThread {
             .downcast()
             .expect("Created renderer is of wrong type")
     }
+
+    /// Use other tag colours, aliases and hidden tags from now on. The view has to
+    /// redraw to show them.
+    pub fn set_tags_config(&self, tags: TagsConfig) {
+        imp::CellRendererThread::from_instance(self).set_tags_config(tags);
+    }
 }
 
@@ -4,6 +4,8 @@ use glib;
 use gdk;
 use md5;
 
+use enamel_core::settings::{TagDisplay, TagRules};
+
 pub trait ToHex{
     fn to_hex(&self) -> String;
 }
@@ -22,12 +24,19 @@ impl ToHex for gdk::RGBA
     }
 }
 
-pub fn get_tag_color_rgba(tag: &str, canvascolor: &gdk::RGBA) -> (gdk::RGBA, gdk::RGBA)
+/// A configured colour, or `fallback` when it does not parse
+fn parse_color(color: &str, fallback: &str) -> gdk::RGBA {
+    gdk::RGBA::from_str(color).or_else(|_| gdk::RGBA::from_str(fallback)).unwrap()
+}
+
+/// The foreground and background colour of a tag. Colours set in
+/// `[tags.display]` win over the ones derived from the tag name.
+pub fn get_tag_color_rgba(display: &TagDisplay, rules: &TagRules, canvascolor: &gdk::RGBA) -> (gdk::RGBA, gdk::RGBA)
 {
-    //TODO: get from settings
-    let tags_upper_color = gdk::RGBA::from_str(&"#e5e5e5".to_string()).unwrap();
-    let tags_lower_color = gdk::RGBA::from_str(&"#333333".to_string()).unwrap();
-    let tags_alpha = 0.5;
+    let config = rules.config();
+    let tags_upper_color = parse_color(&config.upper_color, "#e5e5e5");
+    let tags_lower_color = parse_color(&config.lower_color, "#333333");
+    let tags_alpha = config.alpha;
 
     // # ifndef DISABLE_PLUGINS
     //
@@ -43,7 +52,9 @@ pub fn get_tag_color_rgba(tag: &str, canvascolor: &gdk::RGBA) -> (gdk::RGBA, gdk
     // }
     // # endif
 
-    let tc = md5::compute(tag);
+    let configured = |color: &Option<String>| color.as_ref().and_then(|color| gdk::RGBA::from_str(color).ok());
+
+    let tc = md5::compute(&display.tag);
 
     /*
      * normalize the background tag color to be between upper and
@@ -51,11 +62,19 @@ pub fn get_tag_color_rgba(tag: &str, canvascolor: &gdk::RGBA) -> (gdk::RGBA, gdk
      * luminocity of background color.
      */
 
-    let bg = gdk::RGBA{
-        red: f64::from(tc[0]) * (tags_upper_color.red - tags_lower_color.red) + tags_lower_color.red,
-        green: f64::from(tc[1]) * (tags_upper_color.green - tags_lower_color.green) + tags_lower_color.green,
-        blue: f64::from(tc[2]) * (tags_upper_color.blue - tags_lower_color.blue) + tags_lower_color.blue,
-        alpha: 0.0
+    let bg = match configured(&display.background) {
+        Some(color) => gdk::RGBA{
+            red: color.red * 255.0,
+            green: color.green * 255.0,
+            blue: color.blue * 255.0,
+            alpha: 0.0
+        },
+        None => gdk::RGBA{
+            red: f64::from(tc[0]) * (tags_upper_color.red - tags_lower_color.red) + tags_lower_color.red,
+            green: f64::from(tc[1]) * (tags_upper_color.green - tags_lower_color.green) + tags_lower_color.green,
+            blue: f64::from(tc[2]) * (tags_upper_color.blue - tags_lower_color.blue) + tags_lower_color.blue,
+            alpha: 0.0
+        }
     };
 
     let bc = gdk::RGBA{
@@ -71,10 +90,18 @@ pub fn get_tag_color_rgba(tag: &str, canvascolor: &gdk::RGBA) -> (gdk::RGBA, gdk
     /* float avg = (bg[0] + bg[1] + bg[2]) / (3 * 255.0); */
 
 
-    let fc = if lum > 0.5 {
-        gdk::RGBA::from_str("#000000").unwrap()
-    }else{
-        gdk::RGBA::from_str("#f2f2f2").unwrap()
+    let fc = match configured(&display.foreground) {
+        Some(color) => color,
+        None if lum > 0.5 => gdk::RGBA::from_str("#000000").unwrap(),
+        None => gdk::RGBA::from_str("#f2f2f2").unwrap()
+    };
+
+    // parsed colours are between 0 and 1, `to_hex` expects 0 to 65535
+    let fc = gdk::RGBA{
+        red: fc.red * 65535.0,
+        green: fc.green * 65535.0,
+        blue: fc.blue * 65535.0,
+        alpha: fc.alpha
     };
 
     (fc, bc)
@@ -82,8 +109,8 @@ pub fn get_tag_color_rgba(tag: &str, canvascolor: &gdk::RGBA) -> (gdk::RGBA, gdk
 
 
 
-pub fn get_tag_color (tag: &str, canvascolor: &gdk::RGBA) -> (String, String){
-  let clrs = get_tag_color_rgba(&tag, &canvascolor);
+pub fn get_tag_color (display: &TagDisplay, rules: &TagRules, canvascolor: &gdk::RGBA) -> (String, String){
+  let clrs = get_tag_color_rgba(display, rules, &canvascolor);
 
   (clrs.0.to_hex(), clrs.1.to_hex())
 }
@@ -92,6 +119,7 @@ pub fn get_tag_color (tag: &str, canvascolor: &gdk::RGBA) -> (String, String){
 pub fn concat_tags_color(tags: &[String],
                          use_pango: bool,
                          maxlen: i32,
+                         rules: &TagRules,
                          canvascolor: &gdk::RGBA) ->String
 {
 
@@ -100,10 +128,11 @@ pub fn concat_tags_color(tags: &[String],
     let mut broken = false;
     let mut len = 0;
 
-    let tags_alpha = 0.5;
+    let tags_alpha = rules.config().alpha;
 
     for t in tags {
-        let mut tag = t.clone();
+        let display = rules.display(t);
+        let mut tag = display.text();
 
         if !first {
             if use_pango {
@@ -115,7 +144,7 @@ pub fn concat_tags_color(tags: &[String],
             first = false;
         }
 
-        let mut colors = get_tag_color(&tag, &canvascolor);
+        let mut colors = get_tag_color(&display, rules, &canvascolor);
 
         if maxlen > 0 {
             broken = true;
@@ -124,7 +153,8 @@ pub fn concat_tags_color(tags: &[String],
 
             if (len + tag.len() as i32 + 2) > maxlen {
                 let cur_tag_len = tag.len() as i32;
-                tag.truncate((len + cur_tag_len + 2 - maxlen) as usize);
+                // icons and aliases need not be ascii
+                tag = tag.chars().take((len + cur_tag_len + 2 - maxlen) as usize).collect();
                 tag.add_assign("..");
             }
 
@@ -157,3 +187,21 @@ pub fn concat_tags_color(tags: &[String],
     }
     tag_string
 }
+
+
+#[test]
+fn test_configured_foreground() {
+    let display = TagDisplay {
+        tag: "todo".to_string(),
+        label: "todo".to_string(),
+        foreground: Some("#ffffff".to_string()),
+        background: Some("#336699".to_string()),
+        icon: None,
+        hidden: false
+    };
+    let canvas = gdk::RGBA::from_str("#ffffff").unwrap();
+
+    let (foreground, background) = get_tag_color(&display, &TagRules::default(), &canvas);
+    assert_eq!(&foreground[..7], "#ffffff");
+    assert_eq!(&background[..7], "#336699");
+}
@@ -0,0 +1,458 @@
+use std::rc::Rc;
+
+use log::*;
+use termion::event::Key;
+
+use tui::backend::Backend;
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Style};
+use tui::widgets::{Paragraph, Text, Widget};
+use tui::Frame;
+
+use enamel_core::settings::{Settings, SettingsEvent, Context, KeyPress, Lookup, TagRules};
+use enamel_core::database::Manager;
+use enamel_core::command::{self, Command};
+
+use crate::components::sidebar::Sidebar;
+use crate::components::thread_list::ThreadList;
+use crate::components::thread_view::ThreadView;
+use crate::util::event::key_press;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pane {
+    Sidebar,
+    ThreadList,
+    ThreadView,
+}
+
+impl Pane {
+    fn next(self) -> Self {
+        match self {
+            Pane::Sidebar => Pane::ThreadList,
+            Pane::ThreadList => Pane::ThreadView,
+            Pane::ThreadView => Pane::Sidebar
+        }
+    }
+
+    fn previous(self) -> Self {
+        match self {
+            Pane::Sidebar => Pane::ThreadView,
+            Pane::ThreadList => Pane::Sidebar,
+            Pane::ThreadView => Pane::ThreadList
+        }
+    }
+}
+
+
+/// What the text typed in the bottom line is for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PromptKind {
+    /// a `:` command
+    Command,
+
+    /// text to find in the current message
+    Find,
+
+    /// a query narrowing down the thread list, applied while typing
+    Filter,
+}
+
+struct Prompt {
+    kind: PromptKind,
+    input: String,
+}
+
+impl Prompt {
+    fn new(kind: PromptKind) -> Self {
+        Prompt{
+            kind,
+            input: String::new()
+        }
+    }
+
+    fn prefix(&self) -> char {
+        match self.kind {
+            PromptKind::Command => ':',
+            PromptKind::Find | PromptKind::Filter => '/'
+        }
+    }
+}
+
+
+pub struct EnamelApp {
+    pub size: Rect,
+
+    settings: Rc<Settings>,
+    dbmanager: Rc<Manager>,
+
+    focus: Pane,
+    sidebar: Sidebar,
+    thread_list: ThreadList,
+    thread_view: ThreadView,
+
+    /// shown in the bottom line
+    status: String,
+
+    prompt: Option<Prompt>,
+
+    /// the start of a multi-key binding
+    pending_keys: Vec<KeyPress>,
+
+    /// typed before a binding, like the 5 in `5j`
+    count: Option<usize>,
+}
+
+impl EnamelApp {
+
+    pub fn new(settings: Rc<Settings>, dbmanager: Rc<Manager>) -> Self {
+        let mut app = EnamelApp{
+            size: Rect::default(),
+            settings,
+            dbmanager,
+            focus: Pane::Sidebar,
+            sidebar: Sidebar::new(),
+            thread_list: ThreadList::new(),
+            thread_view: ThreadView::new(),
+            status: String::new(),
+            prompt: None,
+            pending_keys: vec![],
+            count: None
+        };
+
+        app.set_tag_rules();
+        if let Err(err) = app.sidebar.refresh(&app.settings, &app.dbmanager) {
+            app.error(&format!("Could not read tags: {:?}", err));
+        }
+        app.open_search();
+
+        app
+    }
+
+    fn set_tag_rules(&mut self) {
+        let tag_rules = TagRules::new(&self.settings.enamel_config.tags);
+        self.thread_list.set_tag_rules(tag_rules.clone());
+        self.thread_view.set_tag_rules(tag_rules);
+    }
+
+    pub fn settings(&self) -> Rc<Settings> {
+        self.settings.clone()
+    }
+
+    /// Use settings that were changed in the config files
+    pub fn settings_event(&mut self, event: SettingsEvent) {
+        match event {
+            SettingsEvent::Changed(settings, changes) => {
+                debug!("tui: settings changed: {:?}", changes);
+                self.settings = Rc::new(settings);
+
+                if changes.keybindings {
+                    // a half typed sequence may mean something else now
+                    self.pending_keys.clear();
+                    self.count = None;
+                }
+
+                if changes.tags {
+                    self.set_tag_rules();
+                }
+
+                if changes.searches || changes.tags {
+                    if let Err(err) = self.sidebar.refresh(&self.settings, &self.dbmanager) {
+                        self.error(&format!("Could not read tags: {:?}", err));
+                        return;
+                    }
+                }
+
+                self.status = if changes.notmuch {
+                    "Reloaded settings, restart to use the changed notmuch config".to_string()
+                } else {
+                    "Reloaded settings".to_string()
+                };
+            },
+            SettingsEvent::Failed(err) => self.error(&format!("Could not reload settings: {}", err))
+        }
+    }
+
+    /// Handle a key press. Returns false when the application should quit.
+    pub fn handle_key(&mut self, key: Key) -> bool {
+        if self.prompt.is_some() {
+            return self.handle_prompt_key(key);
+        }
+
+        // digits before a binding are a count, a leading 0 is not
+        if self.pending_keys.is_empty() {
+            if let Key::Char(c @ '0'..='9') = key {
+                if c != '0' || self.count.is_some() {
+                    let digit = c.to_digit(10).unwrap_or(0) as usize;
+                    self.count = Some(self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                    return true;
+                }
+            }
+        }
+
+        let press = match key_press(key) {
+            Some(press) => press,
+            None => return true
+        };
+        self.pending_keys.push(press);
+
+        let context = match self.focus {
+            Pane::Sidebar => Context::Global,
+            Pane::ThreadList => Context::ThreadList,
+            Pane::ThreadView => Context::ThreadView
+        };
+
+        match self.settings.keybindings.lookup(context, &self.pending_keys) {
+            Lookup::Prefix => true,
+            Lookup::Action(action) => {
+                self.pending_keys.clear();
+                let count = self.count.take();
+                self.run_action(action, count)
+            },
+            Lookup::None => {
+                self.pending_keys.clear();
+                self.count = None;
+                true
+            }
+        }
+    }
+
+    /// Run a bound action, `count` times where that makes sense. Returns
+    /// false when the application should quit.
+    fn run_action(&mut self, action: &str, count: Option<usize>) -> bool {
+        let n = count.unwrap_or(1);
+
+        match action {
+            "quit" => return false,
+            "command" => self.prompt = Some(Prompt::new(PromptKind::Command)),
+            "focus-next" => self.focus = self.focus.next(),
+            "focus-previous" | "back" => self.focus = self.focus.previous(),
+            "down" | "next-thread" | "scroll-down" => self.move_down(n),
+            "up" | "previous-thread" | "scroll-up" => self.move_up(n),
+            "page-down" => self.move_down(10 * n),
+            "page-up" => self.move_up(10 * n),
+            "open" | "toggle-expand" => self.activate(),
+            "first-thread" => self.thread_list.select(n - 1),
+            "last-thread" => match count {
+                Some(n) => self.thread_list.select(n - 1),
+                None => self.thread_list.last()
+            },
+            "filter" => self.prompt = Some(Prompt::new(PromptKind::Filter)),
+            "next-message" => (0..n).for_each(|_| self.thread_view.next_message()),
+            "previous-message" => (0..n).for_each(|_| self.thread_view.previous_message()),
+            "toggle-quotes" => self.thread_view.toggle_folded(),
+            "find" => self.prompt = Some(Prompt::new(PromptKind::Find)),
+            "find-next" => (0..n).for_each(|_| self.thread_view.next_match()),
+            "find-previous" => (0..n).for_each(|_| self.thread_view.previous_match()),
+            _ => self.status = format!("{} is not available here", action)
+        }
+        true
+    }
+
+    /// Edit the text in the bottom line. Returns false when the application
+    /// should quit.
+    fn handle_prompt_key(&mut self, key: Key) -> bool {
+        let mut prompt = match self.prompt.take() {
+            Some(prompt) => prompt,
+            None => return true
+        };
+
+        match key {
+            Key::Char('\n') => return self.submit_prompt(&prompt),
+            Key::Esc => {
+                if prompt.kind == PromptKind::Filter {
+                    self.filter("");
+                }
+                return true;
+            },
+            Key::Backspace => {
+                if prompt.input.pop().is_none() {
+                    return true;
+                }
+            },
+            Key::Char(c) => prompt.input.push(c),
+            _ => ()
+        }
+
+        if prompt.kind == PromptKind::Filter {
+            self.filter(&prompt.input);
+        }
+
+        self.prompt = Some(prompt);
+        true
+    }
+
+    fn submit_prompt(&mut self, prompt: &Prompt) -> bool {
+        match prompt.kind {
+            PromptKind::Command => return self.run_command(&prompt.input),
+            PromptKind::Find => {
+                if self.thread_view.search(&prompt.input) {
+                    self.status.clear();
+                } else {
+                    self.status = format!("Not found: {}", prompt.input);
+                }
+            },
+            // already applied while typing
+            PromptKind::Filter => ()
+        }
+        true
+    }
+
+    /// Run a `:` command. Returns false when the application should quit.
+    fn run_command(&mut self, line: &str) -> bool {
+        match Command::parse(line) {
+            Ok(Command::Quit) => return false,
+            Ok(Command::Search(query)) => {
+                self.search(&query);
+                self.focus = Pane::ThreadList;
+            },
+            Ok(Command::Tag{ add, remove }) => self.tag_selected(&add, &remove),
+            Ok(Command::Open(query)) => {
+                self.open_query(&query);
+                self.focus = Pane::ThreadView;
+            },
+            Ok(Command::Mark(_)) => self.status = "mark is not available here".to_string(),
+            Ok(Command::Action(action)) => return self.run_action(action, None),
+            Err(command::Error::Empty) => (),
+            Err(err) => self.error(&err.to_string())
+        }
+        true
+    }
+
+    fn move_down(&mut self, count: usize) {
+        match self.focus {
+            Pane::Sidebar => self.sidebar.next(count),
+            Pane::ThreadList => self.thread_list.next(count),
+            Pane::ThreadView => self.thread_view.scroll_down(count as u16)
+        }
+    }
+
+    fn move_up(&mut self, count: usize) {
+        match self.focus {
+            Pane::Sidebar => self.sidebar.previous(count),
+            Pane::ThreadList => self.thread_list.previous(count),
+            Pane::ThreadView => self.thread_view.scroll_up(count as u16)
+        }
+    }
+
+    fn activate(&mut self) {
+        match self.focus {
+            Pane::Sidebar => {
+                self.open_search();
+                self.focus = Pane::ThreadList;
+            },
+            Pane::ThreadList => {
+                self.open_thread();
+                self.focus = Pane::ThreadView;
+            },
+            Pane::ThreadView => self.thread_view.toggle_expanded()
+        }
+    }
+
+    fn open_search(&mut self) {
+        let query = match self.sidebar.selected() {
+            Some(item) => item.query(),
+            None => return
+        };
+
+        self.search(&query);
+    }
+
+    pub fn search(&mut self, query: &str) {
+        debug!("tui: searching {:?}", query);
+        self.thread_view.clear();
+
+        match self.thread_list.search(&self.dbmanager, &self.settings.expand_query(query)) {
+            Ok(()) => self.status = format!("{}: {} threads", query, self.thread_list.len()),
+            Err(err) => self.error(&format!("Search failed: {:?}", err))
+        }
+    }
+
+    /// Narrow down the thread list while the filter is typed. Incomplete
+    /// queries are expected, so failures are not reported.
+    fn filter(&mut self, filter: &str) {
+        if let Err(err) = self.thread_list.filter(&self.dbmanager, &self.settings.expand_query(filter)) {
+            debug!("tui: filter {:?} failed: {:?}", filter, err);
+        }
+    }
+
+    fn tag_selected(&mut self, add: &[String], remove: &[String]) {
+        let thread_id = match self.thread_list.selected() {
+            Some(thread) => thread.id.clone(),
+            None => return
+        };
+
+        let result = self.dbmanager.tag(&format!("thread:{}", thread_id), add, remove)
+                         .and_then(|count| {
+                             self.thread_list.reload(&self.dbmanager, true)?;
+                             self.sidebar.refresh(&self.settings, &self.dbmanager)?;
+                             Ok(count)
+                         });
+
+        match result {
+            Ok(count) => self.status = format!("Changed tags of {} messages", count),
+            Err(err) => self.error(&format!("Could not change tags: {:?}", err))
+        }
+    }
+
+    fn open_query(&mut self, query: &str) {
+        let decrypt = self.settings.enamel_config.crypto.decrypt;
+
+        match self.thread_view.open(&self.dbmanager, &self.settings.expand_query(query), decrypt) {
+            Ok(true) => (),
+            Ok(false) => self.status = format!("No thread matches {}", query),
+            Err(err) => self.error(&format!("Could not show thread: {:?}", err))
+        }
+    }
+
+    fn open_thread(&mut self) {
+        let thread_id = match self.thread_list.selected() {
+            Some(thread) => thread.id.clone(),
+            None => return
+        };
+
+        let decrypt = self.settings.enamel_config.crypto.decrypt;
+        if let Err(err) = self.thread_view.show(&self.dbmanager, &thread_id, decrypt) {
+            self.error(&format!("Could not show thread: {:?}", err));
+        }
+    }
+
+    fn error(&mut self, text: &str) {
+        error!("tui: {}", text);
+        self.status = text.to_string();
+    }
+
+    pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(1)].as_ref())
+            .split(self.size);
+
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref())
+            .split(rows[0]);
+
+        let panes = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
+            .split(columns[1]);
+
+        self.sidebar.draw(f, columns[0], self.focus == Pane::Sidebar);
+        self.thread_list.draw(f, panes[0], self.focus == Pane::ThreadList);
+        self.thread_view.draw(f, panes[1], self.focus == Pane::ThreadView);
+
+        let status = match self.prompt {
+            Some(ref prompt) => format!("{}{}", prompt.prefix(), prompt.input),
+            None if self.count.is_some() || !self.pending_keys.is_empty() => {
+                let keys: Vec<String> = self.pending_keys.iter().map(|key| key.to_string()).collect();
+                format!("{}{}", self.count.map(|n| n.to_string()).unwrap_or_default(), keys.join(" "))
+            },
+            None => self.status.clone()
+        };
+        let status = [Text::raw(status)];
+        Paragraph::new(status.iter())
+            .style(Style::default().fg(Color::Gray))
+            .render(f, rows[1]);
+    }
+}
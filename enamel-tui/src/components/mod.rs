@@ -0,0 +1,4 @@
+pub mod sidebar;
+pub mod tags;
+pub mod thread_list;
+pub mod thread_view;
@@ -0,0 +1,109 @@
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Block, Borders, SelectableList, Widget};
+use tui::Frame;
+
+use notmuch;
+use notmuch::DatabaseMode;
+
+use enamel_core::settings::{Settings, TagRules};
+use enamel_core::database::Manager;
+
+
+#[derive(Clone, Debug)]
+pub enum SidebarItem {
+    Search{ name: String, query: String },
+    Tag{ name: String, label: String },
+}
+
+impl SidebarItem {
+
+    pub fn query(&self) -> String {
+        match self {
+            SidebarItem::Search{ query, .. } => query.clone(),
+            SidebarItem::Tag{ name, .. } => format!("tag:{}", name)
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            SidebarItem::Search{ name, .. } => name.clone(),
+            SidebarItem::Tag{ label, .. } => format!("  {}", label)
+        }
+    }
+}
+
+
+/// Saved searches, followed by all tags in the database
+pub struct Sidebar {
+    items: Vec<SidebarItem>,
+    selected: usize,
+}
+
+impl Sidebar {
+
+    pub fn new() -> Self {
+        Sidebar{
+            items: vec![],
+            selected: 0
+        }
+    }
+
+    pub fn refresh(&mut self, settings: &Settings, dbmanager: &Manager) -> Result<(), notmuch::Error> {
+        let mut items: Vec<SidebarItem> = settings.saved_searches().into_iter()
+            .map(|search| SidebarItem::Search{
+                name: search.name,
+                query: search.query
+            })
+            .collect();
+
+        let db = dbmanager.get(DatabaseMode::ReadOnly)?;
+        let tag_rules = TagRules::new(&settings.enamel_config.tags);
+        items.extend(db.all_tags()?
+                       .map(|tag| tag_rules.display(&tag))
+                       .filter(|display| !display.hidden)
+                       .map(|display| SidebarItem::Tag{ label: display.text(), name: display.tag }));
+
+        self.items = items;
+        self.selected = self.selected.min(self.items.len().saturating_sub(1));
+        Ok(())
+    }
+
+    pub fn selected(&self) -> Option<&SidebarItem> {
+        self.items.get(self.selected)
+    }
+
+    pub fn next(&mut self, count: usize) {
+        if !self.items.is_empty() {
+            self.selected = (self.selected + count).min(self.items.len() - 1);
+        }
+    }
+
+    pub fn previous(&mut self, count: usize) {
+        self.selected = self.selected.saturating_sub(count);
+    }
+
+    pub fn draw<B: Backend>(&self, f: &mut Frame<B>, area: Rect, focused: bool) {
+        let labels: Vec<String> = self.items.iter().map(|item| item.label()).collect();
+
+        SelectableList::default()
+            .block(Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(border_style(focused))
+                        .title("Searches"))
+            .items(&labels)
+            .select(Some(self.selected))
+            .highlight_style(Style::default().modifier(Modifier::Bold).fg(Color::Yellow))
+            .render(f, area);
+    }
+}
+
+
+pub fn border_style(focused: bool) -> Style {
+    if focused {
+        Style::default().fg(Color::Cyan)
+    } else {
+        Style::default()
+    }
+}
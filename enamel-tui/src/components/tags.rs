@@ -0,0 +1,62 @@
+use md5;
+
+use tui::style::{Color, Style};
+
+use enamel_core::settings::{TagDisplay, TagRules};
+
+
+/// An `#rrggbb` or `#rgb` colour
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.trim().trim_start_matches('#');
+    let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+
+    match hex.len() {
+        6 => Some([channel(&hex[0..2])?, channel(&hex[2..4])?, channel(&hex[4..6])?]),
+        3 => Some([channel(&hex[0..1])? * 0x11, channel(&hex[1..2])? * 0x11, channel(&hex[2..3])? * 0x11]),
+        _ => None
+    }
+}
+
+fn configured(color: &Option<String>) -> Option<[u8; 3]> {
+    color.as_ref().and_then(|color| parse_color(color))
+}
+
+
+/// The colours of a tag, derived from the md5 hash of its name the same way
+/// the GTK thread list does: the background is normalized to lie between
+/// the upper and lower colour, the foreground is light or dark depending on
+/// the luminance of the background. Colours set in `[tags.display]` win.
+pub fn tag_colors(display: &TagDisplay, rules: &TagRules) -> (Color, Color) {
+    let config = rules.config();
+    let upper_color = parse_color(&config.upper_color).unwrap_or([0xe5, 0xe5, 0xe5]);
+    let lower_color = parse_color(&config.lower_color).unwrap_or([0x33, 0x33, 0x33]);
+
+    let bg = configured(&display.background).unwrap_or_else(|| {
+        let tc = md5::compute(&display.tag);
+
+        let mut bg = [0u8; 3];
+        for i in 0..3 {
+            let upper = f64::from(upper_color[i]);
+            let lower = f64::from(lower_color[i]);
+            bg[i] = (f64::from(tc[i]) / 255.0 * (upper - lower) + lower) as u8;
+        }
+        bg
+    });
+
+    let lum = (f64::from(bg[0]) * 0.2126 +
+               f64::from(bg[1]) * 0.7152 +
+               f64::from(bg[2]) * 0.0722) / 255.0;
+
+    let fg = match configured(&display.foreground) {
+        Some(fg) => fg,
+        None if lum > 0.5 => [0x00, 0x00, 0x00],
+        None => [0xf2, 0xf2, 0xf2]
+    };
+
+    (Color::Rgb(fg[0], fg[1], fg[2]), Color::Rgb(bg[0], bg[1], bg[2]))
+}
+
+pub fn tag_style(display: &TagDisplay, rules: &TagRules) -> Style {
+    let (fg, bg) = tag_colors(display, rules);
+    Style::default().fg(fg).bg(bg)
+}
@@ -0,0 +1,349 @@
+use chrono::{Local, TimeZone};
+
+use tui::backend::Backend;
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Block, Borders, Widget};
+use tui::Frame;
+
+use notmuch;
+use notmuch::DatabaseMode;
+
+use enamel_core::database::{Manager, ThreadExtra};
+use enamel_core::settings::TagRules;
+
+use super::sidebar::border_style;
+use super::tags::tag_style;
+
+type Threads = notmuch::Threads<'static, 'static>;
+
+const DATE_WIDTH: usize = 10;
+const AUTHORS_WIDTH: usize = 20;
+const TAGS_WIDTH: usize = 30;
+
+
+/// What the thread list shows of a thread
+#[derive(Clone, Debug)]
+pub struct ThreadSummary {
+    pub id: String,
+    pub subject: String,
+    pub authors: Vec<String>,
+    pub newest_date: i64,
+    pub total_messages: i32,
+    pub tags: Vec<String>,
+    pub unread: bool,
+    pub flagged: bool,
+    pub attachment: bool,
+}
+
+impl ThreadSummary {
+
+    fn new(thread: &notmuch::Thread) -> Self {
+        ThreadSummary{
+            id: thread.id().to_string(),
+            subject: thread.subject().to_string(),
+            authors: thread.authors(),
+            newest_date: thread.newest_date(),
+            total_messages: thread.total_messages(),
+            tags: thread.tags().collect(),
+            unread: thread.is_unread(),
+            flagged: thread.is_flagged(),
+            attachment: thread.has_attachment()
+        }
+    }
+
+    /// Time of day for today's threads, the date otherwise
+    fn date(&self) -> String {
+        let date = Local.timestamp(self.newest_date, 0);
+
+        if date.date() == Local::today() {
+            date.format("%H:%M").to_string()
+        } else {
+            date.format("%Y-%m-%d").to_string()
+        }
+    }
+}
+
+
+/// The threads matching a query. Threads are only read from the database
+/// once they scroll into view, so large searches show up immediately.
+pub struct ThreadList {
+    query: String,
+
+    /// narrows down the query while typing
+    filter: String,
+
+    /// the rest of the search results, until they are exhausted
+    pending: Option<Threads>,
+    threads: Vec<ThreadSummary>,
+    total: usize,
+
+    selected: usize,
+
+    /// first visible row
+    offset: usize,
+
+    tag_rules: TagRules,
+}
+
+impl ThreadList {
+
+    pub fn new() -> Self {
+        ThreadList{
+            query: String::new(),
+            filter: String::new(),
+            pending: None,
+            threads: vec![],
+            total: 0,
+            selected: 0,
+            offset: 0,
+            tag_rules: TagRules::default()
+        }
+    }
+
+    /// Show tags with other colours and aliases from now on
+    pub fn set_tag_rules(&mut self, tag_rules: TagRules) {
+        self.tag_rules = tag_rules;
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn search(&mut self, dbmanager: &Manager, query: &str) -> Result<(), notmuch::Error> {
+        self.query = query.to_string();
+        self.filter.clear();
+        self.reload(dbmanager, false)
+    }
+
+    /// Show only the threads that also match `filter`. The previous filter
+    /// stays when the new one is not a valid query.
+    pub fn filter(&mut self, dbmanager: &Manager, filter: &str) -> Result<(), notmuch::Error> {
+        let previous = std::mem::replace(&mut self.filter, filter.to_string());
+
+        let result = self.reload(dbmanager, false);
+        if result.is_err() {
+            self.filter = previous;
+        }
+        result
+    }
+
+    /// Run the query again, after tags changed
+    pub fn reload(&mut self, dbmanager: &Manager, keep_selection: bool) -> Result<(), notmuch::Error> {
+        let db = dbmanager.get(DatabaseMode::ReadOnly)?;
+
+        let notmuch_query = <notmuch::Database as notmuch::DatabaseExt>::create_query(db, &self.full_query())?;
+        let total = notmuch_query.count_threads()? as usize;
+        let threads = <notmuch::Query<'_> as notmuch::QueryExt>::search_threads(notmuch_query)?;
+
+        let selected = if keep_selection { self.selected } else { 0 };
+
+        self.pending = Some(threads);
+        self.threads.clear();
+        self.total = total;
+        self.selected = 0;
+        self.offset = 0;
+
+        // the selected thread may be opened before drawing
+        self.select(selected);
+        Ok(())
+    }
+
+    fn full_query(&self) -> String {
+        if self.filter.trim().is_empty() {
+            self.query.clone()
+        } else {
+            format!("({}) and ({})", self.query, self.filter)
+        }
+    }
+
+    /// Read threads from the search until `count` are loaded
+    fn fetch(&mut self, count: usize) {
+        while self.threads.len() < count {
+            let thread = match self.pending.as_mut().and_then(|threads| threads.next()) {
+                Some(thread) => thread,
+                None => {
+                    // the count may be off when the database changed since
+                    self.pending = None;
+                    self.total = self.threads.len();
+                    return;
+                }
+            };
+            self.threads.push(ThreadSummary::new(&thread));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.total
+    }
+
+    pub fn selected(&self) -> Option<&ThreadSummary> {
+        self.threads.get(self.selected)
+    }
+
+    pub fn next(&mut self, count: usize) {
+        if self.total > 0 {
+            let selected = (self.selected + count).min(self.total - 1);
+            self.fetch(selected + 1);
+            self.selected = selected.min(self.threads.len().saturating_sub(1));
+        }
+    }
+
+    pub fn previous(&mut self, count: usize) {
+        self.selected = self.selected.saturating_sub(count);
+    }
+
+    /// Select the thread at `index`, or the last one
+    pub fn select(&mut self, index: usize) {
+        self.fetch(index + 1);
+        self.selected = index.min(self.threads.len().saturating_sub(1));
+    }
+
+    /// Select the last thread, which loads all of them
+    pub fn last(&mut self) {
+        self.next(self.total);
+    }
+
+    /// Scroll so the selected thread is visible in `height` rows
+    fn scroll_to_selected(&mut self, height: usize) {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if height > 0 && self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height;
+        }
+    }
+
+    pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect, focused: bool) {
+        let title = if self.filter.is_empty() {
+            format!("{} ({})", self.query, self.total)
+        } else {
+            format!("{} /{} ({})", self.query, self.filter, self.total)
+        };
+        let mut block = Block::default()
+                            .borders(Borders::ALL)
+                            .border_style(border_style(focused))
+                            .title(&title);
+
+        let height = block.inner(area).height as usize;
+        self.scroll_to_selected(height);
+        self.fetch(self.offset + height);
+
+        let end = (self.offset + height).min(self.threads.len());
+        let start = self.offset.min(end);
+
+        ThreadRows{
+            block: &mut block,
+            threads: &self.threads[start..end],
+            selected: self.selected.checked_sub(start),
+            tag_rules: &self.tag_rules
+        }.render(f, area);
+    }
+}
+
+
+/// Draws the visible part of the thread list, one thread per row:
+/// date, markers, message count, authors, tags and subject.
+struct ThreadRows<'a, 'b> {
+    block: &'a mut Block<'b>,
+    threads: &'a [ThreadSummary],
+
+    /// relative to the first visible row
+    selected: Option<usize>,
+
+    tag_rules: &'a TagRules,
+}
+
+impl<'a, 'b> Widget for ThreadRows<'a, 'b> {
+
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        self.block.draw(area, buf);
+        let area = self.block.inner(area);
+
+        for (row, thread) in self.threads.iter().enumerate() {
+            let y = area.top() + row as u16;
+            if y >= area.bottom() {
+                break;
+            }
+
+            let mut style = Style::default();
+            if thread.unread {
+                style = style.modifier(Modifier::Bold);
+            }
+            if self.selected == Some(row) {
+                style = style.bg(Color::DarkGray);
+                fill(buf, Rect::new(area.left(), y, area.width, 1), style);
+            }
+
+            let mut line = Line{ buf, x: area.left(), y, right: area.right() };
+
+            line.put(&format!("{:>width$} ", thread.date(), width = DATE_WIDTH), style);
+            line.put(if thread.flagged { "*" } else { " " }, style.fg(Color::Yellow));
+            line.put(if thread.attachment { "@ " } else { "  " }, style);
+            line.put(&format!("({:>2}) ", thread.total_messages), style);
+            line.put(&pad(&thread.authors.join(", "), AUTHORS_WIDTH), style);
+            line.put(" ", style);
+
+            // tags are drawn as " tag " followed by a space
+            let mut tags_len = 0;
+            let tag_rules = self.tag_rules;
+            let tags = thread.tags.iter()
+                                  .filter(|tag| !tag_rules.hidden_in_list(tag))
+                                  .map(|tag| tag_rules.display(tag));
+            for display in tags {
+                let text = display.text();
+                let len = text.chars().count() + 3;
+                if tags_len + len > TAGS_WIDTH {
+                    line.put("..", style);
+                    tags_len += 2;
+                    break;
+                }
+                tags_len += len;
+
+                line.put(&format!(" {} ", text), tag_style(&display, tag_rules));
+                line.put(" ", style);
+            }
+
+            line.put(&pad("", TAGS_WIDTH.saturating_sub(tags_len)), style);
+            line.put(&thread.subject, style);
+        }
+    }
+}
+
+
+/// Writes consecutive cells of one row, clipping at the right edge
+struct Line<'a> {
+    buf: &'a mut Buffer,
+    x: u16,
+    y: u16,
+    right: u16,
+}
+
+impl<'a> Line<'a> {
+
+    fn put(&mut self, text: &str, style: Style) {
+        for c in text.chars() {
+            if self.x >= self.right {
+                return;
+            }
+            self.buf.get_mut(self.x, self.y)
+                    .set_symbol(&c.to_string())
+                    .set_style(style);
+            self.x += 1;
+        }
+    }
+}
+
+fn fill(buf: &mut Buffer, area: Rect, style: Style) {
+    for x in area.left()..area.right() {
+        buf.get_mut(x, area.top()).set_style(style);
+    }
+}
+
+/// Truncate or pad `text` to exactly `width` characters
+fn pad(text: &str, width: usize) -> String {
+    let mut padded: String = text.chars().take(width).collect();
+    let len = padded.chars().count();
+    padded.extend(std::iter::repeat(' ').take(width - len));
+    padded
+}
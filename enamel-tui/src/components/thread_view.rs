@@ -0,0 +1,453 @@
+use chrono::{Local, TimeZone};
+
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Block, Borders, Paragraph, Text, Widget};
+use tui::Frame;
+
+use notmuch;
+use notmuch::DatabaseMode;
+
+use enamel_core::database::Manager;
+use enamel_core::mime::{MimeMessage, ParseOptions};
+use enamel_core::mime::render::{self, Segment};
+use enamel_core::settings::TagRules;
+
+use super::sidebar::border_style;
+
+
+/// An attachment, as listed below the message text
+struct Attachment {
+    filename: String,
+    mime_type: String,
+}
+
+
+/// A message in the thread and how it is shown
+struct MessageView {
+    from: String,
+    to: String,
+    cc: String,
+    date: String,
+    subject: String,
+    tags: Vec<String>,
+
+    segments: Vec<Segment>,
+    attachments: Vec<Attachment>,
+
+    /// collapsed messages only show a summary line
+    expanded: bool,
+
+    /// quotes and signatures are shown in full
+    unfolded: bool,
+}
+
+impl MessageView {
+
+    fn new(message: &notmuch::Message, options: &ParseOptions) -> Self {
+        let header = |name: &str| message.header(name).ok().and_then(|h| h).map(|h| h.to_string()).unwrap_or_default();
+        let tags: Vec<String> = message.tags().collect();
+
+        let mime = MimeMessage::open(&message.filename(), options);
+
+        let body = mime.as_ref()
+                       .and_then(|mime| render::body_text(&mime.root))
+                       .unwrap_or_else(|| "(no text content)".to_string());
+
+        let attachments = mime.as_ref()
+                              .map(|mime| mime.attachments().iter().map(|chunk| Attachment{
+                                  filename: chunk.filename.clone().unwrap_or_else(|| "(unnamed)".to_string()),
+                                  mime_type: chunk.mime_type.clone()
+                              }).collect())
+                              .unwrap_or_default();
+
+        MessageView{
+            from: header("From"),
+            to: header("To"),
+            cc: header("Cc"),
+            date: Local.timestamp(message.date(), 0).format("%Y-%m-%d %H:%M").to_string(),
+            subject: header("Subject"),
+            expanded: tags.iter().any(|tag| tag == "unread"),
+            tags,
+            segments: render::segments(body.trim_end()),
+            attachments,
+            unfolded: false
+        }
+    }
+
+    /// The unwrapped lines of the message
+    fn lines(&self, current: bool, tag_rules: &TagRules) -> Vec<(String, Style)> {
+        let header_style = Style::default().fg(Color::Cyan);
+        let from_style = if current {
+            header_style.modifier(Modifier::Bold).bg(Color::DarkGray)
+        } else {
+            header_style.modifier(Modifier::Bold)
+        };
+
+        if !self.expanded {
+            let snippet = self.segments.iter()
+                              .filter(|segment| !segment.foldable())
+                              .flat_map(|segment| segment.lines())
+                              .find(|line| !line.trim().is_empty())
+                              .map(|line| line.trim().to_string())
+                              .unwrap_or_default();
+
+            return vec![(format!("+ {}  {}  {}", self.date, self.from, snippet), from_style)];
+        }
+
+        let mut lines = vec![(format!("- From: {}", self.from), from_style)];
+        lines.push((format!("  To: {}", self.to), header_style));
+        if !self.cc.is_empty() {
+            lines.push((format!("  Cc: {}", self.cc), header_style));
+        }
+        lines.push((format!("  Date: {}", self.date), header_style));
+        lines.push((format!("  Subject: {}", self.subject), header_style));
+        let tags: Vec<String> = self.tags.iter()
+                                    .map(|tag| tag_rules.display(tag))
+                                    .filter(|display| !display.hidden)
+                                    .map(|display| display.text())
+                                    .collect();
+        lines.push((format!("  Tags: {}", tags.join(", ")), header_style));
+        lines.push((String::new(), Style::default()));
+
+        let quote_style = Style::default().fg(Color::Green);
+        let folded_style = Style::default().fg(Color::DarkGray);
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => {
+                    lines.extend(text.iter().map(|line| (line.clone(), Style::default())));
+                },
+                Segment::Quote{ lines: quote, .. } if self.unfolded => {
+                    lines.extend(quote.iter().map(|line| (line.clone(), quote_style)));
+                },
+                Segment::Quote{ lines: quote, .. } => {
+                    lines.push((format!("[ {} quoted lines ]", quote.len()), folded_style));
+                },
+                Segment::Signature(signature) if self.unfolded => {
+                    lines.push(("-- ".to_string(), folded_style));
+                    lines.extend(signature.iter().map(|line| (line.clone(), folded_style)));
+                },
+                Segment::Signature(_) => {
+                    lines.push(("[ signature ]".to_string(), folded_style));
+                }
+            }
+        }
+
+        if !self.attachments.is_empty() {
+            lines.push((String::new(), Style::default()));
+            lines.push(("Attachments:".to_string(), header_style));
+            lines.extend(self.attachments.iter().map(|attachment| {
+                (format!("  {} ({})", attachment.filename, attachment.mime_type), Style::default())
+            }));
+        }
+
+        lines
+    }
+}
+
+
+/// A row on screen, after wrapping
+struct Row {
+    text: String,
+    style: Style,
+    message: usize,
+}
+
+
+/// The messages of a thread, rendered as text
+pub struct ThreadView {
+    subject: String,
+    messages: Vec<MessageView>,
+
+    /// the message that expand, fold and search act on
+    current: usize,
+
+    scroll: usize,
+
+    /// scroll to the current message on the next draw
+    follow_current: bool,
+
+    search: Option<String>,
+
+    /// rows of the current message that match the search, as of the last
+    /// draw
+    matches: Vec<usize>,
+
+    tag_rules: TagRules,
+}
+
+impl ThreadView {
+
+    pub fn new() -> Self {
+        ThreadView{
+            subject: String::new(),
+            messages: vec![],
+            current: 0,
+            scroll: 0,
+            follow_current: false,
+            search: None,
+            matches: vec![],
+            tag_rules: TagRules::default()
+        }
+    }
+
+    /// Show tags with other aliases from now on
+    pub fn set_tag_rules(&mut self, tag_rules: TagRules) {
+        self.tag_rules = tag_rules;
+    }
+
+    pub fn clear(&mut self) {
+        self.subject.clear();
+        self.messages.clear();
+        self.current = 0;
+        self.scroll = 0;
+        self.search = None;
+        self.matches.clear();
+    }
+
+    pub fn show(&mut self, dbmanager: &Manager, thread_id: &str, decrypt: bool) -> Result<(), notmuch::Error> {
+        self.clear();
+
+        let db = dbmanager.get(DatabaseMode::ReadOnly)?;
+        let query = <notmuch::Database as notmuch::DatabaseExt>::create_query(db, &format!("thread:{}", thread_id))?;
+        query.set_sort(notmuch::Sort::OldestFirst);
+
+        let options = ParseOptions{
+            decrypt,
+            ..Default::default()
+        };
+
+        for message in <notmuch::Query<'_> as notmuch::QueryExt>::search_messages(query)? {
+            self.messages.push(MessageView::new(&message, &options));
+        }
+
+        if let Some(first) = self.messages.first() {
+            self.subject = first.subject.clone();
+        }
+
+        // start at the first unread message, or the last one
+        let unread = self.messages.iter().position(|message| message.expanded);
+        self.current = unread.unwrap_or_else(|| self.messages.len().saturating_sub(1));
+        if let Some(message) = self.messages.get_mut(self.current) {
+            message.expanded = true;
+        }
+        self.follow_current = true;
+
+        Ok(())
+    }
+
+    /// Show the first thread matching a query. Returns whether there was one.
+    pub fn open(&mut self, dbmanager: &Manager, query: &str, decrypt: bool) -> Result<bool, notmuch::Error> {
+        let thread_id = {
+            let db = dbmanager.get(DatabaseMode::ReadOnly)?;
+            let query = <notmuch::Database as notmuch::DatabaseExt>::create_query(db, query)?;
+            let mut threads = <notmuch::Query<'_> as notmuch::QueryExt>::search_threads(query)?;
+
+            match threads.next() {
+                Some(thread) => thread.id().to_string(),
+                None => return Ok(false)
+            }
+        };
+
+        self.show(dbmanager, &thread_id, decrypt)?;
+        Ok(true)
+    }
+
+    pub fn scroll_down(&mut self, count: u16) {
+        self.scroll = self.scroll.saturating_add(count as usize);
+    }
+
+    pub fn scroll_up(&mut self, count: u16) {
+        self.scroll = self.scroll.saturating_sub(count as usize);
+    }
+
+    pub fn next_message(&mut self) {
+        if self.current + 1 < self.messages.len() {
+            self.current += 1;
+            self.follow_current = true;
+        }
+    }
+
+    pub fn previous_message(&mut self) {
+        if self.current > 0 {
+            self.current -= 1;
+            self.follow_current = true;
+        }
+    }
+
+    pub fn toggle_expanded(&mut self) {
+        if let Some(message) = self.messages.get_mut(self.current) {
+            message.expanded = !message.expanded;
+            self.follow_current = true;
+        }
+    }
+
+    /// Show or hide the quotes and signature of the current message
+    pub fn toggle_folded(&mut self) {
+        if let Some(message) = self.messages.get_mut(self.current) {
+            message.expanded = true;
+            message.unfolded = !message.unfolded;
+        }
+    }
+
+    /// Search the current message. Returns whether the text was found.
+    pub fn search(&mut self, text: &str) -> bool {
+        if text.is_empty() {
+            self.search = None;
+            return true;
+        }
+
+        let message = match self.messages.get_mut(self.current) {
+            Some(message) => message,
+            None => return false
+        };
+
+        let needle = lowercase(text);
+        let headers = [&message.from, &message.to, &message.cc, &message.subject];
+        let found = message.segments.iter()
+                           .flat_map(|segment| segment.lines().iter())
+                           .chain(headers.iter().cloned())
+                           .any(|line| find(&lowercase(line), &needle).is_some());
+
+        if found {
+            // matches may hide in folded quotes
+            message.expanded = true;
+            message.unfolded = true;
+            self.search = Some(text.to_string());
+            self.matches.clear();
+            self.follow_current = true;
+        }
+        found
+    }
+
+    pub fn next_match(&mut self) {
+        let next = self.matches.iter().find(|row| **row > self.scroll)
+                               .or_else(|| self.matches.first());
+        if let Some(row) = next {
+            self.scroll = *row;
+        }
+    }
+
+    pub fn previous_match(&mut self) {
+        let previous = self.matches.iter().rev().find(|row| **row < self.scroll)
+                                   .or_else(|| self.matches.last());
+        if let Some(row) = previous {
+            self.scroll = *row;
+        }
+    }
+
+    /// Lay out all messages, wrapped to `width` columns
+    fn rows(&self, width: usize) -> Vec<Row> {
+        let mut rows = vec![];
+
+        for (i, message) in self.messages.iter().enumerate() {
+            for (line, style) in message.lines(i == self.current, &self.tag_rules) {
+                for text in wrap(&line, width) {
+                    rows.push(Row{ text, style, message: i });
+                }
+            }
+            rows.push(Row{ text: String::new(), style: Style::default(), message: i });
+        }
+
+        rows
+    }
+
+    pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect, focused: bool) {
+        let subject = self.subject.clone();
+        let block = Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(border_style(focused))
+                        .title(&subject);
+        let inner = block.inner(area);
+
+        let rows = self.rows(inner.width as usize);
+        let needle = self.search.as_ref().map(|search| lowercase(search));
+
+        let current = self.current;
+        self.matches = match needle {
+            Some(ref needle) => rows.iter().enumerate()
+                                    .filter(|(_, row)| row.message == current)
+                                    .filter(|(_, row)| find(&lowercase(&row.text), needle).is_some())
+                                    .map(|(i, _)| i)
+                                    .collect(),
+            None => vec![]
+        };
+
+        if self.follow_current {
+            self.follow_current = false;
+            if let Some(first) = self.matches.first() {
+                self.scroll = *first;
+            } else if let Some(start) = rows.iter().position(|row| row.message == current) {
+                self.scroll = start;
+            }
+        }
+        self.scroll = self.scroll.min(rows.len().saturating_sub(1));
+
+        let highlight = Style::default().fg(Color::Black).bg(Color::Yellow);
+        let mut text = vec![];
+
+        for row in rows.iter().skip(self.scroll).take(inner.height as usize) {
+            let chars: Vec<char> = row.text.chars().collect();
+            let mut start = 0;
+
+            if let Some(ref needle) = needle {
+                if row.message == current {
+                    let lower = lowercase(&row.text);
+                    while let Some(pos) = find(&lower[start..], needle) {
+                        let pos = start + pos;
+                        text.push(Text::styled(chars[start..pos].iter().collect::<String>(), row.style));
+                        text.push(Text::styled(chars[pos..pos + needle.len()].iter().collect::<String>(), highlight));
+                        start = pos + needle.len();
+                    }
+                }
+            }
+
+            text.push(Text::styled(format!("{}\n", chars[start..].iter().collect::<String>()), row.style));
+        }
+
+        Paragraph::new(text.iter())
+            .block(block)
+            .render(f, area);
+    }
+}
+
+
+/// Lowercase per character, so positions match the original text
+fn lowercase(text: &str) -> Vec<char> {
+    text.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect()
+}
+
+fn find(haystack: &[char], needle: &[char]) -> Option<usize> {
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Break a line into rows of at most `width` characters, preferably at
+/// whitespace
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = line.chars().collect();
+    if width == 0 || chars.len() <= width {
+        return vec![line.to_string()];
+    }
+
+    let mut rows = vec![];
+    let mut start = 0;
+
+    while chars.len() - start > width {
+        let end = chars[start..start + width].iter()
+                      .rposition(|c| c.is_whitespace())
+                      .filter(|pos| *pos > 0)
+                      .map(|pos| start + pos + 1)
+                      .unwrap_or(start + width);
+
+        rows.push(chars[start..end].iter().collect());
+        start = end;
+    }
+    rows.push(chars[start..].iter().collect());
+
+    rows
+}
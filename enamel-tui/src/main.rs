@@ -1,46 +1,36 @@
 use structopt;
 
-use log;
+use log::*;
 use env_logger;
 
-use serde;
-use serde_derive;
-
-
 use std::io;
+use std::rc::Rc;
 use std::fs::DirBuilder;
 use std::path::PathBuf;
+use std::process;
 
 use dirs;
 
 use structopt::StructOpt;
 use structopt::clap::{App, Arg};
 
-use termion::event::Key;
 use termion::input::MouseTerminal;
 use termion::raw::IntoRawMode;
 use termion::screen::AlternateScreen;
 use tui::backend::TermionBackend;
-use tui::layout::{Constraint, Direction, Layout, Rect};
-use tui::style::{Color, Modifier, Style};
-use tui::widgets::{Block, Borders, Widget};
 use tui::Terminal;
 
-mod util;
+use gmime;
 
-use crate::util::event::{Event, Events};
+use enamel_core::settings::{self, Settings};
+use enamel_core::database::Manager;
 
-struct EnamelApp {
-    size: Rect,
-}
+mod util;
+mod app;
+mod components;
 
-impl Default for EnamelApp {
-    fn default() -> EnamelApp {
-        EnamelApp {
-            size: Rect::default(),
-        }
-    }
-}
+use crate::app::EnamelApp;
+use crate::util::event::{Event, Events};
 
 /// Init logger.
 fn init() {
@@ -89,9 +79,9 @@ fn main() -> Result<(), io::Error> {
         .recursive(true)
         .create(default_config.to_str().unwrap()).unwrap();
 
-    // let args = Args::from_args();
+    let default_config = default_config_path();
 
-    let _args = App::new("Enamel")
+    let args = App::new("Enamel")
         .version("0.0.1")
         .author("Dirk Van Haerenborgh <vhdirk@gmail.com>")
         .about("An email client with notmuch rust.")
@@ -106,6 +96,22 @@ fn main() -> Result<(), io::Error> {
         )
         .get_matches();
 
+    let conf_path = PathBuf::from(args.value_of("config").unwrap());
+    debug!("Using config file {:?}", conf_path);
+
+    // load the settings before taking over the terminal, so errors are
+    // readable
+    let settings = match Settings::new(&conf_path) {
+        Ok(settings) => Rc::new(settings),
+        Err(err) => {
+            eprintln!("Failed to load settings: {}", err);
+            process::exit(1);
+        }
+    };
+
+    gmime::init();
+
+    let dbmanager = Rc::new(Manager::new(&settings));
 
     // Terminal initialization
     let stdout = io::stdout().into_raw_mode()?;
@@ -115,12 +121,14 @@ fn main() -> Result<(), io::Error> {
     let mut terminal = Terminal::new(backend)?;
     terminal.hide_cursor()?;
 
-    // Create default app state
-    let mut app = EnamelApp::default();
+    let mut app = EnamelApp::new(settings, dbmanager);
 
     // Setup event handlers
     let events = Events::new();
 
+    let sender = events.sender();
+    settings::watch(&app.settings(), move |event| sender.send(Event::Settings(event)).is_ok());
+
     loop {
         let size = terminal.size()?;
         if app.size != size {
@@ -128,58 +136,15 @@ fn main() -> Result<(), io::Error> {
             app.size = size;
         }
 
-        terminal.draw(|mut f| {
-            // Wrapping block for a group
-            // Just draw the block and the group on the same area and build the group
-            // with at least a margin of 1
-            Block::default().borders(Borders::ALL).render(&mut f, size);
-            let chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .margin(4)
-                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-                .split(app.size);
-            {
-                let chunks = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-                    .split(chunks[0]);
-                Block::default()
-                    .title("With background")
-                    .title_style(Style::default().fg(Color::Yellow))
-                    .style(Style::default().bg(Color::Green))
-                    .render(&mut f, chunks[0]);
-                Block::default()
-                    .title("Styled title")
-                    .title_style(
-                        Style::default()
-                            .fg(Color::White)
-                            .bg(Color::Red)
-                            .modifier(Modifier::Bold),
-                    ).render(&mut f, chunks[1]);
-            }
-            {
-                let chunks = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-                    .split(chunks[1]);
-                Block::default()
-                    .title("With borders")
-                    .borders(Borders::ALL)
-                    .render(&mut f, chunks[0]);
-                Block::default()
-                    .title("With styled borders")
-                    .border_style(Style::default().fg(Color::Cyan))
-                    .borders(Borders::LEFT | Borders::RIGHT)
-                    .render(&mut f, chunks[1]);
-            }
-        })?;
+        terminal.draw(|mut f| app.draw(&mut f))?;
 
         match events.next() {
-            Ok(Event::Input(key)) => if key == Key::Char('q') {
+            Ok(Event::Input(key)) => if !app.handle_key(key) {
                 break;
             },
+            Ok(Event::Settings(event)) => app.settings_event(event),
             _ => {}
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -6,14 +6,19 @@ use std::time::Duration;
 use termion::event::Key;
 use termion::input::TermRead;
 
+use enamel_core::settings::{KeyPress, KeyCode, SettingsEvent};
+
 pub enum Event<I> {
     Input(I),
     Tick,
+    /// the config files changed
+    Settings(SettingsEvent),
 }
 
 /// An small event handler that wrap termion input and tick events. Each event
 /// type is handled in its own thread and returned to a common `Receiver`
 pub struct Events {
+    tx: mpsc::Sender<Event<Key>>,
     rx: mpsc::Receiver<Event<Key>>,
     input_handle: thread::JoinHandle<()>,
     tick_handle: thread::JoinHandle<()>,
@@ -21,14 +26,12 @@ pub struct Events {
 
 #[derive(Debug, Clone, Copy)]
 pub struct Config {
-    pub exit_key: Key,
     pub tick_rate: Duration,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
-            exit_key: Key::Char('q'),
             tick_rate: Duration::from_millis(250),
         }
     }
@@ -51,9 +54,6 @@ impl Events {
                             if let Err(_) = tx.send(Event::Input(key)) {
                                 return;
                             }
-                            if key == config.exit_key {
-                                return;
-                            }
                         }
                         Err(_) => {}
                     }
@@ -71,6 +71,7 @@ impl Events {
             })
         };
         Events {
+            tx,
             rx,
             input_handle,
             tick_handle,
@@ -80,4 +81,37 @@ impl Events {
     pub fn next(&self) -> Result<Event<Key>, mpsc::RecvError> {
         self.rx.recv()
     }
-}
\ No newline at end of file
+
+    /// For events from other threads, like the settings watcher
+    pub fn sender(&self) -> mpsc::Sender<Event<Key>> {
+        self.tx.clone()
+    }
+}
+
+
+/// The key press a termion key stands for, as used in key bindings
+pub fn key_press(key: Key) -> Option<KeyPress> {
+    let press = match key {
+        Key::Char('\n') => KeyPress::new(KeyCode::Enter),
+        Key::Char('\t') => KeyPress::new(KeyCode::Tab),
+        Key::Char(c) => KeyPress::new(KeyCode::Char(c)),
+        Key::Ctrl(c) => KeyPress{ ctrl: true, ..KeyPress::new(KeyCode::Char(c)) },
+        Key::Alt(c) => KeyPress{ alt: true, ..KeyPress::new(KeyCode::Char(c)) },
+        Key::BackTab => KeyPress{ shift: true, ..KeyPress::new(KeyCode::Tab) },
+        Key::Esc => KeyPress::new(KeyCode::Esc),
+        Key::Backspace => KeyPress::new(KeyCode::Backspace),
+        Key::Delete => KeyPress::new(KeyCode::Delete),
+        Key::Up => KeyPress::new(KeyCode::Up),
+        Key::Down => KeyPress::new(KeyCode::Down),
+        Key::Left => KeyPress::new(KeyCode::Left),
+        Key::Right => KeyPress::new(KeyCode::Right),
+        Key::Home => KeyPress::new(KeyCode::Home),
+        Key::End => KeyPress::new(KeyCode::End),
+        Key::PageUp => KeyPress::new(KeyCode::PageUp),
+        Key::PageDown => KeyPress::new(KeyCode::PageDown),
+        Key::F(n) => KeyPress::new(KeyCode::F(n)),
+        _ => return None
+    };
+
+    Some(press.normalized())
+}